@@ -9,3 +9,15 @@ macro_rules! assert_approx {
         }
     };
 }
+
+#[macro_export]
+macro_rules! assert_matrix_approx {
+    ($a:expr, $b:expr, $tol:expr) => {
+        if !$a.approx_eq(&$b, $tol) {
+            panic!(
+                "assertion failed: `left.approx_eq(right, tol)`\n  left: `{:?}`\n right: `{:?}`",
+                $a, $b
+            )
+        }
+    };
+}
@@ -0,0 +1,317 @@
+use geometry::Ray;
+use image::{Color, Image};
+use matrix::Vector3f;
+use rng::Rng;
+use sky;
+use std::f32::consts::PI;
+
+pub enum Background {
+    Uniform(Color),
+    Environment(Environment),
+    /// Arbitrary function of the missed ray, e.g. a procedural sky.
+    Procedural(Box<dyn Fn(&Ray) -> Color + Send + Sync>),
+    /// Analytic clear sky with the sun toward `sun_direction`, see
+    /// `sky::radiance`.
+    Sky {
+        sun_direction: Vector3f,
+        turbidity: f32,
+    },
+    /// Camera rays that miss produce transparent pixels, all other rays see
+    /// the given color.
+    Transparent(Color),
+}
+
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Uniform(color) | Background::Transparent(color) => *color,
+            Background::Environment(environment) => environment.lookup(&ray.direction),
+            Background::Procedural(function) => function(ray),
+            Background::Sky {
+                sun_direction,
+                turbidity,
+            } => sky::radiance(sun_direction, *turbidity, &ray.direction),
+        }
+    }
+
+    /// Color seen by rays after a diffuse scatter, which may use a
+    /// prefiltered environment since they blur it anyway.
+    pub fn indirect_color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Environment(environment) => environment.lookup_indirect(&ray.direction),
+            _ => self.color(ray),
+        }
+    }
+}
+
+/// Equirectangular environment map. A CDF over the texel luminance is built
+/// on construction so that bright regions can be importance sampled.
+pub struct Environment {
+    pub image: Image,
+    /// Mip level used for lookups after diffuse scatters, 0 is full resolution.
+    pub indirect_mip_level: usize,
+    /// Box filtered mip chain, starting at half resolution.
+    mips: Vec<Image>,
+    marginal_cdf: Vec<f32>,
+    conditional_cdfs: Vec<Vec<f32>>,
+}
+
+impl Environment {
+    /// Returns `None` for an image without texels.
+    pub fn new(image: Image) -> Option<Self> {
+        if image.width == 0 || image.height == 0 {
+            return None;
+        }
+        let (width, height) = (image.width as usize, image.height as usize);
+        let mut marginal_cdf = Vec::with_capacity(height);
+        let mut conditional_cdfs = Vec::with_capacity(height);
+        let mut total = 0.0;
+        for y in 0..height {
+            let solid_angle = texel_solid_angle(y, width, height);
+            let mut row_total = 0.0;
+            let cdf: Vec<f32> = image.data[y * width..(y + 1) * width]
+                .iter()
+                .map(|texel| {
+                    row_total += texel.luminance() * solid_angle;
+                    row_total
+                })
+                .collect();
+            total += row_total;
+            marginal_cdf.push(total);
+            conditional_cdfs.push(cdf);
+        }
+        let mut mips: Vec<Image> = Vec::new();
+        loop {
+            let previous = mips.last().unwrap_or(&image);
+            if previous.width <= 1 && previous.height <= 1 {
+                break;
+            }
+            mips.push(previous.downsample());
+        }
+        Some(Self {
+            image,
+            indirect_mip_level: 0,
+            mips,
+            marginal_cdf,
+            conditional_cdfs,
+        })
+    }
+
+    pub fn mip_level(&self, level: usize) -> &Image {
+        match level {
+            0 => &self.image,
+            _ => &self.mips[(level - 1).min(self.mips.len() - 1)],
+        }
+    }
+
+    pub fn lookup(&self, direction: &Vector3f) -> Color {
+        self.lookup_level(direction, 0)
+    }
+
+    pub fn lookup_indirect(&self, direction: &Vector3f) -> Color {
+        self.lookup_level(direction, self.indirect_mip_level)
+    }
+
+    pub fn lookup_level(&self, direction: &Vector3f, level: usize) -> Color {
+        let image = self.mip_level(level);
+        let (u, v) = direction_to_uv(direction);
+        let x = ((u * image.width as f32) as usize).min(image.width as usize - 1);
+        let y = ((v * image.height as f32) as usize).min(image.height as usize - 1);
+        image.data[y * image.width as usize + x]
+    }
+
+    /// Samples a direction proportionally to the luminance of the map,
+    /// returning the direction and its solid angle pdf.
+    pub fn sample(&self, rng: &mut Rng) -> Option<(Vector3f, f32)> {
+        let total = *self.marginal_cdf.last()?;
+        if total <= 0.0 {
+            return None;
+        }
+        let y = sample_cdf(&self.marginal_cdf, rng.uniform() * total);
+        let row = &self.conditional_cdfs[y];
+        let x = sample_cdf(row, rng.uniform() * row[row.len() - 1]);
+
+        // Uniform in solid angle within the texel.
+        let (height, width) = (self.image.height as f32, self.image.width as f32);
+        let cos_theta_top = (y as f32 / height * PI).cos();
+        let cos_theta_bottom = ((y + 1) as f32 / height * PI).cos();
+        let cos_theta = cos_theta_top + rng.uniform() * (cos_theta_bottom - cos_theta_top);
+        let u = (x as f32 + rng.uniform()) / width;
+        let v = cos_theta.clamp(-1.0, 1.0).acos() / PI;
+        Some((uv_to_direction(u, v), self.texel_pdf(x, y)))
+    }
+
+    /// Solid angle pdf of `sample` returning `direction`.
+    pub fn pdf(&self, direction: &Vector3f) -> f32 {
+        let (x, y) = self.texel(direction);
+        self.texel_pdf(x, y)
+    }
+
+    /// Probability of picking texel (x, y) divided by its solid angle.
+    fn texel_pdf(&self, x: usize, y: usize) -> f32 {
+        let total = self.marginal_cdf[self.marginal_cdf.len() - 1];
+        if total <= 0.0 {
+            return 0.0;
+        }
+        let row = &self.conditional_cdfs[y];
+        let weight = row[x] - if x > 0 { row[x - 1] } else { 0.0 };
+        let (width, height) = (self.image.width as usize, self.image.height as usize);
+        weight / total / texel_solid_angle(y, width, height)
+    }
+
+    fn texel(&self, direction: &Vector3f) -> (usize, usize) {
+        let (u, v) = direction_to_uv(direction);
+        let x = ((u * self.image.width as f32) as usize).min(self.image.width as usize - 1);
+        let y = ((v * self.image.height as f32) as usize).min(self.image.height as usize - 1);
+        (x, y)
+    }
+}
+
+/// Radiance toward all directions from one point, stored as six cube faces
+/// in the order +x, -x, +y, -y, +z, -z. See `cube_direction` for the
+/// layout of a face.
+pub struct ReflectionProbe {
+    pub faces: [Image; 6],
+}
+
+impl ReflectionProbe {
+    pub fn lookup(&self, direction: &Vector3f) -> Color {
+        let d = direction.to_array();
+        let axis = (0..3)
+            .max_by(|&a, &b| d[a].abs().total_cmp(&d[b].abs()))
+            .unwrap();
+        let face = 2 * axis + usize::from(d[axis] < 0.0);
+        let major = d[axis].abs();
+        if major == 0.0 {
+            return Color::rgb(0.0, 0.0, 0.0);
+        }
+        let image = &self.faces[face];
+        let texel = |coordinate: f32, size: u32| {
+            let t = (0.5 * (coordinate / major + 1.0) * size as f32) as usize;
+            t.min(size as usize - 1)
+        };
+        let x = texel(d[(axis + 1) % 3], image.width);
+        let y = texel(d[(axis + 2) % 3], image.height);
+        image.data[y * image.width as usize + x]
+    }
+}
+
+/// Unnormalized direction through the point (`u`, `v`) in [0, 1]^2 of cube
+/// `face`. Faces are ordered +x, -x, +y, -y, +z, -z; `u` runs along the
+/// next axis after the face axis and `v` along the one after that.
+pub fn cube_direction(face: usize, u: f32, v: f32) -> Vector3f {
+    let axis = face / 2;
+    let mut d = [0.0; 3];
+    d[axis] = if face.is_multiple_of(2) { 1.0 } else { -1.0 };
+    d[(axis + 1) % 3] = 2.0 * u - 1.0;
+    d[(axis + 2) % 3] = 2.0 * v - 1.0;
+    Vector3f::from(d)
+}
+
+fn texel_solid_angle(y: usize, width: usize, height: usize) -> f32 {
+    let theta_top = y as f32 / height as f32 * PI;
+    let theta_bottom = (y + 1) as f32 / height as f32 * PI;
+    2.0 * PI / width as f32 * (theta_top.cos() - theta_bottom.cos())
+}
+
+fn sample_cdf(cdf: &[f32], target: f32) -> usize {
+    cdf.partition_point(|&c| c <= target).min(cdf.len() - 1)
+}
+
+fn direction_to_uv(direction: &Vector3f) -> (f32, f32) {
+    let (theta, phi) = direction.to_spherical();
+    ((phi + PI) / (2.0 * PI), theta / PI)
+}
+
+fn uv_to_direction(u: f32, v: f32) -> Vector3f {
+    let phi = u * 2.0 * PI - PI;
+    let theta = v * PI;
+    Vector3f::from_spherical(theta, phi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_procedural_background() {
+        let background = Background::Procedural(Box::new(|ray: &Ray| {
+            let t = 0.5 * (ray.direction.y() + 1.0);
+            Color::rgb(1.0, 1.0, 1.0) * (1.0 - t) + Color::rgb(0.5, 0.7, 1.0) * t
+        }));
+        let up = Ray {
+            origin: Vector3f::zeros(),
+            direction: Vector3f::xyz(0.0, 1.0, 0.0),
+        };
+        assert_eq!(background.color(&up), Color::rgb(0.5, 0.7, 1.0));
+
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+        assert_send_sync(&background);
+    }
+
+    #[test]
+    fn test_reflection_probe_lookup() {
+        let faces = std::array::from_fn(|face| {
+            let mut image = Image::new(2, 2);
+            image.data[3] = Color::rgb(face as f32, 1.0, 0.0);
+            image
+        });
+        let probe = ReflectionProbe { faces };
+        for face in 0..6 {
+            let direction = cube_direction(face, 0.75, 0.75);
+            assert_eq!(probe.lookup(&direction), Color::rgb(face as f32, 1.0, 0.0));
+            let direction = cube_direction(face, 0.25, 0.25) * 3.0;
+            assert_eq!(probe.lookup(&direction), Color::rgb(0.0, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn test_mip_chain() {
+        let mut image = Image::new(8, 4);
+        for (i, texel) in image.data.iter_mut().enumerate() {
+            *texel = Color::rgb(i as f32, 0.0, 1.0);
+        }
+        let environment = Environment::new(image).unwrap();
+        let level1 = environment.mip_level(1);
+        assert_eq!((level1.width, level1.height), (4, 2));
+        // Average of texels 0, 1, 8 and 9 of the full resolution image.
+        assert_eq!(level1.data[0], Color::rgb(4.5, 0.0, 1.0));
+        let last = environment.mip_level(10);
+        assert_eq!((last.width, last.height), (1, 1));
+        assert_eq!(last.data[0], Color::rgb(15.5, 0.0, 1.0));
+        assert!(Environment::new(Image::new(0, 4)).is_none());
+    }
+
+    #[test]
+    fn test_uniform_environment_pdf() {
+        let mut image = Image::new(16, 8);
+        image
+            .data
+            .iter_mut()
+            .for_each(|c| *c = Color::rgb(1.0, 1.0, 1.0));
+        let environment = Environment::new(image).unwrap();
+        let mut rng = Rng::new(0);
+        for _ in 0..100 {
+            let (direction, pdf) = environment.sample(&mut rng).unwrap();
+            assert_approx!(direction.length(), 1.0, 1e-5);
+            assert_approx!(pdf, environment.pdf(&direction), 1e-4);
+            assert_approx!(pdf, 1.0 / (4.0 * PI), 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_samples_bright_texel() {
+        let mut image = Image::new(16, 8);
+        image.data[3 * 16 + 5] = Color::rgb(100.0, 100.0, 100.0);
+        let environment = Environment::new(image).unwrap();
+        let mut rng = Rng::new(0);
+        for _ in 0..100 {
+            let (direction, _) = environment.sample(&mut rng).unwrap();
+            assert_eq!(environment.texel(&direction), (5, 3));
+            assert_eq!(
+                environment.lookup(&direction),
+                Color::rgb(100.0, 100.0, 100.0)
+            );
+        }
+    }
+}
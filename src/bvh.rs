@@ -0,0 +1,703 @@
+use geometry::{
+    get_any_hit, get_bounds, get_closest_hit, get_intersection, ray_aabb, Camera, Object, Ray,
+    PLANE_EXTENT,
+};
+use image::{heat_color, Image};
+use matrix::Vector3f;
+use std::thread;
+
+/// Spatial acceleration structure used to find the closest hit of a ray.
+pub enum Accel {
+    Linear,
+    Bvh(Bvh),
+    Grid(UniformGrid),
+}
+
+impl Accel {
+    pub fn closest_hit<'a>(
+        &self,
+        objects: &'a [Object],
+        ray: &Ray,
+    ) -> Option<(Vector3f, &'a Object)> {
+        match self {
+            Accel::Linear => get_closest_hit(objects, ray),
+            Accel::Bvh(bvh) => bvh.closest_hit(objects, ray),
+            Accel::Grid(grid) => grid.closest_hit(objects, ray),
+        }
+    }
+
+    /// Whether the ray hits any object before `t_max`, in units of the ray
+    /// direction.
+    pub fn intersect_any(&self, objects: &[Object], ray: &Ray, t_max: f32) -> bool {
+        match self {
+            Accel::Linear => get_any_hit(objects, ray, t_max),
+            Accel::Bvh(bvh) => bvh.intersect_any(objects, ray, t_max),
+            Accel::Grid(grid) => grid.intersect_any(objects, ray, t_max),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BvhNodeKind {
+    Leaf { first: usize, count: usize },
+    Interior { left: usize, right: usize },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BvhNode {
+    pub min: Vector3f,
+    pub max: Vector3f,
+    pub kind: BvhNodeKind,
+}
+
+/// Bounding volume hierarchy over the objects of a scene. Stores indices
+/// into the object list it was built from.
+#[derive(Debug, PartialEq)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+const MAX_LEAF_SIZE: usize = 2;
+/// Line width of `Bvh::draw_wireframe` in pixels.
+const WIREFRAME_WIDTH_PX: f32 = 1.0;
+
+impl Bvh {
+    pub fn new(objects: &[Object]) -> Self {
+        Bvh::new_parallel(objects, 1)
+    }
+
+    /// Like `new`, but builds the subtrees below the top levels on up to
+    /// `threads` threads. The tree is identical to the one of `new`.
+    pub fn new_parallel(objects: &[Object], threads: usize) -> Self {
+        let bounds: Vec<(Vector3f, Vector3f)> =
+            objects.iter().map(|o| get_bounds(&o.surface)).collect();
+        let mut indices: Vec<usize> = (0..objects.len()).collect();
+        let nodes = if objects.is_empty() {
+            Vec::new()
+        } else {
+            let split_depth = threads.max(1).next_power_of_two().trailing_zeros();
+            build_parallel(&bounds, &mut indices, 0, split_depth)
+        };
+        Bvh { nodes, indices }
+    }
+
+    pub fn closest_hit<'a>(
+        &self,
+        objects: &'a [Object],
+        ray: &Ray,
+    ) -> Option<(Vector3f, &'a Object)> {
+        let mut closest: Option<(Vector3f, &Object)> = None;
+        let mut closest_t = f32::INFINITY;
+        let direction_length = ray.direction.length();
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if ray_aabb(ray, &node.min, &node.max, 0.0, closest_t).is_none() {
+                continue;
+            }
+            match node.kind {
+                BvhNodeKind::Leaf { first, count } => {
+                    for &i in &self.indices[first..first + count] {
+                        let object = &objects[i];
+                        if let Some(hit) = get_intersection(&object.surface, ray) {
+                            let t = ray.origin.distance(&hit) / direction_length;
+                            if t < closest_t {
+                                closest_t = t;
+                                closest = Some((hit, object));
+                            }
+                        }
+                    }
+                }
+                BvhNodeKind::Interior { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        closest
+    }
+
+    /// Whether the ray hits any object before `t_max`, returning at the
+    /// first hit found instead of searching for the closest.
+    pub fn intersect_any(&self, objects: &[Object], ray: &Ray, t_max: f32) -> bool {
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if ray_aabb(ray, &node.min, &node.max, 0.0, t_max).is_none() {
+                continue;
+            }
+            match node.kind {
+                BvhNodeKind::Leaf { first, count } => {
+                    let indices = &self.indices[first..first + count];
+                    if get_any_hit_of(objects, indices, ray, t_max) {
+                        return true;
+                    }
+                }
+                BvhNodeKind::Interior { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        false
+    }
+
+    /// Depth of every node, 0 for the root.
+    pub fn node_depths(&self) -> Vec<u32> {
+        let mut depths = vec![0; self.nodes.len()];
+        // Children are always built after their parent.
+        for (index, node) in self.nodes.iter().enumerate() {
+            if let BvhNodeKind::Interior { left, right } = node.kind {
+                depths[left] = depths[index] + 1;
+                depths[right] = depths[index] + 1;
+            }
+        }
+        depths
+    }
+
+    /// Debug view drawing the edges of all node boxes seen by `camera` over
+    /// `image`, colored from blue at the root to red at the deepest nodes.
+    /// Edges are not hidden by objects; draw over a black image to see the
+    /// boxes alone.
+    pub fn draw_wireframe(&self, camera: &Camera, image: &mut Image) {
+        let depths = self.node_depths();
+        let max_depth = depths.iter().cloned().max().unwrap_or(0).max(1);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let ray = camera.back_project(x as f32, y as f32);
+                let next = camera.back_project(x as f32 + 1.0, y as f32);
+                let pixel_angle = ray.direction.cos_angle(&next.direction).min(1.0).acos();
+                let mut closest: Option<(f32, u32)> = None;
+                for (node, &depth) in self.nodes.iter().zip(&depths) {
+                    let (t_enter, t_exit) =
+                        match ray_aabb(&ray, &node.min, &node.max, 0.0, f32::INFINITY) {
+                            Some(span) => span,
+                            None => continue,
+                        };
+                    for t in [t_enter, t_exit] {
+                        let width = WIREFRAME_WIDTH_PX * pixel_angle * (camera.near + t);
+                        if t > 0.0
+                            && closest.is_none_or(|(closest_t, _)| t < closest_t)
+                            && is_on_box_edge(&ray.at(t), &node.min, &node.max, width)
+                        {
+                            closest = Some((t, depth));
+                        }
+                    }
+                }
+                if let Some((_, depth)) = closest {
+                    image.data[(y * image.width + x) as usize] =
+                        heat_color(depth as f32 / max_depth as f32);
+                }
+            }
+        }
+    }
+}
+
+/// Leaf node over `indices`, which start at `first` in the index list of
+/// the tree, and unless it is small enough to stay a leaf, the split point
+/// of its children. Median split along the longest axis of the centroids,
+/// partitioning `indices` around it.
+fn split(
+    bounds: &[(Vector3f, Vector3f)],
+    indices: &mut [usize],
+    first: usize,
+) -> (BvhNode, Option<usize>) {
+    let (min, max) = indices
+        .iter()
+        .map(|&i| bounds[i])
+        .reduce(|(min_a, max_a), (min_b, max_b)| {
+            (min_a.component_min(&min_b), max_a.component_max(&max_b))
+        })
+        .unwrap();
+    let node = BvhNode {
+        min,
+        max,
+        kind: BvhNodeKind::Leaf {
+            first,
+            count: indices.len(),
+        },
+    };
+    if indices.len() <= MAX_LEAF_SIZE {
+        return (node, None);
+    }
+
+    let centroid = |i: usize| (bounds[i].0 + bounds[i].1) * 0.5;
+    let (centroid_min, centroid_max) = indices
+        .iter()
+        .map(|&i| (centroid(i), centroid(i)))
+        .reduce(|(min_a, max_a), (min_b, max_b)| {
+            (min_a.component_min(&min_b), max_a.component_max(&max_b))
+        })
+        .unwrap();
+    let extent = centroid_max - centroid_min;
+    let axis = (0..3)
+        .max_by(|&a, &b| extent.data[a][0].total_cmp(&extent.data[b][0]))
+        .unwrap();
+    let middle = indices.len() / 2;
+    indices.select_nth_unstable_by(middle, |&a, &b| {
+        centroid(a).data[axis][0].total_cmp(&centroid(b).data[axis][0])
+    });
+    (node, Some(middle))
+}
+
+/// Appends the subtree over `indices` to `nodes` in preorder, returning the
+/// index of its root. See `split` for `first`.
+fn build(
+    nodes: &mut Vec<BvhNode>,
+    bounds: &[(Vector3f, Vector3f)],
+    indices: &mut [usize],
+    first: usize,
+) -> usize {
+    let node_index = nodes.len();
+    let (node, middle) = split(bounds, indices, first);
+    nodes.push(node);
+    if let Some(middle) = middle {
+        let (left_indices, right_indices) = indices.split_at_mut(middle);
+        let left = build(nodes, bounds, left_indices, first);
+        let right = build(nodes, bounds, right_indices, first + middle);
+        nodes[node_index].kind = BvhNodeKind::Interior { left, right };
+    }
+    node_index
+}
+
+/// Nodes of the subtree over `indices` as laid out by `build`, with the
+/// two children of each of the top `split_depth` levels built on separate
+/// threads.
+fn build_parallel(
+    bounds: &[(Vector3f, Vector3f)],
+    indices: &mut [usize],
+    first: usize,
+    split_depth: u32,
+) -> Vec<BvhNode> {
+    if split_depth == 0 {
+        let mut nodes = Vec::new();
+        build(&mut nodes, bounds, indices, first);
+        return nodes;
+    }
+    let (node, middle) = split(bounds, indices, first);
+    let middle = match middle {
+        Some(middle) => middle,
+        None => return vec![node],
+    };
+    let (left_indices, right_indices) = indices.split_at_mut(middle);
+    let (left_nodes, right_nodes) = thread::scope(|scope| {
+        let left = scope.spawn(|| build_parallel(bounds, left_indices, first, split_depth - 1));
+        let right = build_parallel(bounds, right_indices, first + middle, split_depth - 1);
+        (left.join().unwrap(), right)
+    });
+    let (left, right) = (1, 1 + left_nodes.len());
+    let mut nodes = Vec::with_capacity(right + right_nodes.len());
+    nodes.push(BvhNode {
+        kind: BvhNodeKind::Interior { left, right },
+        ..node
+    });
+    for (offset, subtree) in [(left, left_nodes), (right, right_nodes)] {
+        nodes.extend(subtree.into_iter().map(|node| match node.kind {
+            BvhNodeKind::Interior { left, right } => BvhNode {
+                kind: BvhNodeKind::Interior {
+                    left: left + offset,
+                    right: right + offset,
+                },
+                ..node
+            },
+            BvhNodeKind::Leaf { .. } => node,
+        }));
+    }
+    nodes
+}
+
+/// Whether any of `objects[indices]` is hit before `t_max`.
+fn get_any_hit_of(objects: &[Object], indices: &[usize], ray: &Ray, t_max: f32) -> bool {
+    let direction_length = ray.direction.length();
+    indices.iter().any(|&i| {
+        get_intersection(&objects[i].surface, ray)
+            .is_some_and(|hit| ray.origin.distance(&hit) / direction_length < t_max)
+    })
+}
+
+/// Whether a point on the surface of a box is within `width` of one of its
+/// edges, that is of two of its faces.
+fn is_on_box_edge(point: &Vector3f, min: &Vector3f, max: &Vector3f, width: f32) -> bool {
+    (0..3)
+        .filter(|&axis| {
+            let c = point.data[axis][0];
+            (c - min.data[axis][0]).abs() < width || (c - max.data[axis][0]).abs() < width
+        })
+        .count()
+        >= 2
+}
+
+/// Uniform grid of voxels over the bounding box of a scene, each listing the
+/// objects whose bounding box overlaps it. Cheaper to build than a `Bvh` and
+/// as fast for evenly distributed objects of similar size. Stores indices
+/// into the object list it was built from.
+pub struct UniformGrid {
+    min: Vector3f,
+    max: Vector3f,
+    cell_size: Vector3f,
+    resolution: [usize; 3],
+    cells: Vec<Vec<usize>>,
+    /// Objects as large as planes, tested against every ray.
+    unbounded: Vec<usize>,
+}
+
+/// Target number of voxels per object.
+const GRID_CELLS_PER_OBJECT: f32 = 2.0;
+const MAX_GRID_RESOLUTION: usize = 64;
+
+impl UniformGrid {
+    pub fn new(objects: &[Object]) -> Self {
+        let bounds: Vec<(Vector3f, Vector3f)> =
+            objects.iter().map(|o| get_bounds(&o.surface)).collect();
+        let (bounded, unbounded): (Vec<usize>, Vec<usize>) = (0..objects.len()).partition(|&i| {
+            (bounds[i].1 - bounds[i].0)
+                .iter()
+                .all(|&e| e < PLANE_EXTENT)
+        });
+        let (min, max) = bounded
+            .iter()
+            .map(|&i| bounds[i])
+            .reduce(|(min_a, max_a), (min_b, max_b)| {
+                (min_a.component_min(&min_b), max_a.component_max(&max_b))
+            })
+            .unwrap_or((Vector3f::zeros(), Vector3f::zeros()));
+        let extent = (max - min).component_max(&Vector3f::xyz(1e-4, 1e-4, 1e-4));
+        let volume = extent.x() * extent.y() * extent.z();
+        let cells_per_unit = (GRID_CELLS_PER_OBJECT * bounded.len() as f32 / volume).cbrt();
+        let mut resolution = [1; 3];
+        for (axis, resolution) in resolution.iter_mut().enumerate() {
+            *resolution = ((extent.data[axis][0] * cells_per_unit).ceil() as usize)
+                .clamp(1, MAX_GRID_RESOLUTION);
+        }
+        let mut grid = UniformGrid {
+            min,
+            max: min + extent,
+            cell_size: Vector3f::xyz(
+                extent.x() / resolution[0] as f32,
+                extent.y() / resolution[1] as f32,
+                extent.z() / resolution[2] as f32,
+            ),
+            resolution,
+            cells: vec![Vec::new(); resolution.iter().product()],
+            unbounded,
+        };
+        for i in bounded {
+            let low = grid.cell_of(&bounds[i].0);
+            let high = grid.cell_of(&bounds[i].1);
+            for z in low[2]..=high[2] {
+                for y in low[1]..=high[1] {
+                    for x in low[0]..=high[0] {
+                        let cell = grid.cell_index([x, y, z]);
+                        grid.cells[cell].push(i);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    /// Walks the voxels along the ray with a 3D-DDA, stopping once the
+    /// closest hit so far lies before the exit of the current voxel.
+    pub fn closest_hit<'a>(
+        &self,
+        objects: &'a [Object],
+        ray: &Ray,
+    ) -> Option<(Vector3f, &'a Object)> {
+        let mut closest: Option<(Vector3f, &Object)> = None;
+        let mut closest_t = f32::INFINITY;
+        let direction_length = ray.direction.length();
+        let mut test = |indices: &[usize], closest_t: &mut f32| {
+            for &i in indices {
+                let object = &objects[i];
+                if let Some(hit) = get_intersection(&object.surface, ray) {
+                    let t = ray.origin.distance(&hit) / direction_length;
+                    if t < *closest_t {
+                        *closest_t = t;
+                        closest = Some((hit, object));
+                    }
+                }
+            }
+        };
+        test(&self.unbounded, &mut closest_t);
+
+        let (t_enter, _) = match ray_aabb(ray, &self.min, &self.max, 0.0, closest_t) {
+            Some(range) => range,
+            None => return closest,
+        };
+        let mut cell = self.cell_of(&(ray.origin + ray.direction * t_enter));
+        let (step, mut t_next, t_delta) = self.traversal(ray, cell);
+        loop {
+            test(&self.cells[self.cell_index(cell)], &mut closest_t);
+            let axis = (0..3)
+                .min_by(|&a, &b| t_next[a].total_cmp(&t_next[b]))
+                .unwrap();
+            if closest_t <= t_next[axis] {
+                break;
+            }
+            let next = cell[axis] as isize + step[axis];
+            if next < 0 || next >= self.resolution[axis] as isize {
+                break;
+            }
+            cell[axis] = next as usize;
+            t_next[axis] += t_delta[axis];
+        }
+        closest
+    }
+
+    /// Whether the ray hits any object before `t_max`. Walks the voxels like
+    /// `closest_hit` but returns at the first hit within range.
+    pub fn intersect_any(&self, objects: &[Object], ray: &Ray, t_max: f32) -> bool {
+        if get_any_hit_of(objects, &self.unbounded, ray, t_max) {
+            return true;
+        }
+        let (t_enter, _) = match ray_aabb(ray, &self.min, &self.max, 0.0, t_max) {
+            Some(range) => range,
+            None => return false,
+        };
+        let mut cell = self.cell_of(&(ray.origin + ray.direction * t_enter));
+        let (step, mut t_next, t_delta) = self.traversal(ray, cell);
+        loop {
+            if get_any_hit_of(objects, &self.cells[self.cell_index(cell)], ray, t_max) {
+                return true;
+            }
+            let axis = (0..3)
+                .min_by(|&a, &b| t_next[a].total_cmp(&t_next[b]))
+                .unwrap();
+            if t_max <= t_next[axis] {
+                return false;
+            }
+            let next = cell[axis] as isize + step[axis];
+            if next < 0 || next >= self.resolution[axis] as isize {
+                return false;
+            }
+            cell[axis] = next as usize;
+            t_next[axis] += t_delta[axis];
+        }
+    }
+
+    /// Step direction, ray parameter of the next voxel boundary and distance
+    /// between boundaries along each axis for a 3D-DDA starting in `cell`.
+    fn traversal(&self, ray: &Ray, cell: [usize; 3]) -> ([isize; 3], [f32; 3], [f32; 3]) {
+        let mut step = [0_isize; 3];
+        let mut t_next = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            let direction = ray.direction.data[axis][0];
+            let size = self.cell_size.data[axis][0];
+            let cell_min = self.min.data[axis][0] + cell[axis] as f32 * size;
+            let origin = ray.origin.data[axis][0];
+            if direction > 0.0 {
+                step[axis] = 1;
+                t_next[axis] = (cell_min + size - origin) / direction;
+                t_delta[axis] = size / direction;
+            } else if direction < 0.0 {
+                step[axis] = -1;
+                t_next[axis] = (cell_min - origin) / direction;
+                t_delta[axis] = -size / direction;
+            }
+        }
+        (step, t_next, t_delta)
+    }
+
+    /// Voxel containing `point`, clamped to the grid.
+    fn cell_of(&self, point: &Vector3f) -> [usize; 3] {
+        let mut cell = [0; 3];
+        for (axis, cell) in cell.iter_mut().enumerate() {
+            let offset =
+                (point.data[axis][0] - self.min.data[axis][0]) / self.cell_size.data[axis][0];
+            *cell = (offset.max(0.0) as usize).min(self.resolution[axis] - 1);
+        }
+        cell
+    }
+
+    fn cell_index(&self, cell: [usize; 3]) -> usize {
+        (cell[2] * self.resolution[1] + cell[1]) * self.resolution[0] + cell[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::{FovAxis, Material, Surface};
+    use matrix::Vector2i;
+    use rng::Rng;
+    use texture::Texture;
+
+    fn random_objects(count: usize, rng: &mut Rng) -> Vec<Object> {
+        (0..count)
+            .map(|i| {
+                let center = Vector3f::xyz(
+                    10.0 * rng.uniform() - 5.0,
+                    10.0 * rng.uniform() - 5.0,
+                    -10.0 * rng.uniform() - 2.0,
+                );
+                let surface = if i % 2 == 0 {
+                    Surface::Sphere {
+                        center,
+                        radius: 0.5 * rng.uniform() + 0.1,
+                    }
+                } else {
+                    Surface::Box {
+                        min: center - 0.3,
+                        max: center + 0.3,
+                    }
+                };
+                Object::new(
+                    surface,
+                    Material::Lambertian {
+                        albedo: Texture::Solid(Vector3f::xyz(0.5, 0.5, 0.5)),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bvh_matches_linear_search() {
+        let mut rng = Rng::new(7);
+        let objects = random_objects(100, &mut rng);
+        let bvh = Bvh::new(&objects);
+        for _ in 0..500 {
+            let ray = Ray {
+                origin: Vector3f::zeros(),
+                direction: Vector3f::xyz(rng.uniform() - 0.5, rng.uniform() - 0.5, -1.0)
+                    .normalized(),
+            };
+            let expected = get_closest_hit(&objects, &ray).map(|(hit, _)| hit);
+            let actual = bvh.closest_hit(&objects, &ray).map(|(hit, _)| hit);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_grid_matches_linear_search() {
+        let mut rng = Rng::new(11);
+        let mut objects = random_objects(200, &mut rng);
+        objects.push(Object::new(
+            Surface::Plane {
+                point: Vector3f::xyz(0.0, -6.0, 0.0),
+                normal: Vector3f::xyz(0.0, 1.0, 0.0),
+            },
+            Material::Lambertian {
+                albedo: Texture::Solid(Vector3f::xyz(0.5, 0.5, 0.5)),
+            },
+        ));
+        let grid = UniformGrid::new(&objects);
+        for i in 0..500 {
+            // Half of the rays start inside the grid.
+            let origin = if i % 2 == 0 {
+                Vector3f::zeros()
+            } else {
+                Vector3f::xyz(0.0, 0.0, -7.0)
+            };
+            let ray = Ray {
+                origin,
+                direction: Vector3f::xyz(rng.uniform() - 0.5, rng.uniform() - 0.5, -1.0)
+                    .normalized(),
+            };
+            let expected = get_closest_hit(&objects, &ray).map(|(hit, _)| hit);
+            let actual = grid.closest_hit(&objects, &ray).map(|(hit, _)| hit);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_intersect_any_matches_closest_hit() {
+        let mut rng = Rng::new(13);
+        let objects = random_objects(100, &mut rng);
+        let accels = [
+            Accel::Linear,
+            Accel::Bvh(Bvh::new(&objects)),
+            Accel::Grid(UniformGrid::new(&objects)),
+        ];
+        for i in 0..500 {
+            let ray = Ray {
+                origin: Vector3f::zeros(),
+                direction: Vector3f::xyz(rng.uniform() - 0.5, rng.uniform() - 0.5, -1.0)
+                    .normalized(),
+            };
+            let t_max = if i % 2 == 0 { f32::INFINITY } else { 6.0 };
+            let expected = get_closest_hit(&objects, &ray)
+                .is_some_and(|(hit, _)| ray.origin.distance(&hit) < t_max);
+            for accel in &accels {
+                assert_eq!(accel.intersect_any(&objects, &ray, t_max), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_build_matches_serial() {
+        let mut rng = Rng::new(17);
+        let objects = random_objects(300, &mut rng);
+        let serial = Bvh::new(&objects);
+        for threads in [2, 3, 8] {
+            assert_eq!(Bvh::new_parallel(&objects, threads), serial);
+        }
+        assert_eq!(Bvh::new_parallel(&objects[..2], 4), Bvh::new(&objects[..2]));
+    }
+
+    #[test]
+    fn test_root_encloses_all_objects() {
+        let mut rng = Rng::new(5);
+        let objects = random_objects(50, &mut rng);
+        let bvh = Bvh::new(&objects);
+        let root = &bvh.nodes[0];
+        for object in &objects {
+            let (min, max) = get_bounds(&object.surface);
+            assert_eq!(root.min.component_min(&min), root.min);
+            assert_eq!(root.max.component_max(&max), root.max);
+        }
+        let depths = bvh.node_depths();
+        assert_eq!(depths[0], 0);
+        assert!(depths.iter().all(|&depth| depth <= 6));
+    }
+
+    #[test]
+    fn test_wireframe_draws_box_edges() {
+        let objects = vec![Object::new(
+            Surface::Box {
+                min: Vector3f::xyz(-1.0, -1.0, -3.0),
+                max: Vector3f::xyz(1.0, 1.0, -2.0),
+            },
+            Material::Lambertian {
+                albedo: Texture::Solid(Vector3f::xyz(0.5, 0.5, 0.5)),
+            },
+        )];
+        let camera = Camera::new(
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(33, 33),
+        );
+        let mut image = Image::new(33, 33);
+        Bvh::new(&objects).draw_wireframe(&camera, &mut image);
+        let black = Vector3f::zeros();
+        let pixel = |x: u32, y: u32| image.data[(y * 33 + x) as usize];
+        // The front face spans pixels 8 to 24, the back face 10.7 to 21.3.
+        assert_ne!(pixel(8, 16), black);
+        assert_ne!(pixel(16, 11), black);
+        assert_eq!(pixel(16, 16), black);
+        assert_eq!(pixel(12, 16), black);
+        assert_eq!(pixel(2, 16), black);
+    }
+
+    #[test]
+    fn test_empty_accel() {
+        let bvh = Bvh::new(&[]);
+        let ray = Ray {
+            origin: Vector3f::zeros(),
+            direction: Vector3f::xyz(0.0, 0.0, -1.0),
+        };
+        assert!(bvh.closest_hit(&[], &ray).is_none());
+        assert!(UniformGrid::new(&[]).closest_hit(&[], &ray).is_none());
+    }
+}
@@ -0,0 +1,45 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Error of loading, saving or combining images and scenes.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// Malformed content at the 1-based `line` of a text format, or of the
+    /// header of a binary one.
+    Parse {
+        line: usize,
+        msg: String,
+    },
+    /// The named file is not in a format that can be read or written.
+    UnsupportedFormat(String),
+    /// Buffers combined per pixel differ in size, as described.
+    SizeMismatch(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => error.fmt(f),
+            Error::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+            Error::UnsupportedFormat(name) => write!(f, "unsupported format: {}", name),
+            Error::SizeMismatch(msg) => write!(f, "size mismatch: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
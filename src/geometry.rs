@@ -1,7 +1,53 @@
-use matrix::{Vector2f, Vector2i, Vector3f};
+use matrix::{Matrix4f, Vector2f, Vector2i, Vector3f};
 use rng::Rng;
+use sampling::concentric_disk;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use texture::Texture;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FovAxis {
+    Horizontal,
+    Vertical,
+    Diagonal,
+}
+
+/// Pixel the row index 0 starts from, which decides whether the up axis of
+/// the camera points toward the first or the last row of the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageOrigin {
+    /// Rows run top to bottom, as written to PPM and PNG.
+    TopLeft,
+    /// Rows run bottom to top, as in OpenGL and many image formats of
+    /// other tools.
+    BottomLeft,
+}
+
+#[derive(Clone)]
 pub struct Camera {
+    pub position: Vector3f,
+    /// Radius of the thin lens, 0 for a pinhole camera.
+    pub aperture_radius: f32,
+    /// Distance along the forward axis of the plane in focus.
+    pub focus_distance: f32,
+    /// Width over height of a pixel, 1 for square pixels. Stretches the
+    /// field of view horizontally.
+    pub pixel_aspect: f32,
+    pub image_origin: ImageOrigin,
+    /// Exposure time in seconds.
+    pub shutter_time: f32,
+    /// Focal length over the aperture diameter. Only affects the exposure,
+    /// depth of field follows `aperture_radius`.
+    pub f_number: f32,
+    /// Sensor sensitivity, 100 is the base of `exposure_value`.
+    pub iso: f32,
+    /// Distance along camera rays before which hits are skipped.
+    pub near: f32,
+    /// Distance along camera rays beyond which hits are left out of the
+    /// depth output, which is normalized from `near` to `far`. Infinite to
+    /// fit the scene instead.
+    pub far: f32,
     forward: Vector3f,
     up: Vector3f,
     right: Vector3f,
@@ -11,17 +57,55 @@ pub struct Camera {
 }
 
 impl Camera {
-    pub fn new(forward: Vector3f, up: Vector3f, fov_rad: f32, sensor_size_px: Vector2i) -> Self {
+    pub fn new(
+        forward: Vector3f,
+        up: Vector3f,
+        roll_rad: f32,
+        fov_rad: f32,
+        fov_axis: FovAxis,
+        sensor_size_px: Vector2i,
+    ) -> Self {
         let forward = forward.normalized();
-        let right = forward.cross(&up).normalized();
+        let mut right = forward.cross(&up);
+        if right.squared_length() < 1e-12 {
+            // `up` is parallel to `forward`, any perpendicular axis will do.
+            let axis = if forward.x().abs() < 0.9 {
+                Vector3f::xyz(1.0, 0.0, 0.0)
+            } else {
+                Vector3f::xyz(0.0, 0.0, 1.0)
+            };
+            right = forward.cross(&axis);
+        }
+        let right = right.normalized();
         let up = right.cross(&forward).normalized();
-        let sensor_size = sensor_size_px.x().max(sensor_size_px.y()) as f32;
-        let focal_length = (0.5 * (sensor_size - 1.0) / (fov_rad * 0.5).tan()).abs();
+        let (sin_roll, cos_roll) = roll_rad.sin_cos();
+        let (right, up) = (
+            right * cos_roll + up * sin_roll,
+            up * cos_roll - right * sin_roll,
+        );
+        let width = sensor_size_px.x() as f32 - 1.0;
+        let height = sensor_size_px.y() as f32 - 1.0;
+        let sensor_size = match fov_axis {
+            FovAxis::Horizontal => width,
+            FovAxis::Vertical => height,
+            FovAxis::Diagonal => (width * width + height * height).sqrt(),
+        };
+        let focal_length = (0.5 * sensor_size / (fov_rad * 0.5).tan()).abs();
         let principal_point = Vector2f::xy(
             sensor_size_px.x() as f32 / 2.0 - 0.5,
             sensor_size_px.y() as f32 / 2.0 - 0.5,
         );
         Self {
+            position: Vector3f::zeros(),
+            aperture_radius: 0.0,
+            focus_distance: 1.0,
+            pixel_aspect: 1.0,
+            image_origin: ImageOrigin::TopLeft,
+            shutter_time: 1.0,
+            f_number: 1.0,
+            iso: 100.0,
+            near: 0.0,
+            far: f32::INFINITY,
             forward,
             up,
             right,
@@ -32,14 +116,133 @@ impl Camera {
     }
 
     pub fn back_project(&self, x: f32, y: f32) -> Ray {
-        let x = x - self.principal_point.x();
-        let y = -(y - self.principal_point.y());
+        self.clip_near(self.pinhole_ray(x, y))
+    }
+
+    fn pinhole_ray(&self, x: f32, y: f32) -> Ray {
+        let x = (x - self.principal_point.x()) * self.pixel_aspect;
+        let y = match self.image_origin {
+            ImageOrigin::TopLeft => -(y - self.principal_point.y()),
+            ImageOrigin::BottomLeft => y - self.principal_point.y(),
+        };
         Ray {
-            origin: Vector3f::zeros(),
+            origin: self.position,
             direction: (self.forward * self.focal_length + self.right * x + self.up * y)
                 .normalized(),
         }
     }
+
+    /// Inverse of `back_project` for a pinhole camera: the pixel position
+    /// `point` is seen at, or `None` if it is not in front of the camera.
+    pub fn project(&self, point: &Vector3f) -> Option<Vector2f> {
+        let offset = *point - self.position;
+        let depth = offset.dot(&self.forward);
+        if depth <= 0.0 {
+            return None;
+        }
+        let scale = self.focal_length / depth;
+        let x = offset.dot(&self.right) * scale / self.pixel_aspect;
+        let y = offset.dot(&self.up) * scale;
+        let y = match self.image_origin {
+            ImageOrigin::TopLeft => -y,
+            ImageOrigin::BottomLeft => y,
+        };
+        Some(self.principal_point + Vector2f::xy(x, y))
+    }
+
+    pub fn forward(&self) -> Vector3f {
+        self.forward
+    }
+
+    /// Exposure value at ISO 100 of the shutter time, f-number and ISO
+    /// settings. Each step up halves the light reaching the image.
+    pub fn exposure_value(&self) -> f32 {
+        (self.f_number * self.f_number / self.shutter_time * 100.0 / self.iso).log2()
+    }
+
+    /// Linear scale from scene radiance to image values, 1 at EV 0 such as
+    /// 1 s at f/1 and ISO 100.
+    pub fn exposure_multiplier(&self) -> f32 {
+        (-self.exposure_value()).exp2()
+    }
+
+    /// Like `back_project` but with the ray origin sampled on the lens
+    /// aperture, such that only the focus plane is sharp.
+    pub fn sample_ray(&self, x: f32, y: f32, rng: &mut Rng) -> Ray {
+        if self.aperture_radius <= 0.0 {
+            return self.back_project(x, y);
+        }
+        let lens_sample = Vector2f::xy(rng.uniform(), rng.uniform());
+        self.lens_ray(x, y, &lens_sample)
+    }
+
+    /// Ray through `film_sample`, the position on the sensor scaled to
+    /// [0, 1] with (0.5, 0.5) at its center, from the point of the lens
+    /// aperture `lens_sample` in [0, 1]^2 maps to. Leaves generating the
+    /// samples to the caller, which can stratify both.
+    pub fn generate_ray(&self, film_sample: Vector2f, lens_sample: Vector2f) -> Ray {
+        let x = film_sample.x() * self.sensor_size_px.x() as f32 - 0.5;
+        let y = film_sample.y() * self.sensor_size_px.y() as f32 - 0.5;
+        self.lens_ray(x, y, &lens_sample)
+    }
+
+    fn lens_ray(&self, x: f32, y: f32, lens_sample: &Vector2f) -> Ray {
+        let ray = self.pinhole_ray(x, y);
+        if self.aperture_radius <= 0.0 {
+            return self.clip_near(ray);
+        }
+        let focus_point = ray.at(self.focus_distance / ray.direction.dot(&self.forward));
+        let lens = concentric_disk(lens_sample) * self.aperture_radius;
+        let origin = self.position + self.right * lens.x() + self.up * lens.y();
+        self.clip_near(Ray {
+            origin,
+            direction: (focus_point - origin).normalized(),
+        })
+    }
+
+    /// Moves the origin of a camera ray with a unit direction to `near`.
+    fn clip_near(&self, ray: Ray) -> Ray {
+        Ray {
+            origin: ray.at(self.near),
+            direction: ray.direction,
+        }
+    }
+
+    /// Returns a camera with the same intrinsics, placed at `position` and
+    /// looking at `target`.
+    pub fn look_at(&self, position: Vector3f, target: Vector3f, up: Vector3f) -> Camera {
+        let forward = (target - position).normalized();
+        let right = forward.cross(&up).normalized();
+        let up = right.cross(&forward).normalized();
+        Camera {
+            position,
+            forward,
+            up,
+            right,
+            ..self.clone()
+        }
+    }
+
+    /// Left and right eye cameras, moved apart by the interpupillary
+    /// distance `ipd` along the right axis and looking in parallel.
+    pub fn stereo_pair(&self, ipd: f32) -> (Camera, Camera) {
+        let offset = self.right * (0.5 * ipd);
+        let eye = |position| Camera {
+            position,
+            ..self.clone()
+        };
+        (eye(self.position - offset), eye(self.position + offset))
+    }
+
+    /// Returns a camera orbiting a bounding sphere at the given azimuth,
+    /// close enough for the sphere to fill the narrower image dimension.
+    pub fn frame_sphere(&self, center: Vector3f, radius: f32, azimuth_rad: f32) -> Camera {
+        let narrow_size = self.sensor_size_px.x().min(self.sensor_size_px.y()) as f32 - 1.0;
+        let half_fov = (0.5 * narrow_size / self.focal_length).atan();
+        let distance = radius / half_fov.sin();
+        let offset = Vector3f::xyz(azimuth_rad.sin(), 0.0, azimuth_rad.cos()) * distance;
+        self.look_at(center + offset, center, Vector3f::xyz(0.0, 1.0, 0.0))
+    }
 }
 
 pub struct Ray {
@@ -53,57 +256,947 @@ impl Ray {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Material {
-    Lambertian { albedo: Vector3f },
+    Lambertian {
+        albedo: Texture,
+    },
+    Mirror {
+        tint: Vector3f,
+    },
+    /// Rough diffuse surface (Oren and Nayar, "Generalization of Lambert's
+    /// Reflectance Model", 1994), brighter than Lambertian toward the light
+    /// like clay or the moon. `roughness` is the standard deviation of the
+    /// microfacet slope angle in radians; 0 is Lambertian.
+    OrenNayar {
+        albedo: Texture,
+        roughness: f32,
+    },
+    /// Light source absorbing all incoming light. Emits only within
+    /// `emit_cone_angle` radians of the surface normal, or over the full
+    /// hemisphere if `None`.
+    Emissive {
+        radiance: Vector3f,
+        emit_cone_angle: Option<f32>,
+    },
+    /// Glass-like refracting material. `absorption` is the Beer-Lambert
+    /// attenuation coefficient per unit distance traveled inside.
+    /// `dispersion` is the B coefficient of Cauchy's equation in um^2, about
+    /// 0.004 for crown glass, and only has an effect in spectral rendering.
+    /// `ior` applies at `REFERENCE_WAVELENGTH`, see `get_ior`.
+    Dielectric {
+        ior: f32,
+        absorption: Vector3f,
+        dispersion: f32,
+    },
+    /// Shades like `base` with the normal perturbed by a tangent space
+    /// normal map, whose texels encode components in [-1, 1] as [0, 1].
+    NormalMapped {
+        base: Box<Material>,
+        normal_map: Texture,
+    },
+    /// Stochastic mix scattering like `layer` with probability `weight` and
+    /// like `base` otherwise.
+    Blend {
+        base: Box<Material>,
+        layer: Box<Material>,
+        weight: f32,
+    },
+    /// Dielectric clear coat over a base material.
+    Coated {
+        base: Box<Material>,
+        coat_ior: f32,
+        coat_roughness: f32,
+    },
 }
 
+#[derive(Debug, PartialEq)]
 pub enum Surface {
-    Sphere { center: Vector3f, radius: f32 },
+    Sphere {
+        center: Vector3f,
+        radius: f32,
+    },
+    Box {
+        min: Vector3f,
+        max: Vector3f,
+    },
+    /// Infinite plane through `point`, facing `normal`.
+    Plane {
+        point: Vector3f,
+        normal: Vector3f,
+    },
+    /// Double sided triangle with texture coordinates at its vertices.
+    Triangle {
+        vertices: [Vector3f; 3],
+        uvs: [Vector2f; 3],
+    },
+    /// Several surfaces forming one logical object.
+    Compound(Vec<Surface>),
+    /// Shared geometry placed in the world by `transform`.
+    Instance {
+        geometry: Arc<Surface>,
+        transform: Transform,
+    },
+}
+
+/// Affine object-to-world transform together with its inverse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    matrix: Matrix4f,
+    inverse: Matrix4f,
+}
+
+impl Transform {
+    /// Returns `None` if `matrix` is not invertible.
+    pub fn new(matrix: Matrix4f) -> Option<Self> {
+        Some(Self {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    pub fn translation(offset: &Vector3f) -> Self {
+        let mut matrix = Matrix4f::identity();
+        let mut inverse = Matrix4f::identity();
+        for i in 0..3 {
+            *matrix.at_mut(i, 3) = offset.data[i][0];
+            *inverse.at_mut(i, 3) = -offset.data[i][0];
+        }
+        Self { matrix, inverse }
+    }
+
+    /// Composes two transforms, applying `self` first.
+    pub fn then(&self, other: &Transform) -> Self {
+        Self {
+            matrix: other.matrix.mat_mul(&self.matrix),
+            inverse: self.inverse.mat_mul(&other.inverse),
+        }
+    }
+
+    pub fn matrix(&self) -> &Matrix4f {
+        &self.matrix
+    }
+
+    pub fn inverse(&self) -> &Matrix4f {
+        &self.inverse
+    }
 }
 
 pub struct Object {
     pub surface: Surface,
-    pub material: Material,
+    /// Shared with other objects using the same material, such as the
+    /// triangles of a mesh.
+    pub material: Arc<Material>,
+    /// Materials of the faces of a `Surface::Box` in the order of
+    /// `get_face`, overriding `material`. Light sampling only considers
+    /// `material`.
+    pub face_materials: Option<Box<[Material; 6]>>,
+    id: u32,
+}
+
+impl Object {
+    /// Creates an object with a process-wide unique, non-zero id.
+    pub fn new<M: Into<Arc<Material>>>(surface: Surface, material: M) -> Self {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+        Self {
+            surface,
+            material: material.into(),
+            face_materials: None,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Box with a material per face, like a painted die or a room. `faces`
+    /// are ordered +x, -x, +y, -y, +z, -z.
+    pub fn with_face_materials(min: Vector3f, max: Vector3f, faces: [Material; 6]) -> Self {
+        let mut object = Self::new(Surface::Box { min, max }, faces[0].clone());
+        object.face_materials = Some(Box::new(faces));
+        object
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Material at `point` on the surface.
+    pub fn material_at(&self, point: &Vector3f) -> &Material {
+        match (&self.face_materials, get_face(&self.surface, point)) {
+            (Some(faces), Some(face)) => &faces[face],
+            _ => &self.material,
+        }
+    }
+}
+
+/// Offset of secondary ray origins from the surface relative to the
+/// magnitude of the hit point coordinates, see `offset_origin`.
+const ORIGIN_OFFSET: f32 = 1e-5;
+
+/// Origin for a ray leaving the surface at `point` toward `direction`,
+/// moved off the surface along the geometric `normal` to the side
+/// `direction` points to. The rounding error of a hit point grows with its
+/// coordinates, and so does the offset, so rays do not hit the surface they
+/// start from even on large objects far from the origin.
+pub fn offset_origin(point: &Vector3f, normal: &Vector3f, direction: &Vector3f) -> Vector3f {
+    let magnitude = point.x().abs().max(point.y().abs()).max(point.z().abs());
+    let offset = ORIGIN_OFFSET * magnitude.max(1.0);
+    if direction.dot(normal) < 0.0 {
+        *point - *normal * offset
+    } else {
+        *point + *normal * offset
+    }
+}
+
+/// Half size of the bounding box given to planes, acceleration structures
+/// miss plane hits farther out.
+pub const PLANE_EXTENT: f32 = 1e6;
+
+/// Horizontal floor at `height` that mirrors a `reflection_strength`
+/// fraction of the light and diffusely reflects the rest with `albedo`.
+pub fn mirror_floor(height: f32, albedo: Vector3f, reflection_strength: f32) -> Object {
+    Object::new(
+        Surface::Plane {
+            point: Vector3f::xyz(0.0, height, 0.0),
+            normal: Vector3f::xyz(0.0, 1.0, 0.0),
+        },
+        Material::Blend {
+            base: Box::new(Material::Lambertian {
+                albedo: Texture::Solid(albedo),
+            }),
+            layer: Box::new(Material::Mirror {
+                tint: Vector3f::rgb(1.0, 1.0, 1.0),
+            }),
+            weight: reflection_strength,
+        },
+    )
+}
+
+/// Deterministic field of `count` small spheres centered within the box
+/// `bounds`, with random diffuse, mirror and glass materials.
+pub fn random_spheres(count: usize, rng: &mut Rng, bounds: (Vector3f, Vector3f)) -> Vec<Object> {
+    let (min, max) = bounds;
+    let random_color = |rng: &mut Rng| Vector3f::rgb(rng.uniform(), rng.uniform(), rng.uniform());
+    (0..count)
+        .map(|_| {
+            let offset = Vector3f::xyz(rng.uniform(), rng.uniform(), rng.uniform());
+            let center = min + (max - min) * offset;
+            let radius = 0.1 + 0.2 * rng.uniform();
+            let choice = rng.uniform();
+            let material = if choice < 0.6 {
+                Material::Lambertian {
+                    albedo: Texture::Solid(random_color(rng)),
+                }
+            } else if choice < 0.85 {
+                Material::Mirror {
+                    tint: random_color(rng) * 0.5 + 0.5,
+                }
+            } else {
+                Material::Dielectric {
+                    ior: 1.5,
+                    absorption: random_color(rng),
+                    dispersion: 0.0,
+                }
+            };
+            Object::new(Surface::Sphere { center, radius }, material)
+        })
+        .collect()
 }
 
 pub fn get_intersection(surface: &Surface, ray: &Ray) -> Option<Vector3f> {
-    const MIN_DISTANCE: f32 = 1e-3;
     match surface {
-        Surface::Sphere { center, radius } => {
-            get_sphere_intersection(ray, center, *radius, MIN_DISTANCE)
+        Surface::Sphere { center, radius } => get_sphere_intersection(ray, center, *radius),
+        Surface::Box { min, max } => {
+            let (t_enter, t_exit) = ray_aabb(ray, min, max, f32::NEG_INFINITY, f32::INFINITY)?;
+            match (t_enter > 0.0, t_exit > 0.0) {
+                (true, _) => Some(ray.at(t_enter)),
+                (false, true) => Some(ray.at(t_exit)),
+                (false, false) => None,
+            }
+        }
+        Surface::Plane { point, normal } => {
+            let t = (*point - ray.origin).dot(normal) / ray.direction.dot(normal);
+            (t.is_finite() && t > 0.0).then(|| ray.at(t))
+        }
+        Surface::Triangle { vertices, .. } => get_triangle_intersection(ray, vertices),
+        Surface::Compound(surfaces) => surfaces
+            .iter()
+            .filter_map(|surface| get_intersection(surface, ray))
+            .min_by(|a, b| a.distance(&ray.origin).total_cmp(&b.distance(&ray.origin))),
+        Surface::Instance {
+            geometry,
+            transform,
+        } => {
+            let local_ray = Ray {
+                origin: transform.inverse.transform_point(&ray.origin),
+                direction: transform
+                    .inverse
+                    .transform_direction(&ray.direction)
+                    .normalized(),
+            };
+            get_intersection(geometry, &local_ray).map(|p| transform.matrix.transform_point(&p))
         }
     }
 }
 
-pub fn get_normal(surface: &Surface, point: &Vector3f, anti_reference: &Vector3f) -> Vector3f {
-    let mut normal = match surface {
-        Surface::Sphere { center, .. } => (*point - *center).normalized(),
+pub fn get_closest_hit<'a>(objects: &'a [Object], ray: &Ray) -> Option<(Vector3f, &'a Object)> {
+    let compare = |a: &Vector3f, b: &Vector3f| {
+        (ray.origin.squared_distance(a))
+            .partial_cmp(&ray.origin.squared_distance(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
     };
+    objects
+        .iter()
+        .filter_map(|object| get_intersection(&object.surface, ray).map(|hit| (hit, object)))
+        .min_by(|(a, _), (b, _)| compare(a, b))
+}
+
+/// Whether the ray hits any object before `t_max`, in units of the ray
+/// direction. Stops at the first such hit, which is all shadow rays need.
+pub fn get_any_hit(objects: &[Object], ray: &Ray, t_max: f32) -> bool {
+    let direction_length = ray.direction.length();
+    objects.iter().any(|object| {
+        get_intersection(&object.surface, ray)
+            .is_some_and(|hit| ray.origin.distance(&hit) / direction_length < t_max)
+    })
+}
+
+/// Axis-aligned bounding box of the surface as (min, max) corners.
+pub fn get_bounds(surface: &Surface) -> (Vector3f, Vector3f) {
+    match surface {
+        Surface::Sphere { center, radius } => (*center - *radius, *center + *radius),
+        Surface::Box { min, max } => (*min, *max),
+        Surface::Plane { .. } => (
+            Vector3f::xyz(-PLANE_EXTENT, -PLANE_EXTENT, -PLANE_EXTENT),
+            Vector3f::xyz(PLANE_EXTENT, PLANE_EXTENT, PLANE_EXTENT),
+        ),
+        Surface::Triangle { vertices, .. } => (
+            vertices[0]
+                .component_min(&vertices[1])
+                .component_min(&vertices[2]),
+            vertices[0]
+                .component_max(&vertices[1])
+                .component_max(&vertices[2]),
+        ),
+        Surface::Compound(surfaces) => surfaces
+            .iter()
+            .map(get_bounds)
+            .reduce(|(min_a, max_a), (min_b, max_b)| {
+                (min_a.component_min(&min_b), max_a.component_max(&max_b))
+            })
+            .unwrap_or((Vector3f::zeros(), Vector3f::zeros())),
+        Surface::Instance {
+            geometry,
+            transform,
+        } => {
+            let (min, max) = get_bounds(geometry);
+            let mut world_min = Vector3f::xyz(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+            let mut world_max = world_min * -1.0;
+            for corner in 0..8 {
+                let local = Vector3f::xyz(
+                    if corner & 1 == 0 { min.x() } else { max.x() },
+                    if corner & 2 == 0 { min.y() } else { max.y() },
+                    if corner & 4 == 0 { min.z() } else { max.z() },
+                );
+                let world = transform.matrix.transform_point(&local);
+                world_min = world_min.component_min(&world);
+                world_max = world_max.component_max(&world);
+            }
+            (world_min, world_max)
+        }
+    }
+}
+
+/// Slab test of a ray against the box spanned by `min` and `max`. Returns
+/// the entry and exit parameters clipped to [`t_min`, `t_max`].
+pub fn ray_aabb(
+    ray: &Ray,
+    min: &Vector3f,
+    max: &Vector3f,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, f32)> {
+    let mut t_enter = t_min;
+    let mut t_exit = t_max;
+    for axis in 0..3 {
+        let origin = ray.origin.data[axis][0];
+        let direction = ray.direction.data[axis][0];
+        let (low, high) = (min.data[axis][0], max.data[axis][0]);
+        if direction == 0.0 {
+            // Parallel to the slab, which the origin must then lie within.
+            if origin < low || origin > high {
+                return None;
+            }
+            continue;
+        }
+        let inverse = 1.0 / direction;
+        let t0 = (low - origin) * inverse;
+        let t1 = (high - origin) * inverse;
+        let (t0, t1) = if inverse < 0.0 { (t1, t0) } else { (t0, t1) };
+        t_enter = t_enter.max(t0);
+        t_exit = t_exit.min(t1);
+        if t_enter > t_exit {
+            return None;
+        }
+    }
+    Some((t_enter, t_exit))
+}
+
+pub fn get_normal(surface: &Surface, point: &Vector3f, anti_reference: &Vector3f) -> Vector3f {
+    let mut normal = get_outward_normal(surface, point);
     if normal.dot(anti_reference) > 0.0 {
         normal = normal * -1.0;
     }
     normal
 }
 
-pub fn get_scatter_direction(normal: &Vector3f, material: &Material, rng: &mut Rng) -> Vector3f {
+pub struct Scatter {
+    pub direction: Vector3f,
+    pub attenuation: Vector3f,
+    /// Solid angle pdf of `direction`, if it can be evaluated.
+    pub pdf: Option<f32>,
+    pub specular: bool,
+}
+
+/// `normal` faces against `ray`, `front_face` tells whether the ray arrives
+/// from the outside of the surface.
+pub fn get_scatter(
+    ray: &Ray,
+    normal: &Vector3f,
+    front_face: bool,
+    uv: &Vector2f,
+    material: &Material,
+    rng: &mut Rng,
+) -> Scatter {
+    match material {
+        Material::Lambertian { albedo } => {
+            // The BRDF albedo / pi times cos(theta) over the pdf cos(theta) / pi
+            // leaves only the albedo as attenuation.
+            let direction = rng.cosine_direction(normal);
+            Scatter {
+                direction,
+                attenuation: albedo.sample(uv),
+                pdf: get_scatter_pdf(material, &direction, normal),
+                specular: false,
+            }
+        }
+        Material::OrenNayar { albedo, roughness } => {
+            // Sampled like Lambertian, so the attenuation is the albedo
+            // scaled by the ratio of the two BRDFs.
+            let direction = rng.cosine_direction(normal);
+            let ratio = get_oren_nayar(normal, &(ray.direction * -1.0), &direction, *roughness);
+            Scatter {
+                direction,
+                attenuation: albedo.sample(uv) * ratio,
+                pdf: get_scatter_pdf(material, &direction, normal),
+                specular: false,
+            }
+        }
+        Material::Mirror { tint } => Scatter {
+            direction: ray.direction.reflect(normal),
+            attenuation: *tint,
+            pdf: None,
+            specular: true,
+        },
+        Material::Emissive { .. } => Scatter {
+            direction: *normal,
+            attenuation: Vector3f::zeros(),
+            pdf: None,
+            specular: true,
+        },
+        Material::Dielectric { ior, .. } => {
+            let eta = if front_face { 1.0 / ior } else { *ior };
+            let cos_theta = (-ray.direction.dot(normal)).clamp(0.0, 1.0);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let total_internal_reflection = eta * sin_theta > 1.0;
+            let direction =
+                if total_internal_reflection || rng.uniform() < fresnel_schlick(cos_theta, *ior) {
+                    ray.direction.reflect(normal)
+                } else {
+                    let perpendicular = (ray.direction + *normal * cos_theta) * eta;
+                    let parallel = *normal * -(1.0 - perpendicular.squared_length()).abs().sqrt();
+                    perpendicular + parallel
+                };
+            Scatter {
+                direction,
+                attenuation: Vector3f::rgb(1.0, 1.0, 1.0),
+                pdf: None,
+                specular: true,
+            }
+        }
+        // The renderer substitutes the shading normal before scattering.
+        Material::NormalMapped { base, .. } => get_scatter(ray, normal, front_face, uv, base, rng),
+        Material::Blend {
+            base,
+            layer,
+            weight,
+        } => {
+            let material = if rng.uniform() < *weight { layer } else { base };
+            get_scatter(ray, normal, front_face, uv, material, rng)
+        }
+        Material::Coated {
+            base,
+            coat_ior,
+            coat_roughness,
+        } => {
+            let cos_theta = (-ray.direction.dot(normal)).clamp(0.0, 1.0);
+            if rng.uniform() >= fresnel_schlick(cos_theta, *coat_ior) {
+                return get_scatter(ray, normal, front_face, uv, base, rng);
+            }
+            let direction =
+                (ray.direction.reflect(normal) + rng.unit_sphere() * *coat_roughness).normalized();
+            // Reflections below the surface are absorbed.
+            let attenuation = if direction.dot(normal) > 0.0 {
+                Vector3f::rgb(1.0, 1.0, 1.0)
+            } else {
+                Vector3f::zeros()
+            };
+            Scatter {
+                direction,
+                attenuation,
+                pdf: None,
+                specular: *coat_roughness == 0.0,
+            }
+        }
+    }
+}
+
+/// Solid angle pdf with which `get_scatter` scatters into `outgoing`, for
+/// weighting it against light sampling. `None` if the material scatters
+/// into discrete directions or cannot evaluate the pdf.
+pub fn get_scatter_pdf(material: &Material, outgoing: &Vector3f, normal: &Vector3f) -> Option<f32> {
     match material {
-        Material::Lambertian { .. } => (*normal + rng.unit_vector()).normalized(),
+        Material::Lambertian { .. } | Material::OrenNayar { .. } => {
+            Some(normal.dot(&outgoing.normalized()).max(0.0) / PI)
+        }
+        Material::Mirror { .. } | Material::Emissive { .. } | Material::Dielectric { .. } => None,
+        Material::NormalMapped { base, .. } => get_scatter_pdf(base, outgoing, normal),
+        Material::Blend {
+            base,
+            layer,
+            weight,
+        } => {
+            let base = get_scatter_pdf(base, outgoing, normal)?;
+            let layer = get_scatter_pdf(layer, outgoing, normal)?;
+            Some(weight * layer + (1.0 - weight) * base)
+        }
+        // The jittered coat reflection has no closed form pdf.
+        Material::Coated { .. } => None,
     }
 }
 
-pub fn get_attenuation(material: &Material) -> Vector3f {
+/// Reflectance of `material` at `uv` for denoiser albedo guides: the
+/// texture of diffuse materials, the tint of mirrors, white for glass and
+/// black for emitters.
+pub fn get_albedo(material: &Material, uv: &Vector2f) -> Vector3f {
     match material {
-        Material::Lambertian { albedo } => *albedo,
+        Material::Lambertian { albedo } | Material::OrenNayar { albedo, .. } => albedo.sample(uv),
+        Material::Mirror { tint } => *tint,
+        Material::Emissive { .. } => Vector3f::rgb(0.0, 0.0, 0.0),
+        Material::Dielectric { .. } => Vector3f::rgb(1.0, 1.0, 1.0),
+        Material::NormalMapped { base, .. } | Material::Coated { base, .. } => get_albedo(base, uv),
+        Material::Blend {
+            base,
+            layer,
+            weight,
+        } => get_albedo(layer, uv) * *weight + get_albedo(base, uv) * (1.0 - weight),
     }
 }
 
-fn get_sphere_intersection(
-    ray: &Ray,
-    center: &Vector3f,
-    radius: f32,
-    min_distance: f32,
-) -> Option<Vector3f> {
+/// Oren-Nayar BRDF between the unit directions `incoming` and `outgoing`,
+/// both pointing away from the surface, relative to the Lambertian BRDF of
+/// the same albedo.
+pub fn get_oren_nayar(
+    normal: &Vector3f,
+    incoming: &Vector3f,
+    outgoing: &Vector3f,
+    roughness: f32,
+) -> f32 {
+    let variance = roughness * roughness;
+    let a = 1.0 - 0.5 * variance / (variance + 0.33);
+    let b = 0.45 * variance / (variance + 0.09);
+    let cos_in = normal.dot(incoming).clamp(0.0, 1.0);
+    let cos_out = normal.dot(outgoing).clamp(0.0, 1.0);
+    // Cosine of the azimuth between the directions, 0 if either is normal.
+    let tangent_in = *incoming - *normal * cos_in;
+    let tangent_out = *outgoing - *normal * cos_out;
+    let tangent_lengths = tangent_in.length() * tangent_out.length();
+    let cos_azimuth = if tangent_lengths > 1e-6 {
+        (tangent_in.dot(&tangent_out) / tangent_lengths).max(0.0)
+    } else {
+        0.0
+    };
+    // sin(alpha) tan(beta) with alpha the larger and beta the smaller of
+    // the polar angles.
+    let (cos_alpha, cos_beta) = (cos_in.min(cos_out), cos_in.max(cos_out));
+    let sin_alpha = (1.0 - cos_alpha * cos_alpha).sqrt();
+    let tan_beta = (1.0 - cos_beta * cos_beta).sqrt() / cos_beta.max(1e-6);
+    a + b * cos_azimuth * sin_alpha * tan_beta
+}
+
+/// Radiance emitted toward the origin of a ray with direction `direction`
+/// hitting a surface with `normal` facing the ray.
+pub fn get_emission(material: &Material, normal: &Vector3f, direction: &Vector3f) -> Vector3f {
+    match material {
+        Material::Emissive {
+            radiance,
+            emit_cone_angle,
+        } => match emit_cone_angle {
+            Some(angle) if -direction.dot(normal) < angle.cos() => Vector3f::zeros(),
+            _ => *radiance,
+        },
+        _ => Vector3f::zeros(),
+    }
+}
+
+/// Wavelength in nm at which the `ior` of a dielectric is given.
+pub const REFERENCE_WAVELENGTH: f32 = 550.0;
+
+/// Index of refraction at `wavelength` in nm of a dielectric with `ior` at
+/// `REFERENCE_WAVELENGTH` and the Cauchy coefficient `dispersion` in um^2.
+pub fn get_ior(ior: f32, dispersion: f32, wavelength: f32) -> f32 {
+    let inverse_square = |nm: f32| 1e6 / (nm * nm);
+    ior + dispersion * (inverse_square(wavelength) - inverse_square(REFERENCE_WAVELENGTH))
+}
+
+/// Fraction of light surviving a ray segment of length `distance` that ends
+/// on the inside of a surface with `material`.
+pub fn get_transmittance(material: &Material, distance: f32) -> Vector3f {
+    match material {
+        Material::Dielectric { absorption, .. } => Vector3f::rgb(
+            (-absorption.r() * distance).exp(),
+            (-absorption.g() * distance).exp(),
+            (-absorption.b() * distance).exp(),
+        ),
+        _ => Vector3f::rgb(1.0, 1.0, 1.0),
+    }
+}
+
+/// Schlick's approximation of the Fresnel reflectance of a dielectric
+/// surface surrounded by air.
+pub fn fresnel_schlick(cos_theta: f32, ior: f32) -> f32 {
+    let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
+}
+
+/// Normal pointing out of closed surfaces, or along the winding of triangles.
+pub fn get_outward_normal(surface: &Surface, point: &Vector3f) -> Vector3f {
+    match surface {
+        Surface::Sphere { center, .. } => (*point - *center).normalized(),
+        Surface::Box { min, max } => get_box_normal(point, min, max),
+        Surface::Plane { normal, .. } => normal.normalized(),
+        Surface::Triangle { vertices, .. } => (vertices[1] - vertices[0])
+            .cross(&(vertices[2] - vertices[0]))
+            .normalized(),
+        Surface::Compound(surfaces) => get_outward_normal(get_member(surfaces, point), point),
+        Surface::Instance {
+            geometry,
+            transform,
+        } => {
+            let local_normal =
+                get_outward_normal(geometry, &transform.inverse.transform_point(point));
+            // Normals transform with the inverse transpose.
+            transform
+                .inverse
+                .transpose()
+                .transform_direction(&local_normal)
+                .normalized()
+        }
+    }
+}
+
+/// Index of the face of a box surface at `point`, ordered +x, -x, +y, -y,
+/// +z, -z along the axes of the box before any instance transform. `None`
+/// for other surfaces.
+pub fn get_face(surface: &Surface, point: &Vector3f) -> Option<usize> {
+    match surface {
+        Surface::Box { min, max } => Some(get_box_face(point, min, max)),
+        Surface::Instance {
+            geometry,
+            transform,
+        } => get_face(geometry, &transform.inverse.transform_point(point)),
+        _ => None,
+    }
+}
+
+/// Area of the surfaces that can be sampled by `get_surface_sample`.
+pub fn get_area(surface: &Surface) -> Option<f32> {
+    match surface {
+        Surface::Sphere { radius, .. } => Some(4.0 * PI * radius * radius),
+        Surface::Triangle { vertices, .. } => Some(
+            0.5 * (vertices[1] - vertices[0])
+                .cross(&(vertices[2] - vertices[0]))
+                .length(),
+        ),
+        _ => None,
+    }
+}
+
+/// Point distributed uniformly by area on `surface`, with its outward normal.
+pub fn get_surface_sample(surface: &Surface, rng: &mut Rng) -> Option<(Vector3f, Vector3f)> {
+    match surface {
+        Surface::Sphere { center, radius } => {
+            let normal = rng.unit_sphere_surface();
+            Some((*center + normal * *radius, normal))
+        }
+        Surface::Triangle { vertices, .. } => {
+            let su = rng.uniform().sqrt();
+            let b0 = 1.0 - su;
+            let b1 = rng.uniform() * su;
+            let point = vertices[0] * b0 + vertices[1] * b1 + vertices[2] * (1.0 - b0 - b1);
+            Some((point, get_outward_normal(surface, &point)))
+        }
+        _ => None,
+    }
+}
+
+/// Direction from `from` toward a sphere, uniformly distributed over the
+/// cone the sphere subtends, and its solid angle pdf. `None` for other
+/// surfaces and for points inside the sphere, which need area sampling.
+pub fn get_sample_toward(
+    surface: &Surface,
+    from: &Vector3f,
+    rng: &mut Rng,
+) -> Option<(Vector3f, f32)> {
+    let (axis, cone) = get_sphere_cone(surface, from)?;
+    let cos_theta = 1.0 - rng.uniform() * cone;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.uniform();
+    let (tangent, bitangent) = axis.orthonormal_basis();
+    let direction =
+        tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + axis * cos_theta;
+    Some((direction, 1.0 / (2.0 * PI * cone)))
+}
+
+/// Solid angle pdf with which `get_sample_toward` returns `direction`.
+pub fn get_pdf_toward(surface: &Surface, from: &Vector3f, direction: &Vector3f) -> Option<f32> {
+    let (axis, cone) = get_sphere_cone(surface, from)?;
+    if direction.cos_angle(&axis) >= 1.0 - cone {
+        Some(1.0 / (2.0 * PI * cone))
+    } else {
+        Some(0.0)
+    }
+}
+
+/// Axis toward the center of a sphere seen from outside, and one minus the
+/// cosine of the half angle of the cone it subtends.
+fn get_sphere_cone(surface: &Surface, from: &Vector3f) -> Option<(Vector3f, f32)> {
+    match surface {
+        Surface::Sphere { center, radius } => {
+            let offset = *center - *from;
+            let squared_distance = offset.squared_length();
+            if squared_distance <= radius * radius {
+                return None;
+            }
+            let sin2_theta_max = radius * radius / squared_distance;
+            let cos_theta_max = (1.0 - sin2_theta_max).sqrt();
+            // 1 - cos computed without cancellation for distant spheres.
+            let cone = sin2_theta_max / (1.0 + cos_theta_max);
+            Some((offset / squared_distance.sqrt(), cone))
+        }
+        _ => None,
+    }
+}
+
+/// Texture coordinates at `point` on `surface`. Spheres use their
+/// spherical angles, boxes have no parametrization.
+pub fn get_uv(surface: &Surface, point: &Vector3f) -> Vector2f {
+    match surface {
+        Surface::Sphere { center, .. } => {
+            let (theta, phi) = (*point - *center).to_spherical();
+            Vector2f::xy((phi + PI) / (2.0 * PI), theta / PI)
+        }
+        Surface::Box { .. } | Surface::Plane { .. } => Vector2f::zeros(),
+        Surface::Triangle { vertices, uvs } => {
+            let [b0, b1, b2] = get_barycentrics(point, vertices);
+            uvs[0] * b0 + uvs[1] * b1 + uvs[2] * b2
+        }
+        Surface::Compound(surfaces) => get_uv(get_member(surfaces, point), point),
+        Surface::Instance {
+            geometry,
+            transform,
+        } => get_uv(geometry, &transform.inverse.transform_point(point)),
+    }
+}
+
+/// Direction of increasing u at `point`, not necessarily perpendicular to
+/// the normal. Surfaces without a parametrization return any tangent.
+pub fn get_tangent(surface: &Surface, point: &Vector3f) -> Vector3f {
+    match surface {
+        Surface::Sphere { center, .. } => {
+            let local = *point - *center;
+            let tangent = Vector3f::xyz(-local.z(), 0.0, local.x());
+            if tangent.squared_length() > 0.0 {
+                tangent.normalized()
+            } else {
+                Vector3f::xyz(1.0, 0.0, 0.0)
+            }
+        }
+        Surface::Triangle { vertices, uvs } => {
+            let edge1 = vertices[1] - vertices[0];
+            let edge2 = vertices[2] - vertices[0];
+            let duv1 = uvs[1] - uvs[0];
+            let duv2 = uvs[2] - uvs[0];
+            let determinant = duv1.x() * duv2.y() - duv2.x() * duv1.y();
+            if determinant.abs() < 1e-12 {
+                return edge1.normalized();
+            }
+            ((edge1 * duv2.y() - edge2 * duv1.y()) / determinant).normalized()
+        }
+        Surface::Compound(surfaces) => get_tangent(get_member(surfaces, point), point),
+        Surface::Instance {
+            geometry,
+            transform,
+        } => {
+            let local_tangent = get_tangent(geometry, &transform.inverse.transform_point(point));
+            transform
+                .matrix
+                .transform_direction(&local_tangent)
+                .normalized()
+        }
+        Surface::Box { .. } | Surface::Plane { .. } => {
+            let normal = get_outward_normal(surface, point);
+            let axis = if normal.x().abs() < 0.9 {
+                Vector3f::xyz(1.0, 0.0, 0.0)
+            } else {
+                Vector3f::xyz(0.0, 0.0, 1.0)
+            };
+            normal.cross(&axis).normalized()
+        }
+    }
+}
+
+/// Shading normal for a normal map `texel` in the tangent frame around
+/// `normal`, which `tangent` need not be perpendicular to.
+pub fn apply_normal_map(normal: &Vector3f, tangent: &Vector3f, texel: &Vector3f) -> Vector3f {
+    let tangent = (*tangent - *normal * normal.dot(tangent)).normalized();
+    let bitangent = normal.cross(&tangent);
+    let local = *texel * 2.0 - 1.0;
+    (tangent * local.x() + bitangent * local.y() + *normal * local.z()).normalized()
+}
+
+/// Member of a compound surface that `point` lies on.
+fn get_member<'a>(surfaces: &'a [Surface], point: &Vector3f) -> &'a Surface {
+    surfaces
+        .iter()
+        .min_by(|a, b| get_surface_distance(a, point).total_cmp(&get_surface_distance(b, point)))
+        .expect("compound surfaces must not be empty")
+}
+
+/// Distance from `point` to `surface`, only exact close to the surface.
+fn get_surface_distance(surface: &Surface, point: &Vector3f) -> f32 {
+    match surface {
+        Surface::Sphere { center, radius } => (point.distance(center) - radius).abs(),
+        Surface::Box { min, max } => {
+            let center = (*min + *max) * 0.5;
+            let half_size = (*max - *min) * 0.5;
+            let offset = *point - center;
+            let outside = (0..3)
+                .map(|i| {
+                    (offset.data[i][0].abs() - half_size.data[i][0])
+                        .max(0.0)
+                        .powi(2)
+                })
+                .sum::<f32>()
+                .sqrt();
+            let inside = (0..3)
+                .map(|i| half_size.data[i][0] - offset.data[i][0].abs())
+                .fold(f32::INFINITY, f32::min);
+            outside.max(inside)
+        }
+        Surface::Plane {
+            point: origin,
+            normal,
+        } => (*point - *origin).dot(&normal.normalized()).abs(),
+        Surface::Triangle { vertices, .. } => {
+            let normal = get_outward_normal(surface, point);
+            let plane_distance = (*point - vertices[0]).dot(&normal).abs();
+            if get_barycentrics(point, vertices)
+                .iter()
+                .all(|&b| b >= -1e-4)
+            {
+                plane_distance
+            } else {
+                vertices
+                    .iter()
+                    .map(|v| v.distance(point))
+                    .fold(f32::INFINITY, f32::min)
+            }
+        }
+        Surface::Compound(surfaces) => surfaces
+            .iter()
+            .map(|surface| get_surface_distance(surface, point))
+            .fold(f32::INFINITY, f32::min),
+        Surface::Instance {
+            geometry,
+            transform,
+        } => get_surface_distance(geometry, &transform.inverse.transform_point(point)),
+    }
+}
+
+/// Möller-Trumbore intersection.
+fn get_triangle_intersection(ray: &Ray, vertices: &[Vector3f; 3]) -> Option<Vector3f> {
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+    let p = ray.direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < 1e-8 {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let s = ray.origin - vertices[0];
+    let u = s.dot(&p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(&edge1);
+    let v = ray.direction.dot(&q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(&q) * inverse_determinant;
+    (t > 0.0).then(|| ray.at(t))
+}
+
+fn get_barycentrics(point: &Vector3f, vertices: &[Vector3f; 3]) -> [f32; 3] {
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+    let offset = *point - vertices[0];
+    let d11 = edge1.dot(&edge1);
+    let d12 = edge1.dot(&edge2);
+    let d22 = edge2.dot(&edge2);
+    let d1 = offset.dot(&edge1);
+    let d2 = offset.dot(&edge2);
+    let denominator = d11 * d22 - d12 * d12;
+    let b1 = (d22 * d1 - d12 * d2) / denominator;
+    let b2 = (d11 * d2 - d12 * d1) / denominator;
+    [1.0 - b1 - b2, b1, b2]
+}
+
+fn get_box_normal(point: &Vector3f, min: &Vector3f, max: &Vector3f) -> Vector3f {
+    let face = get_box_face(point, min, max);
+    let mut normal = Vector3f::zeros();
+    normal.data[face / 2][0] = if face.is_multiple_of(2) { 1.0 } else { -1.0 };
+    normal
+}
+
+/// Face of the box nearest to `point`, in the order +x, -x, +y, -y, +z, -z.
+fn get_box_face(point: &Vector3f, min: &Vector3f, max: &Vector3f) -> usize {
+    // The hit face is the one along which the point is relatively farthest
+    // from the center.
+    let center = (*min + *max) * 0.5;
+    let half_size = (*max - *min) * 0.5;
+    let offset = *point - center;
+    let local = |axis: usize| offset.data[axis][0] / half_size.data[axis][0];
+    let axis = (0..3)
+        .max_by(|&a, &b| local(a).abs().total_cmp(&local(b).abs()))
+        .unwrap();
+    if local(axis) >= 0.0 {
+        2 * axis
+    } else {
+        2 * axis + 1
+    }
+}
+
+fn get_sphere_intersection(ray: &Ray, center: &Vector3f, radius: f32) -> Option<Vector3f> {
     let oc = ray.origin - *center;
     let half_p = ray.direction.dot(&oc);
     let q = oc.dot(&oc) - radius * radius;
@@ -115,7 +1208,7 @@ fn get_sphere_intersection(
     let t1 = -half_p - discriminant.sqrt();
     let t2 = -half_p + discriminant.sqrt();
 
-    let intersection_point = match (t1 > min_distance, t2 > min_distance) {
+    let intersection_point = match (t1 > 0.0, t2 > 0.0) {
         (true, _) => ray.at(t1),
         (false, true) => ray.at(t2),
         (false, false) => return None,
@@ -126,13 +1219,16 @@ fn get_sphere_intersection(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use image::{Color, Image};
 
     #[test]
     fn test_camera_ray_forward() {
         let camera = Camera::new(
             Vector3f::xyz(0.0, 0.0, -1.0),
             Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
             90_f32.to_radians(),
+            FovAxis::Horizontal,
             Vector2i::xy(256, 256),
         );
 
@@ -150,7 +1246,14 @@ mod tests {
     fn test_camera_ray_left_side() {
         let forward = Vector3f::xyz(0.0, 0.0, -1.0);
         let up = Vector3f::xyz(0.0, 1.0, 0.0);
-        let camera = Camera::new(forward, up, 90_f32.to_radians(), Vector2i::xy(256, 256));
+        let camera = Camera::new(
+            forward,
+            up,
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(256, 256),
+        );
 
         let ray = camera.back_project(0.0, 127.5);
         let tol = 1e-6;
@@ -165,7 +1268,14 @@ mod tests {
     fn test_camera_ray_up_side() {
         let forward = Vector3f::xyz(0.0, 0.0, -1.0);
         let up = Vector3f::xyz(0.0, 1.0, 0.0);
-        let camera = Camera::new(forward, up, 90_f32.to_radians(), Vector2i::xy(256, 512));
+        let camera = Camera::new(
+            forward,
+            up,
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Vertical,
+            Vector2i::xy(256, 512),
+        );
 
         let ray = camera.back_project(127.5, 0.0);
         let tol = 1e-6;
@@ -176,4 +1286,744 @@ mod tests {
             tol
         );
     }
+
+    #[test]
+    fn test_camera_roll_swaps_right_and_up() {
+        let forward = Vector3f::xyz(0.0, 0.0, -1.0);
+        let up = Vector3f::xyz(0.0, 1.0, 0.0);
+        let size = Vector2i::xy(256, 256);
+        let fov = 90_f32.to_radians();
+        let camera = Camera::new(forward, up, 0.0, fov, FovAxis::Horizontal, size);
+        let rolled = Camera::new(forward, up, fov, fov, FovAxis::Horizontal, size);
+
+        let tol = 1e-6;
+        for i in 0..3 {
+            assert_approx!(rolled.right.data[i][0], camera.up.data[i][0], tol);
+            assert_approx!(rolled.up.data[i][0], -camera.right.data[i][0], tol);
+        }
+        let ray = rolled.back_project(255.0, 127.5);
+        assert!(ray.direction.y() > 0.0);
+        assert_approx!(ray.direction.x(), 0.0, tol);
+    }
+
+    #[test]
+    fn test_pixel_aspect() {
+        let mut camera = Camera::new(
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(64, 48),
+        );
+        let spread = |camera: &Camera, x: f32, y: f32| {
+            let direction = camera.back_project(x, y).direction;
+            (
+                direction.x() / -direction.z(),
+                direction.y() / -direction.z(),
+            )
+        };
+        let square = spread(&camera, 63.0, 0.0);
+        camera.pixel_aspect = 2.0;
+        let anamorphic = spread(&camera, 63.0, 0.0);
+        assert_approx!(anamorphic.0, 2.0 * square.0, 1e-5);
+        assert_approx!(anamorphic.1, square.1, 1e-5);
+    }
+
+    #[test]
+    fn test_generate_ray() {
+        let mut camera = Camera::new(
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(64, 48),
+        );
+        camera.aperture_radius = 0.1;
+        camera.focus_distance = 2.0;
+        let center = Vector2f::xy(0.5, 0.5);
+        let expected = camera.back_project(31.5, 23.5);
+        let ray = camera.generate_ray(center, center);
+        assert_matrix_approx!(ray.origin, expected.origin, 1e-6);
+        assert_matrix_approx!(ray.direction, expected.direction, 1e-6);
+        // Film samples span the pixels from their outer edges.
+        let corner = camera.generate_ray(Vector2f::xy(0.0, 0.0), center);
+        assert_matrix_approx!(
+            corner.direction,
+            camera.back_project(-0.5, -0.5).direction,
+            1e-6
+        );
+        // Other lens samples move the origin but still meet in focus.
+        let lens_ray = camera.generate_ray(center, Vector2f::xy(1.0, 0.5));
+        assert_matrix_approx!(lens_ray.origin, Vector3f::xyz(0.1, 0.0, 0.0), 1e-6);
+        assert_matrix_approx!(
+            lens_ray.at(2.0 / -lens_ray.direction.z()),
+            expected.at(2.0),
+            1e-5
+        );
+    }
+
+    #[test]
+    fn test_project_inverts_back_project() {
+        let mut camera = Camera::new(
+            Vector3f::xyz(1.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.3,
+            70_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(64, 48),
+        );
+        camera.position = Vector3f::xyz(1.0, 2.0, 3.0);
+        camera.pixel_aspect = 1.5;
+        for origin in [ImageOrigin::TopLeft, ImageOrigin::BottomLeft] {
+            camera.image_origin = origin;
+            let ray = camera.back_project(10.0, 40.0);
+            let pixel = camera.project(&ray.at(5.0)).unwrap();
+            assert_matrix_approx!(pixel, Vector2f::xy(10.0, 40.0), 1e-3);
+        }
+        assert!(camera
+            .project(&(camera.position - camera.forward()))
+            .is_none());
+    }
+
+    #[test]
+    fn test_exposure_triangle() {
+        let mut camera = Camera::new(
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(4, 4),
+        );
+        assert_eq!(camera.exposure_value(), 0.0);
+        assert_eq!(camera.exposure_multiplier(), 1.0);
+        // Sunny 16: 1/100 s at f/16 and ISO 100.
+        (camera.shutter_time, camera.f_number) = (0.01, 16.0);
+        assert_approx!(camera.exposure_value(), 14.64, 0.01);
+        let multiplier = camera.exposure_multiplier();
+        camera.shutter_time /= 2.0;
+        assert_approx!(camera.exposure_multiplier(), multiplier / 2.0, 1e-9);
+        camera.iso *= 2.0;
+        assert_approx!(camera.exposure_multiplier(), multiplier, 1e-9);
+    }
+
+    #[test]
+    fn test_stereo_pair() {
+        let camera = Camera::new(
+            Vector3f::xyz(1.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(64, 64),
+        );
+        let (left, right) = camera.stereo_pair(0.064);
+        let baseline = right.position - left.position;
+        assert_approx!(baseline.dot(&camera.right), 0.064, 1e-6);
+        assert_approx!(baseline.length(), 0.064, 1e-6);
+        assert_eq!((left.position + right.position) * 0.5, camera.position);
+        assert_eq!(left.forward(), camera.forward());
+    }
+
+    #[test]
+    fn test_thin_lens_focus() {
+        let mut camera = Camera::new(
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(64, 64),
+        );
+        let mut rng = Rng::new(0);
+        let pinhole = camera.back_project(10.0, 20.0);
+        let ray = camera.sample_ray(10.0, 20.0, &mut rng);
+        assert_eq!(ray.origin, pinhole.origin);
+        assert_eq!(ray.direction, pinhole.direction);
+
+        camera.aperture_radius = 0.1;
+        camera.focus_distance = 3.0;
+        let focus_point = pinhole.at(3.0 / -pinhole.direction.z());
+        for _ in 0..10 {
+            let ray = camera.sample_ray(10.0, 20.0, &mut rng);
+            assert!(ray.origin.distance(&pinhole.origin) <= 0.1 + 1e-6);
+            let t = (focus_point.z() - ray.origin.z()) / ray.direction.z();
+            assert_approx!(ray.at(t).distance(&focus_point), 0.0, 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_frame_sphere() {
+        let camera = Camera::new(
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(65, 65),
+        );
+        let center = Vector3f::xyz(1.0, 0.0, -5.0);
+        let framed = camera.frame_sphere(center, 1.0, 90_f32.to_radians());
+        let tol = 1e-5;
+        assert_approx!(framed.position.distance(&center), 2_f32.sqrt(), tol);
+        assert_approx!(framed.position.x(), 1.0 + 2_f32.sqrt(), tol);
+
+        let ray = framed.back_project(32.0, 32.0);
+        assert_approx!(ray.direction.x(), -1.0, tol);
+        // The edge ray is tangent to the sphere.
+        let edge = framed.back_project(0.0, 32.0);
+        let to_center = center - edge.origin;
+        let distance_to_ray =
+            (to_center - edge.direction * to_center.dot(&edge.direction)).length();
+        assert_approx!(distance_to_ray, 1.0, tol);
+    }
+
+    #[test]
+    fn test_camera_fov_axis() {
+        let forward = Vector3f::xyz(0.0, 0.0, -1.0);
+        let up = Vector3f::xyz(0.0, 1.0, 0.0);
+        let size = Vector2i::xy(401, 201);
+        let fov = 90_f32.to_radians();
+        let tol = 1e-6;
+
+        let camera = Camera::new(forward, up, 0.0, fov, FovAxis::Horizontal, size);
+        let ray = camera.back_project(0.0, 100.0);
+        assert_approx!(ray.direction.cos_angle(&forward), (fov / 2.0).cos(), tol);
+
+        let camera = Camera::new(forward, up, 0.0, fov, FovAxis::Vertical, size);
+        let ray = camera.back_project(200.0, 0.0);
+        assert_approx!(ray.direction.cos_angle(&forward), (fov / 2.0).cos(), tol);
+
+        let camera = Camera::new(forward, up, 0.0, fov, FovAxis::Diagonal, size);
+        let ray = camera.back_project(0.0, 0.0);
+        assert_approx!(ray.direction.cos_angle(&forward), (fov / 2.0).cos(), tol);
+    }
+
+    #[test]
+    fn test_ray_aabb_miss() {
+        let ray = Ray {
+            origin: Vector3f::xyz(0.0, 2.0, 0.0),
+            direction: Vector3f::xyz(0.0, 0.0, -1.0),
+        };
+        let (min, max) = (
+            Vector3f::xyz(-1.0, -1.0, -3.0),
+            Vector3f::xyz(1.0, 1.0, -2.0),
+        );
+        assert!(ray_aabb(&ray, &min, &max, 0.0, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_ray_aabb_graze() {
+        let ray = Ray {
+            origin: Vector3f::xyz(0.0, 1.0, 0.0),
+            direction: Vector3f::xyz(0.0, 0.0, -1.0),
+        };
+        let (min, max) = (
+            Vector3f::xyz(-1.0, -1.0, -3.0),
+            Vector3f::xyz(1.0, 1.0, -2.0),
+        );
+        assert_eq!(
+            ray_aabb(&ray, &min, &max, 0.0, f32::INFINITY),
+            Some((2.0, 3.0))
+        );
+    }
+
+    #[test]
+    fn test_ray_aabb_through() {
+        let ray = Ray {
+            origin: Vector3f::xyz(-2.0, -2.0, 0.0),
+            direction: Vector3f::xyz(1.0, 1.0, -1.0).normalized(),
+        };
+        let (min, max) = (
+            Vector3f::xyz(-1.0, -1.0, -3.0),
+            Vector3f::xyz(1.0, 1.0, -1.0),
+        );
+        let (t_enter, t_exit) = ray_aabb(&ray, &min, &max, 0.0, f32::INFINITY).unwrap();
+        let tol = 1e-5;
+        assert_approx!(t_enter, 3_f32.sqrt(), tol);
+        assert_approx!(t_exit, 3.0 * 3_f32.sqrt(), tol);
+        assert!(ray_aabb(&ray, &min, &max, 0.0, 1.0).is_none());
+
+        let reversed = Ray {
+            origin: Vector3f::xyz(2.0, 2.0, -4.0),
+            direction: ray.direction * -1.0,
+        };
+        let (t_enter, t_exit) = ray_aabb(&reversed, &min, &max, 0.0, f32::INFINITY).unwrap();
+        assert_approx!(t_enter, 3_f32.sqrt(), tol);
+        assert_approx!(t_exit, 3.0 * 3_f32.sqrt(), tol);
+    }
+
+    #[test]
+    fn test_box_intersection_and_normal() {
+        let surface = Surface::Box {
+            min: Vector3f::xyz(-1.0, -1.0, -3.0),
+            max: Vector3f::xyz(1.0, 1.0, -2.0),
+        };
+        let ray = Ray {
+            origin: Vector3f::zeros(),
+            direction: Vector3f::xyz(0.0, 0.0, -1.0),
+        };
+        let point = get_intersection(&surface, &ray).unwrap();
+        assert_eq!(point, Vector3f::xyz(0.0, 0.0, -2.0));
+        let normal = get_normal(&surface, &point, &ray.direction);
+        assert_eq!(normal, Vector3f::xyz(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_mirror_reflects_exactly() {
+        let ray = Ray {
+            origin: Vector3f::xyz(-1.0, 1.0, 0.0),
+            direction: Vector3f::xyz(1.0, -1.0, 0.0).normalized(),
+        };
+        let normal = Vector3f::xyz(0.0, 1.0, 0.0);
+        let material = Material::Mirror {
+            tint: Vector3f::rgb(1.0, 1.0, 1.0),
+        };
+        let mut rng = Rng::new(0);
+
+        let scatter = get_scatter(&ray, &normal, true, &Vector2f::zeros(), &material, &mut rng);
+        assert_eq!(scatter.direction, Vector3f::xyz(1.0, 1.0, 0.0).normalized());
+        assert_eq!(scatter.attenuation, Vector3f::rgb(1.0, 1.0, 1.0));
+        assert!(scatter.specular);
+    }
+
+    #[test]
+    fn test_emission_cone() {
+        let normal = Vector3f::xyz(0.0, -1.0, 0.0);
+        let radiance = Vector3f::rgb(5.0, 5.0, 5.0);
+        let downlight = Material::Emissive {
+            radiance,
+            emit_cone_angle: Some(30_f32.to_radians()),
+        };
+        let straight_up = Vector3f::xyz(0.0, 1.0, 0.0);
+        let oblique = Vector3f::xyz(1.0, 1.0, 0.0).normalized();
+        assert_eq!(get_emission(&downlight, &normal, &straight_up), radiance);
+        assert_eq!(
+            get_emission(&downlight, &normal, &oblique),
+            Vector3f::zeros()
+        );
+
+        let area_light = Material::Emissive {
+            radiance,
+            emit_cone_angle: None,
+        };
+        assert_eq!(get_emission(&area_light, &normal, &oblique), radiance);
+        let diffuse = Material::Lambertian {
+            albedo: Texture::Solid(radiance),
+        };
+        assert_eq!(get_emission(&diffuse, &normal, &oblique), Vector3f::zeros());
+    }
+
+    #[test]
+    fn test_coat_reflects_more_at_grazing_angles() {
+        let material = Material::Coated {
+            base: Box::new(Material::Lambertian {
+                albedo: Texture::Solid(Vector3f::rgb(0.8, 0.1, 0.1)),
+            }),
+            coat_ior: 1.5,
+            coat_roughness: 0.0,
+        };
+        let normal = Vector3f::xyz(0.0, 1.0, 0.0);
+        let mut rng = Rng::new(0);
+        let mut coat_fraction = |direction: Vector3f| {
+            let ray = Ray {
+                origin: Vector3f::zeros(),
+                direction: direction.normalized(),
+            };
+            let num_samples = 10000;
+            let num_reflected = (0..num_samples)
+                .filter(|_| {
+                    get_scatter(&ray, &normal, true, &Vector2f::zeros(), &material, &mut rng)
+                        .specular
+                })
+                .count();
+            num_reflected as f32 / num_samples as f32
+        };
+        let normal_incidence = coat_fraction(Vector3f::xyz(0.0, -1.0, 0.0));
+        let grazing = coat_fraction(Vector3f::xyz(1.0, -0.05, 0.0));
+        assert_approx!(normal_incidence, fresnel_schlick(1.0, 1.5), 0.01);
+        assert!(grazing > 0.5);
+        assert!(grazing > 10.0 * normal_incidence);
+    }
+
+    #[test]
+    fn test_face_of_instance() {
+        let die = Surface::Box {
+            min: Vector3f::xyz(-1.0, -1.0, -1.0),
+            max: Vector3f::xyz(1.0, 1.0, 1.0),
+        };
+        let moved = Surface::Instance {
+            geometry: Arc::new(die),
+            transform: Transform::translation(&Vector3f::xyz(10.0, 0.0, 0.0)),
+        };
+        assert_eq!(get_face(&moved, &Vector3f::xyz(11.0, 0.2, 0.3)), Some(0));
+        assert_eq!(get_face(&moved, &Vector3f::xyz(10.2, 0.3, -1.0)), Some(5));
+    }
+
+    #[test]
+    fn test_instances_share_geometry() {
+        let sphere = Arc::new(Surface::Sphere {
+            center: Vector3f::zeros(),
+            radius: 1.0,
+        });
+        let scaled = Transform::new(Matrix4f::new([
+            [2.0, 0.0, 0.0, 0.0],
+            [0.0, 2.0, 0.0, 0.0],
+            [0.0, 0.0, 2.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]))
+        .unwrap();
+        let near = Surface::Instance {
+            geometry: sphere.clone(),
+            transform: Transform::translation(&Vector3f::xyz(0.0, 0.0, -3.0)),
+        };
+        let far = Surface::Instance {
+            geometry: sphere.clone(),
+            transform: scaled.then(&Transform::translation(&Vector3f::xyz(4.0, 0.0, -10.0))),
+        };
+        assert_eq!(Arc::strong_count(&sphere), 3);
+
+        let direction = Vector3f::xyz(0.0, 0.0, -1.0);
+        let ray = Ray {
+            origin: Vector3f::zeros(),
+            direction,
+        };
+        let hit = get_intersection(&near, &ray).unwrap();
+        assert!(hit.distance(&Vector3f::xyz(0.0, 0.0, -2.0)) < 1e-4);
+        assert!(get_intersection(&far, &ray).is_none());
+
+        let ray = Ray {
+            origin: Vector3f::xyz(4.0, 0.0, 0.0),
+            direction,
+        };
+        let hit = get_intersection(&far, &ray).unwrap();
+        assert!(hit.distance(&Vector3f::xyz(4.0, 0.0, -8.0)) < 1e-4);
+        let normal = get_normal(&far, &hit, &direction);
+        assert!(normal.distance(&Vector3f::xyz(0.0, 0.0, 1.0)) < 1e-4);
+        let (min, max) = get_bounds(&far);
+        assert!(min.distance(&Vector3f::xyz(2.0, -2.0, -12.0)) < 1e-4);
+        assert!(max.distance(&Vector3f::xyz(6.0, 2.0, -8.0)) < 1e-4);
+    }
+
+    #[test]
+    fn test_checker_texture_on_quad() {
+        let mut checker = Image::new(2, 2);
+        checker.data[0] = Color::rgb(1.0, 1.0, 1.0);
+        checker.data[3] = Color::rgb(1.0, 1.0, 1.0);
+        let material = Material::Lambertian {
+            albedo: Texture::Image(checker),
+        };
+        let corners = [
+            Vector3f::xyz(-1.0, 1.0, -2.0),
+            Vector3f::xyz(1.0, 1.0, -2.0),
+            Vector3f::xyz(1.0, -1.0, -2.0),
+            Vector3f::xyz(-1.0, -1.0, -2.0),
+        ];
+        let uvs = [
+            Vector2f::xy(0.0, 0.0),
+            Vector2f::xy(1.0, 0.0),
+            Vector2f::xy(1.0, 1.0),
+            Vector2f::xy(0.0, 1.0),
+        ];
+        let quad = [
+            Surface::Triangle {
+                vertices: [corners[0], corners[1], corners[2]],
+                uvs: [uvs[0], uvs[1], uvs[2]],
+            },
+            Surface::Triangle {
+                vertices: [corners[0], corners[2], corners[3]],
+                uvs: [uvs[0], uvs[2], uvs[3]],
+            },
+        ];
+        let mut rng = Rng::new(0);
+        let mut albedo_at = |x: f32, y: f32| {
+            let ray = Ray {
+                origin: Vector3f::xyz(x, y, 0.0),
+                direction: Vector3f::xyz(0.0, 0.0, -1.0),
+            };
+            let (surface, point) = quad
+                .iter()
+                .find_map(|surface| Some((surface, get_intersection(surface, &ray)?)))
+                .unwrap();
+            let normal = get_normal(surface, &point, &ray.direction);
+            let uv = get_uv(surface, &point);
+            get_scatter(&ray, &normal, true, &uv, &material, &mut rng).attenuation
+        };
+        let white = Color::rgb(1.0, 1.0, 1.0);
+        let black = Color::rgb(0.0, 0.0, 0.0);
+        assert_eq!(albedo_at(-0.9, 0.9), white);
+        assert_eq!(albedo_at(0.9, 0.9), black);
+        assert_eq!(albedo_at(0.9, -0.9), white);
+        assert_eq!(albedo_at(-0.9, -0.9), black);
+    }
+
+    #[test]
+    fn test_camera_with_parallel_up() {
+        let axis = Vector3f::xyz(0.0, 1.0, 0.0);
+        let camera = Camera::new(
+            axis,
+            axis,
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(8, 8),
+        );
+        for v in [camera.forward, camera.up, camera.right] {
+            assert!((v.length() - 1.0).abs() < 1e-5);
+        }
+        assert!(camera.forward.dot(&camera.up).abs() < 1e-5);
+        assert!(camera.forward.dot(&camera.right).abs() < 1e-5);
+        assert!(camera.up.dot(&camera.right).abs() < 1e-5);
+        let direction = camera.back_project(0.0, 0.0).direction;
+        assert!(direction.iter().all(|c| c.is_finite()));
+    }
+
+    #[test]
+    fn test_random_spheres_are_reproducible() {
+        let bounds = (
+            Vector3f::xyz(-5.0, 0.0, -10.0),
+            Vector3f::xyz(5.0, 1.0, -2.0),
+        );
+        let a = random_spheres(20, &mut Rng::new(7), bounds);
+        let b = random_spheres(20, &mut Rng::new(7), bounds);
+        let c = random_spheres(20, &mut Rng::new(8), bounds);
+        assert_eq!(a.len(), 20);
+        for (a, b) in a.iter().zip(&b) {
+            assert_eq!(a.surface, b.surface);
+            assert_eq!(a.material, b.material);
+        }
+        assert!(a.iter().zip(&c).any(|(a, c)| a.surface != c.surface));
+        for object in &a {
+            let (min, max) = get_bounds(&object.surface);
+            let center = (min + max) * 0.5;
+            assert_eq!(center.component_max(&bounds.0), center);
+            assert_eq!(center.component_min(&bounds.1), center);
+        }
+    }
+
+    #[test]
+    fn test_dispersion() {
+        assert_eq!(get_ior(1.5, 0.0042, REFERENCE_WAVELENGTH), 1.5);
+        let blue = get_ior(1.5, 0.0042, 450.0);
+        let red = get_ior(1.5, 0.0042, 650.0);
+        assert!(blue > 1.5 && red < 1.5);
+        assert_approx!(blue - red, 0.0042 * (1.0 / 0.2025 - 1.0 / 0.4225), 1e-5);
+        assert_eq!(get_ior(1.5, 0.0, 450.0), get_ior(1.5, 0.0, 650.0));
+    }
+
+    #[test]
+    fn test_dielectric_refracts_towards_normal() {
+        let material = Material::Dielectric {
+            ior: 1.5,
+            absorption: Vector3f::zeros(),
+            dispersion: 0.0,
+        };
+        let normal = Vector3f::xyz(0.0, 1.0, 0.0);
+        let ray = Ray {
+            origin: Vector3f::zeros(),
+            direction: Vector3f::xyz(1.0, -1.0, 0.0).normalized(),
+        };
+        let mut rng = Rng::new(0);
+        let refracted = (0..100)
+            .map(|_| get_scatter(&ray, &normal, true, &Vector2f::zeros(), &material, &mut rng))
+            .find(|scatter| scatter.direction.y() < 0.0)
+            .unwrap()
+            .direction;
+        // Snell's law: sin(45 deg) = 1.5 sin(theta_t).
+        let sin_t = refracted.x() / refracted.length();
+        assert!((sin_t - 45_f32.to_radians().sin() / 1.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_mirror_floor_reflects() {
+        let floor = mirror_floor(0.0, Vector3f::rgb(0.5, 0.5, 0.5), 1.0);
+        let ray = Ray {
+            origin: Vector3f::xyz(0.0, 1.0, 0.0),
+            direction: Vector3f::xyz(1.0, -1.0, 0.0).normalized(),
+        };
+        let hit = get_intersection(&floor.surface, &ray).unwrap();
+        assert_matrix_approx!(hit, Vector3f::xyz(1.0, 0.0, 0.0), 1e-6);
+        let normal = get_normal(&floor.surface, &hit, &ray.direction);
+        let mut rng = Rng::new(0);
+        let scatter = get_scatter(
+            &ray,
+            &normal,
+            true,
+            &Vector2f::zeros(),
+            &floor.material,
+            &mut rng,
+        );
+        let expected = Vector3f::xyz(1.0, 1.0, 0.0).normalized();
+        assert_matrix_approx!(scatter.direction, expected, 1e-6);
+        assert!(scatter.specular);
+
+        let parallel = Ray {
+            origin: Vector3f::xyz(0.0, 1.0, 0.0),
+            direction: Vector3f::xyz(1.0, 0.0, 0.0),
+        };
+        assert!(get_intersection(&floor.surface, &parallel).is_none());
+    }
+
+    #[test]
+    fn test_flat_normal_map() {
+        let sphere = Surface::Sphere {
+            center: Vector3f::zeros(),
+            radius: 1.0,
+        };
+        let point = Vector3f::xyz(0.6, 0.0, 0.8);
+        let normal = get_normal(&sphere, &point, &Vector3f::xyz(0.0, 0.0, -1.0));
+        let tangent = get_tangent(&sphere, &point);
+        assert_approx!(tangent.dot(&normal), 0.0, 1e-6);
+        let flat = Vector3f::xyz(0.5, 0.5, 1.0);
+        assert_matrix_approx!(apply_normal_map(&normal, &tangent, &flat), normal, 1e-6);
+        let tilted = apply_normal_map(&normal, &tangent, &Vector3f::xyz(1.0, 0.5, 0.5));
+        assert_matrix_approx!(tilted, tangent, 1e-6);
+    }
+
+    #[test]
+    fn test_sphere_cone_sampling() {
+        let sphere = Surface::Sphere {
+            center: Vector3f::xyz(1.0, 2.0, -4.0),
+            radius: 1.5,
+        };
+        let from = Vector3f::xyz(0.0, 0.0, 1.0);
+        let mut rng = Rng::new(6);
+        for _ in 0..100 {
+            let (direction, pdf) = get_sample_toward(&sphere, &from, &mut rng).unwrap();
+            let ray = Ray {
+                origin: from,
+                direction,
+            };
+            assert!(get_intersection(&sphere, &ray).is_some());
+            assert_eq!(get_pdf_toward(&sphere, &from, &direction), Some(pdf));
+        }
+        // Integrating the pdf over all directions by uniform sampling.
+        let samples = 200_000;
+        let integral = (0..samples)
+            .map(|_| get_pdf_toward(&sphere, &from, &rng.unit_sphere_surface()).unwrap())
+            .sum::<f32>()
+            * 4.0
+            * PI
+            / samples as f32;
+        assert_approx!(integral, 1.0, 0.03);
+        let inside = Vector3f::xyz(1.0, 2.0, -3.0);
+        assert!(get_sample_toward(&sphere, &inside, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_lambertian_pdf_integrates_to_one() {
+        let material = Material::Lambertian {
+            albedo: Texture::Solid(Vector3f::rgb(0.5, 0.5, 0.5)),
+        };
+        let normal = Vector3f::xyz(0.0, 0.0, 1.0);
+        let mut rng = Rng::new(8);
+        let samples = 100_000;
+        let integral = (0..samples)
+            .map(|_| {
+                let outgoing = rng.unit_sphere_surface();
+                get_scatter_pdf(&material, &outgoing, &normal).unwrap()
+            })
+            .sum::<f32>()
+            * 4.0
+            * PI
+            / samples as f32;
+        assert_approx!(integral, 1.0, 0.02);
+        let mirror = Material::Mirror {
+            tint: Vector3f::rgb(1.0, 1.0, 1.0),
+        };
+        assert_eq!(get_scatter_pdf(&mirror, &normal, &normal), None);
+        let blend = Material::Blend {
+            base: Box::new(material.clone()),
+            layer: Box::new(mirror),
+            weight: 0.5,
+        };
+        assert_eq!(get_scatter_pdf(&blend, &normal, &normal), None);
+    }
+
+    #[test]
+    fn test_offset_origin_prevents_acne_on_large_sphere() {
+        let sphere = Surface::Sphere {
+            center: Vector3f::zeros(),
+            radius: 1e4,
+        };
+        let mut rng = Rng::new(4);
+        let (mut acne_fixed, mut acne_offset) = (0, 0);
+        for _ in 0..1000 {
+            let point = rng.unit_sphere_surface() * 1e4;
+            let normal = get_outward_normal(&sphere, &point);
+            // Leaving the convex sphere, no ray may hit it again.
+            let direction = (rng.unit_sphere_surface() + normal).normalized();
+            let fixed = Ray {
+                origin: point,
+                direction,
+            };
+            if get_intersection(&sphere, &fixed).is_some_and(|hit| hit.distance(&point) > 1e-3) {
+                acne_fixed += 1;
+            }
+            let offset = Ray {
+                origin: offset_origin(&point, &normal, &direction),
+                direction,
+            };
+            if get_intersection(&sphere, &offset).is_some() {
+                acne_offset += 1;
+            }
+        }
+        assert!(acne_fixed > 0);
+        assert_eq!(acne_offset, 0);
+    }
+
+    #[test]
+    fn test_oren_nayar() {
+        let albedo = Texture::Solid(Vector3f::rgb(0.5, 0.5, 0.5));
+        let lambertian = Material::Lambertian {
+            albedo: albedo.clone(),
+        };
+        let smooth = Material::OrenNayar {
+            albedo,
+            roughness: 0.0,
+        };
+        let normal = Vector3f::xyz(0.0, 1.0, 0.0);
+        let ray = Ray {
+            origin: Vector3f::xyz(1.0, 1.0, 0.0),
+            direction: Vector3f::xyz(-1.0, -1.0, 0.0).normalized(),
+        };
+        let uv = Vector2f::xy(0.0, 0.0);
+        for seed in 0..10 {
+            let expected = get_scatter(&ray, &normal, true, &uv, &lambertian, &mut Rng::new(seed));
+            let scatter = get_scatter(&ray, &normal, true, &uv, &smooth, &mut Rng::new(seed));
+            assert_eq!(scatter.direction, expected.direction);
+            assert_approx!(scatter.attenuation.r(), expected.attenuation.r(), 1e-6);
+            assert_eq!(scatter.pdf, expected.pdf);
+        }
+        // Rough surfaces scatter back toward grazing light more than
+        // Lambertian ones, and less away from it.
+        let grazing = Vector3f::xyz(1.0, 0.2, 0.0).normalized();
+        let back = get_oren_nayar(&normal, &grazing, &grazing, 0.5);
+        let forward = get_oren_nayar(&normal, &grazing, &(grazing * -1.0).reflect(&normal), 0.5);
+        assert!(back > 1.0, "{}", back);
+        assert!(forward < 1.0, "{}", forward);
+    }
+
+    #[test]
+    fn test_compound_returns_nearer_hit() {
+        let sphere = |z: f32| Surface::Sphere {
+            center: Vector3f::xyz(0.0, 0.0, z),
+            radius: 1.0,
+        };
+        let compound = Surface::Compound(vec![sphere(-6.0), sphere(-3.0)]);
+        let ray = Ray {
+            origin: Vector3f::zeros(),
+            direction: Vector3f::xyz(0.0, 0.0, -1.0),
+        };
+        let hit = get_intersection(&compound, &ray).unwrap();
+        assert_matrix_approx!(hit, Vector3f::xyz(0.0, 0.0, -2.0), 1e-5);
+        let far_side = Vector3f::xyz(0.0, 0.0, -7.0);
+        let normal = get_outward_normal(&compound, &far_side);
+        assert_matrix_approx!(normal, Vector3f::xyz(0.0, 0.0, -1.0), 1e-5);
+        let (min, max) = get_bounds(&compound);
+        assert_matrix_approx!(min, Vector3f::xyz(-1.0, -1.0, -7.0), 1e-6);
+        assert_matrix_approx!(max, Vector3f::xyz(1.0, 1.0, -2.0), 1e-6);
+    }
 }
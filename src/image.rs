@@ -1,7 +1,17 @@
-use matrix::Vector3f;
-use std::fs::File;
+use error::Error;
+use matrix::{Vector2i, Vector3f};
+use std::fs::{self, File};
 use std::io::Write;
 
+/// Bits per color channel of saved images.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    /// Keeps more tonal range, e.g. for later grading.
+    Sixteen,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Image {
     pub width: u32,
     pub height: u32,
@@ -10,30 +20,416 @@ pub struct Image {
 
 impl Image {
     pub fn new(width: u32, height: u32) -> Image {
-        let black = Color::rgb(0.0, 0.0, 0.0);
+        Image::filled(width, height, Color::rgb(0.0, 0.0, 0.0))
+    }
+
+    pub fn filled(width: u32, height: u32, color: Color) -> Image {
         Image {
             width,
             height,
-            data: vec![black; (width * height) as usize],
+            data: vec![color; (width * height) as usize],
         }
     }
 
-    pub fn save(&self, filename: &str) {
-        assert!(filename.ends_with(".ppm"));
-        let mut file = File::create(filename).unwrap();
-        file.write_all(&self.to_ppm()).unwrap();
+    /// Sets every pixel to `color`.
+    pub fn clear(&mut self, color: Color) {
+        self.data.fill(color);
     }
 
-    fn to_ppm(&self) -> Vec<u8> {
-        let float_to_byte = |f: f32| ((f.max(0.0).min(1.0) * 255.0).round()) as u8;
-        let header = format!("P6 {} {} 255 ", self.width, self.height);
-        let data_bytes: Vec<u8> = self
-            .data
-            .iter()
-            .map(|c| [c.r(), c.g(), c.b()])
-            .flatten()
-            .map(float_to_byte)
-            .collect();
+    pub fn from_counts(width: u32, height: u32, counts: &[u32], max: u32) -> Image {
+        assert_eq!(counts.len(), (width * height) as usize);
+        let scale = 1.0 / max.max(1) as f32;
+        Image {
+            width,
+            height,
+            data: counts
+                .iter()
+                .map(|&count| heat_color(count as f32 * scale))
+                .collect(),
+        }
+    }
+
+    /// Halves the resolution by averaging blocks of 2x2 pixels. Odd sizes
+    /// are rounded up, repeating the last row or column.
+    pub fn downsample(&self) -> Image {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut result = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Color::rgb(0.0, 0.0, 0.0);
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (2 * x + dx).min(self.width - 1);
+                    let sy = (2 * y + dy).min(self.height - 1);
+                    sum += self.data[(sy * self.width + sx) as usize];
+                }
+                result.data[(y * width + x) as usize] = sum * 0.25;
+            }
+        }
+        result
+    }
+
+    /// Bilinearly interpolates between pixel centers. `u` runs left to
+    /// right and `v` top to bottom over [0, 1], clamped at the border.
+    pub fn sample_bilinear(&self, u: f32, v: f32) -> Color {
+        let x = (u * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let pixel = |x: u32, y: u32| self.data[(y * self.width + x) as usize];
+        let top = pixel(x0, y0) * (1.0 - fx) + pixel(x1, y0) * fx;
+        let bottom = pixel(x0, y1) * (1.0 - fx) + pixel(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    /// Reverses the order of the rows, e.g. for bottom-up formats.
+    pub fn flip_vertical(&self) -> Image {
+        self.remap(self.width, self.height, |x, y| (x, self.height - 1 - y))
+    }
+
+    /// Mirrors every row.
+    pub fn flip_horizontal(&self) -> Image {
+        self.remap(self.width, self.height, |x, y| (self.width - 1 - x, y))
+    }
+
+    /// Rotates clockwise by 90 degrees, swapping width and height.
+    pub fn rotate_90(&self) -> Image {
+        self.remap(self.height, self.width, |x, y| (y, self.height - 1 - x))
+    }
+
+    /// New image of the given size whose pixel (x, y) is taken from the
+    /// pixel of this image at `source(x, y)`.
+    fn remap<F: Fn(u32, u32) -> (u32, u32)>(&self, width: u32, height: u32, source: F) -> Image {
+        Image {
+            width,
+            height,
+            data: (0..width * height)
+                .map(|i| {
+                    let (x, y) = source(i % width, i / width);
+                    self.data[(y * self.width + x) as usize]
+                })
+                .collect(),
+        }
+    }
+
+    /// Sets the pixel at `point` to `color`, if inside the image. For debug
+    /// overlays, e.g. at `Camera::project` of a hit.
+    pub fn draw_point(&mut self, point: Vector2i, color: Color) {
+        let (x, y) = (point.x(), point.y());
+        if x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height {
+            self.data[(y as u32 * self.width + x as u32) as usize] = color;
+        }
+    }
+
+    /// Draws the line from `a` to `b`, both ends included, with Bresenham's
+    /// algorithm. The line is clipped to the image first, so ends far
+    /// outside, e.g. of points projected close to the camera plane, do not
+    /// slow it down.
+    pub fn draw_line(&mut self, a: Vector2i, b: Vector2i, color: Color) {
+        let ((ax, ay), (bx, by)) = match self.clip_line(a, b) {
+            Some(ends) => ends,
+            None => return,
+        };
+        let (dx, dy) = ((bx - ax).abs(), -(by - ay).abs());
+        let (step_x, step_y) = ((bx - ax).signum(), (by - ay).signum());
+        let (mut x, mut y) = (ax, ay);
+        let mut error = dx + dy;
+        loop {
+            self.draw_point(Vector2i::xy(x as i32, y as i32), color);
+            if x == bx && y == by {
+                break;
+            }
+            if 2 * error >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if 2 * error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Ends of the part of the segment from `a` to `b` within the pixel
+    /// centers of the image, rounded to pixels, by Liang-Barsky clipping.
+    /// `None` if the segment misses the image.
+    fn clip_line(&self, a: Vector2i, b: Vector2i) -> Option<((i64, i64), (i64, i64))> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let (x, y) = (a.x() as f64, a.y() as f64);
+        let (dx, dy) = (b.x() as f64 - x, b.y() as f64 - y);
+        let (max_x, max_y) = ((self.width - 1) as f64, (self.height - 1) as f64);
+        let (mut t_enter, mut t_exit) = (0.0_f64, 1.0_f64);
+        for (p, q) in [(-dx, x), (dx, max_x - x), (-dy, y), (dy, max_y - y)] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+            } else if p < 0.0 {
+                t_enter = t_enter.max(q / p);
+            } else {
+                t_exit = t_exit.min(q / p);
+            }
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+        let at = |t: f64| ((x + t * dx).round() as i64, (y + t * dy).round() as i64);
+        Some((at(t_enter), at(t_exit)))
+    }
+
+    /// Composites the image over an opaque `background` of the same size
+    /// with the Porter-Duff "over" operator, e.g. a render with a
+    /// transparent background over a photo. `alpha` holds the coverage of
+    /// every pixel, as in `RenderOutput::alpha`; colors are expected not to
+    /// be premultiplied.
+    pub fn over(&self, alpha: &[f32], background: &Image) -> Result<Image, Error> {
+        if (self.width, self.height) != (background.width, background.height) {
+            return Err(Error::SizeMismatch(format!(
+                "{}x{} image over {}x{} background",
+                self.width, self.height, background.width, background.height
+            )));
+        }
+        if alpha.len() != self.data.len() {
+            return Err(Error::SizeMismatch(format!(
+                "{} alpha values for {} pixels",
+                alpha.len(),
+                self.data.len()
+            )));
+        }
+        Ok(Image {
+            width: self.width,
+            height: self.height,
+            data: self
+                .data
+                .iter()
+                .zip(alpha)
+                .zip(&background.data)
+                .map(|((&color, &a), &behind)| color * a + behind * (1.0 - a))
+                .collect(),
+        })
+    }
+
+    /// Adds a glow around pixels brighter than `threshold`. Operates on the
+    /// linear HDR image, i.e. before any display transform.
+    pub fn bloom(&self, threshold: f32, radius: u32, intensity: f32) -> Image {
+        let bright = Image {
+            width: self.width,
+            height: self.height,
+            data: self
+                .data
+                .iter()
+                .map(|c| {
+                    if c.luminance() > threshold {
+                        *c
+                    } else {
+                        Color::rgb(0.0, 0.0, 0.0)
+                    }
+                })
+                .collect(),
+        };
+        let kernel = gaussian_kernel(radius);
+        let blurred = bright.convolve(&kernel, true).convolve(&kernel, false);
+        Image {
+            width: self.width,
+            height: self.height,
+            data: self
+                .data
+                .iter()
+                .zip(blurred.data.iter())
+                .map(|(c, glow)| *c + *glow * intensity)
+                .collect(),
+        }
+    }
+
+    /// Convolves with a 1D kernel of odd length along rows or columns,
+    /// clamping at the borders.
+    fn convolve(&self, kernel: &[f32], horizontal: bool) -> Image {
+        let radius = (kernel.len() / 2) as i64;
+        let mut result = Image::new(self.width, self.height);
+        for y in 0..self.height as i64 {
+            for x in 0..self.width as i64 {
+                let mut sum = Color::rgb(0.0, 0.0, 0.0);
+                for (k, weight) in kernel.iter().enumerate() {
+                    let offset = k as i64 - radius;
+                    let (sx, sy) = if horizontal {
+                        ((x + offset).clamp(0, self.width as i64 - 1), y)
+                    } else {
+                        (x, (y + offset).clamp(0, self.height as i64 - 1))
+                    };
+                    sum += self.data[(sy * self.width as i64 + sx) as usize] * *weight;
+                }
+                result.data[(y * self.width as i64 + x) as usize] = sum;
+            }
+        }
+        result
+    }
+
+    /// Writes the image as 8-bit binary PPM. Fails for filenames without a
+    /// `.ppm` extension.
+    pub fn save(&self, filename: &str) -> Result<(), Error> {
+        self.save_with_depth(filename, BitDepth::Eight)
+    }
+
+    /// Like `save` with `bit_depth` bits per channel.
+    pub fn save_with_depth(&self, filename: &str, bit_depth: BitDepth) -> Result<(), Error> {
+        if !filename.ends_with(".ppm") {
+            return Err(Error::UnsupportedFormat(filename.to_string()));
+        }
+        let mut file = File::create(filename)?;
+        Ok(file.write_all(&self.to_ppm(bit_depth))?)
+    }
+
+    /// Writes the image as 8-bit RGBA PNG, fully opaque if `alpha` is
+    /// `None`. Fails for filenames without a `.png` extension.
+    pub fn save_png(&self, filename: &str, alpha: Option<&[f32]>) -> Result<(), Error> {
+        if !filename.ends_with(".png") {
+            return Err(Error::UnsupportedFormat(filename.to_string()));
+        }
+        let mut file = File::create(filename)?;
+        Ok(file.write_all(&self.to_png(alpha))?)
+    }
+
+    /// Reads a binary PPM file, as written by `save`.
+    pub fn load_ppm(filename: &str) -> Result<Image, Error> {
+        Image::from_ppm(&fs::read(filename)?)
+    }
+
+    /// Decodes binary PPM with up to 16 bits per channel, mapping the
+    /// maximum value to 1.
+    pub fn from_ppm(bytes: &[u8]) -> Result<Image, Error> {
+        // Header fields are separated by whitespace and may be interleaved
+        // with comments, a single whitespace byte precedes the pixels.
+        let mut position = 0;
+        let mut line = 1;
+        let mut field = |name: &str| -> Result<(String, usize), Error> {
+            loop {
+                match bytes.get(position) {
+                    Some(b'#') => {
+                        while bytes.get(position).is_some_and(|&b| b != b'\n') {
+                            position += 1;
+                        }
+                    }
+                    Some(b) if b.is_ascii_whitespace() => {
+                        line += (*b == b'\n') as usize;
+                        position += 1;
+                    }
+                    Some(_) => break,
+                    None => {
+                        return Err(Error::Parse {
+                            line,
+                            msg: format!("missing {}", name),
+                        })
+                    }
+                }
+            }
+            let start = position;
+            while bytes
+                .get(position)
+                .is_some_and(|b| !b.is_ascii_whitespace() && *b != b'#')
+            {
+                position += 1;
+            }
+            let text = String::from_utf8_lossy(&bytes[start..position]).into_owned();
+            Ok((text, line))
+        };
+        let (magic, _) = field("magic number")?;
+        if magic != "P6" {
+            return Err(Error::UnsupportedFormat(format!(
+                "PPM magic number `{}`",
+                magic
+            )));
+        }
+        let mut number = |name: &str| -> Result<u32, Error> {
+            let (text, line) = field(name)?;
+            match text.parse() {
+                Ok(value) if value > 0 => Ok(value),
+                _ => Err(Error::Parse {
+                    line,
+                    msg: format!("invalid {} `{}`", name, text),
+                }),
+            }
+        };
+        let width = number("width")?;
+        let height = number("height")?;
+        let max_value = number("maximum value")?;
+        if max_value > 65535 {
+            return Err(Error::UnsupportedFormat(format!(
+                "PPM with maximum value {}",
+                max_value
+            )));
+        }
+        let pixels = &bytes[(position + 1).min(bytes.len())..];
+        // Big-endian pairs of bytes above 8 bits.
+        let bytes_per_value = if max_value > 255 { 2 } else { 1 };
+        let expected = width as usize * height as usize * 3 * bytes_per_value;
+        if pixels.len() < expected {
+            return Err(Error::Parse {
+                line,
+                msg: format!(
+                    "expected {} bytes of pixel data, found {}",
+                    expected,
+                    pixels.len()
+                ),
+            });
+        }
+        let scale = 1.0 / max_value as f32;
+        Ok(Image {
+            width,
+            height,
+            data: pixels[..expected]
+                .chunks_exact(3 * bytes_per_value)
+                .map(|rgb| {
+                    let value = |channel: usize| {
+                        let bytes = &rgb[channel * bytes_per_value..][..bytes_per_value];
+                        bytes.iter().fold(0, |value, &b| value << 8 | b as u32) as f32 * scale
+                    };
+                    Color::rgb(value(0), value(1), value(2))
+                })
+                .collect(),
+        })
+    }
+
+    /// Uncompressed PNG, colors are expected not to be premultiplied.
+    fn to_png(&self, alpha: Option<&[f32]>) -> Vec<u8> {
+        let mut scanlines = Vec::with_capacity(self.data.len() * 4 + self.height as usize);
+        for (i, color) in self.data.iter().enumerate() {
+            if i % self.width as usize == 0 {
+                // No filtering.
+                scanlines.push(0);
+            }
+            let a = alpha.map_or(1.0, |alpha| alpha[i]);
+            scanlines.extend([color.r(), color.g(), color.b(), a].map(to_byte));
+        }
+        let mut header = Vec::new();
+        header.extend(self.width.to_be_bytes());
+        header.extend(self.height.to_be_bytes());
+        // 8 bits per channel, RGBA, default compression, filtering and no
+        // interlacing.
+        header.extend([8, 6, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_png_chunk(&mut png, b"IHDR", &header);
+        write_png_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+        write_png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    fn to_ppm(&self, bit_depth: BitDepth) -> Vec<u8> {
+        let max_value = match bit_depth {
+            BitDepth::Eight => 255,
+            BitDepth::Sixteen => 65535,
+        };
+        let header = format!("P6 {} {} {} ", self.width, self.height, max_value);
+        let channels = self.data.iter().flat_map(|c| [c.r(), c.g(), c.b()]);
+        let data_bytes: Vec<u8> = match bit_depth {
+            BitDepth::Eight => channels.map(to_byte).collect(),
+            BitDepth::Sixteen => channels
+                .flat_map(|f| ((f.clamp(0.0, 1.0) * 65535.0).round() as u16).to_be_bytes())
+                .collect(),
+        };
         header
             .as_bytes()
             .iter()
@@ -43,6 +439,42 @@ impl Image {
     }
 }
 
+/// Running sum of images, e.g. renders from different seeds.
+pub struct Accumulator {
+    sum: Image,
+    count: u32,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            sum: Image::new(width, height),
+            count: 0,
+        }
+    }
+
+    pub fn add(&mut self, image: &Image) {
+        assert_eq!(image.data.len(), self.sum.data.len());
+        for (sum, color) in self.sum.data.iter_mut().zip(image.data.iter()) {
+            *sum += *color;
+        }
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn average(&self) -> Image {
+        let weight = 1.0 / self.count.max(1) as f32;
+        Image {
+            width: self.sum.width,
+            height: self.sum.height,
+            data: self.sum.data.iter().map(|color| *color * weight).collect(),
+        }
+    }
+}
+
 pub type Color = Vector3f;
 
 impl Color {
@@ -58,12 +490,128 @@ impl Color {
     pub fn b(&self) -> f32 {
         self.z()
     }
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r() + 0.7152 * self.g() + 0.0722 * self.b()
+    }
+    pub fn pow(&self, exponent: f32) -> Color {
+        Color::rgb(
+            self.r().powf(exponent),
+            self.g().powf(exponent),
+            self.b().powf(exponent),
+        )
+    }
+    /// Mixes the color toward its luminance, fully gray at `amount` = 1.
+    pub fn desaturate(&self, amount: f32) -> Color {
+        let gray = self.luminance();
+        *self * (1.0 - amount) + Color::rgb(gray, gray, gray) * amount
+    }
+}
+
+fn to_byte(f: f32) -> u8 {
+    (f.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn write_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// Zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 65535;
+    let mut stream = vec![0x78, 0x01];
+    let block_count = data.len().div_ceil(MAX_BLOCK).max(1);
+    for i in 0..block_count {
+        let block = &data[(i * MAX_BLOCK).min(data.len())..((i + 1) * MAX_BLOCK).min(data.len())];
+        stream.push((i + 1 == block_count) as u8);
+        stream.extend((block.len() as u16).to_le_bytes());
+        stream.extend((!(block.len() as u16)).to_le_bytes());
+        stream.extend(block);
+    }
+    let (a, b) = data.iter().fold((1_u32, 0_u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    stream.extend(((b << 16) | a).to_be_bytes());
+    stream
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
 }
 
 pub fn gamma_correct(color: Color) -> Color {
     Color::rgb(color.r().sqrt(), color.g().sqrt(), color.b().sqrt())
 }
 
+/// Compression of linear HDR colors before gamma correction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    /// No compression, values above 1 are clipped by the display.
+    Linear,
+    /// `x / (1 + x)` per channel, which shifts saturated colors towards
+    /// white.
+    Reinhard,
+    /// Reinhard applied to the luminance, with all channels scaled alike so
+    /// the hue is preserved.
+    LuminanceReinhard,
+}
+
+impl ToneMap {
+    pub fn apply(&self, color: Color) -> Color {
+        match self {
+            ToneMap::Linear => color,
+            ToneMap::Reinhard => Color::rgb(
+                color.r() / (1.0 + color.r()),
+                color.g() / (1.0 + color.g()),
+                color.b() / (1.0 + color.b()),
+            ),
+            ToneMap::LuminanceReinhard => {
+                let luminance = color.luminance();
+                if luminance <= 0.0 {
+                    return color;
+                }
+                color / (1.0 + luminance)
+            }
+        }
+    }
+}
+
+/// Normalized Gaussian weights for offsets -radius..=radius.
+fn gaussian_kernel(radius: u32) -> Vec<f32> {
+    let sigma = (radius as f32 / 2.0).max(0.5);
+    let weights: Vec<f32> = (-(radius as i32)..=radius as i32)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / sum).collect()
+}
+
+/// Maps `t` in [0, 1] onto a blue-green-red ramp.
+pub fn heat_color(t: f32) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    if t < 0.5 {
+        Color::rgb(0.0, 2.0 * t, 1.0 - 2.0 * t)
+    } else {
+        Color::rgb(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -76,14 +624,264 @@ mod tests {
         assert_eq!(img.data.len(), 10000);
     }
 
+    #[test]
+    fn test_flip_and_rotate() {
+        let mut img = Image::new(3, 2);
+        for (i, color) in img.data.iter_mut().enumerate() {
+            *color = Color::rgb(i as f32, 0.0, 0.0);
+        }
+        assert_eq!(img.flip_vertical().flip_vertical(), img);
+        assert_eq!(img.flip_horizontal().flip_horizontal(), img);
+        assert_eq!(img.flip_vertical().data[0].r(), 3.0);
+        assert_eq!(img.flip_horizontal().data[0].r(), 2.0);
+
+        let rotated = img.rotate_90();
+        assert_eq!((rotated.width, rotated.height), (2, 3));
+        // The bottom left corner moves to the top left.
+        assert_eq!(rotated.data[0].r(), 3.0);
+        assert_eq!(rotated.data[1].r(), 0.0);
+        assert_eq!(rotated.rotate_90().rotate_90().rotate_90(), img);
+    }
+
+    #[test]
+    fn test_filled_and_clear() {
+        let gray = Color::rgb(0.5, 0.5, 0.5);
+        let mut img = Image::filled(3, 2, gray);
+        assert_eq!(img.data.len(), 6);
+        assert!(img.data.iter().all(|color| *color == gray));
+        img.clear(Color::rgb(0.0, 0.0, 0.0));
+        assert_eq!(img, Image::new(3, 2));
+    }
+
+    #[test]
+    fn test_luminance_reinhard_preserves_hue() {
+        let red = Color::rgb(8.0, 0.5, 0.0);
+        let mapped = ToneMap::LuminanceReinhard.apply(red);
+        assert_approx!(mapped.g() / mapped.r(), 0.5 / 8.0, 1e-6);
+        assert_eq!(mapped.b(), 0.0);
+        assert!(mapped.luminance() < 1.0);
+        // Per channel the green channel is lifted relative to red.
+        let per_channel = ToneMap::Reinhard.apply(red);
+        assert!(per_channel.g() / per_channel.r() > 0.3);
+        assert_eq!(ToneMap::Linear.apply(red), red);
+    }
+
     #[test]
     fn test_to_ppm() {
         let img = Image::new(100, 100);
-        let ppm = img.to_ppm();
+        let ppm = img.to_ppm(BitDepth::Eight);
         let expected_header = "P6 100 100 255 ";
         let expected_num_bytes = img.width * img.height * 3 + expected_header.len() as u32;
         assert_eq!(ppm.len(), expected_num_bytes as usize);
         assert!(ppm.starts_with(expected_header.as_bytes()));
         assert!(ppm.ends_with(&[0, 0, 0][..]));
     }
+
+    #[test]
+    fn test_to_ppm_16_bit() {
+        let mut img = Image::new(2, 1);
+        img.data[1] = Color::rgb(1.0, 0.5, 1.0 / 65535.0);
+        let eight = img.to_ppm(BitDepth::Eight);
+        let sixteen = img.to_ppm(BitDepth::Sixteen);
+        let header = "P6 2 1 65535 ";
+        assert!(sixteen.starts_with(header.as_bytes()));
+        let data = &sixteen[header.len()..];
+        assert_eq!(data.len(), 2 * (eight.len() - "P6 2 1 255 ".len()));
+        assert_eq!(&data[6..], &[0xff, 0xff, 0x80, 0x00, 0x00, 0x01][..]);
+        let loaded = Image::from_ppm(&sixteen).unwrap();
+        assert_eq!(
+            loaded.data[1],
+            Color::rgb(1.0, 32768.0 / 65535.0, 1.0 / 65535.0)
+        );
+    }
+
+    #[test]
+    fn test_accumulator_average() {
+        let mut accumulator = Accumulator::new(2, 1);
+        let mut img = Image::new(2, 1);
+        img.data[0] = Color::rgb(1.0, 0.0, 0.0);
+        accumulator.add(&img);
+        accumulator.add(&Image::new(2, 1));
+        assert_eq!(accumulator.count(), 2);
+        assert_eq!(accumulator.average().data[0], Color::rgb(0.5, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_full_desaturation_is_luminance() {
+        let color = Color::rgb(0.9, 0.4, 0.1);
+        let gray = color.desaturate(1.0);
+        let tol = 1e-6;
+        assert_approx!(gray.r(), color.luminance(), tol);
+        assert_approx!(gray.g(), color.luminance(), tol);
+        assert_approx!(gray.b(), color.luminance(), tol);
+        assert_eq!(color.desaturate(0.0), color);
+    }
+
+    #[test]
+    fn test_saturate_and_pow() {
+        assert_eq!(
+            Color::rgb(-0.5, 0.5, 1.5).saturate(),
+            Color::rgb(0.0, 0.5, 1.0)
+        );
+        assert_eq!(
+            Color::rgb(0.25, 1.0, 4.0).pow(0.5),
+            Color::rgb(0.5, 1.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn test_bloom_spreads_bright_pixel() {
+        let mut img = Image::new(9, 9);
+        img.data[4 * 9 + 4] = Color::rgb(10.0, 10.0, 10.0);
+        img.data[0] = Color::rgb(0.5, 0.5, 0.5);
+        let bloomed = img.bloom(1.0, 2, 1.0);
+        let at = |x: usize, y: usize| bloomed.data[y * 9 + x].r();
+        assert!(at(4, 4) > 10.0);
+        assert!(at(3, 4) > 0.0 && at(4, 5) > 0.0 && at(5, 5) > 0.0);
+        assert!(at(3, 4) > at(2, 4));
+        assert_approx!(at(3, 4), at(5, 4), 1e-6);
+        assert_eq!(at(8, 8), 0.0);
+        assert_eq!(at(0, 0), 0.5);
+    }
+
+    #[test]
+    fn test_from_uniform_counts() {
+        let counts = vec![16; 12];
+        let img = Image::from_counts(4, 3, &counts, 32);
+        assert_eq!(img.data.len(), 12);
+        assert!(img.data.iter().all(|c| *c == img.data[0]));
+        assert_eq!(img.data[0], heat_color(0.5));
+    }
+
+    #[test]
+    fn test_save_rejects_bad_paths() {
+        let image = Image::new(1, 1);
+        let dir = std::env::temp_dir();
+        let wrong_extension = dir.join("image.png");
+        assert!(matches!(
+            image.save(wrong_extension.to_str().unwrap()),
+            Err(Error::UnsupportedFormat(_))
+        ));
+        let missing_dir = dir.join("does-not-exist").join("image.ppm");
+        assert!(image.save(missing_dir.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_ppm_round_trip() {
+        let mut image = Image::new(3, 2);
+        image.data[1] = Color::rgb(1.0, 0.2, 0.0);
+        let path = std::env::temp_dir().join("test_ppm_round_trip.ppm");
+        let path = path.to_str().unwrap();
+        image.save(path).unwrap();
+        let loaded = Image::load_ppm(path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert_eq!(loaded.data[0], Color::rgb(0.0, 0.0, 0.0));
+        assert_matrix_approx!(loaded.data[1], Color::rgb(1.0, 0.2, 0.0), 1e-6);
+        let commented = Image::from_ppm(b"P6\n# comment\n1 1\n255\n\xff\x00\x00").unwrap();
+        assert_eq!(commented.data, vec![Color::rgb(1.0, 0.0, 0.0)]);
+    }
+
+    #[test]
+    fn test_malformed_ppm_header() {
+        match Image::from_ppm(b"P6\n# size\n4 x4\n255\n") {
+            Err(Error::Parse { line, msg }) => {
+                assert_eq!(line, 3);
+                assert!(msg.contains("`x4`"), "{}", msg);
+            }
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+        assert!(matches!(
+            Image::from_ppm(b"P6 2 2 255 \x00"),
+            Err(Error::Parse { line: 1, .. })
+        ));
+        assert!(matches!(
+            Image::from_ppm(b"P3 1 1 255 0 0 0"),
+            Err(Error::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_sample_bilinear() {
+        let mut image = Image::new(2, 1);
+        image.data[1] = Color::rgb(1.0, 1.0, 1.0);
+        assert_eq!(image.sample_bilinear(0.0, 0.5), Color::rgb(0.0, 0.0, 0.0));
+        assert_eq!(image.sample_bilinear(0.5, 0.5), Color::rgb(0.5, 0.5, 0.5));
+        assert_eq!(image.sample_bilinear(1.0, 0.0), Color::rgb(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_draw_line() {
+        let white = Color::rgb(1.0, 1.0, 1.0);
+        let mut image = Image::new(6, 3);
+        image.draw_line(Vector2i::xy(1, 1), Vector2i::xy(4, 1), white);
+        let lit: Vec<usize> = (0..image.data.len())
+            .filter(|&i| image.data[i] == white)
+            .collect();
+        assert_eq!(lit, vec![7, 8, 9, 10]);
+
+        let mut image = Image::new(3, 3);
+        image.draw_line(Vector2i::xy(-1, -1), Vector2i::xy(5, 5), white);
+        let lit: Vec<usize> = (0..image.data.len())
+            .filter(|&i| image.data[i] == white)
+            .collect();
+        assert_eq!(lit, vec![0, 4, 8]);
+    }
+
+    #[test]
+    fn test_draw_line_with_far_off_screen_ends() {
+        let white = Color::rgb(1.0, 1.0, 1.0);
+        let lit = |image: &Image| -> Vec<usize> {
+            (0..image.data.len())
+                .filter(|&i| image.data[i] == white)
+                .collect()
+        };
+        let mut image = Image::new(4, 3);
+        image.draw_line(Vector2i::xy(i32::MIN, 1), Vector2i::xy(i32::MAX, 1), white);
+        assert_eq!(lit(&image), vec![4, 5, 6, 7]);
+
+        let mut image = Image::new(3, 3);
+        image.draw_line(
+            Vector2i::xy(-i32::MAX, -i32::MAX),
+            Vector2i::xy(i32::MAX, i32::MAX),
+            white,
+        );
+        assert_eq!(lit(&image), vec![0, 4, 8]);
+
+        let mut image = Image::new(3, 3);
+        image.draw_line(
+            Vector2i::xy(i32::MIN, i32::MAX),
+            Vector2i::xy(i32::MAX, i32::MAX),
+            white,
+        );
+        assert!(lit(&image).is_empty());
+    }
+
+    #[test]
+    fn test_over() {
+        let red = Image::filled(2, 1, Color::rgb(1.0, 0.0, 0.0));
+        let blue = Image::filled(2, 1, Color::rgb(0.0, 0.0, 1.0));
+        let composite = red.over(&[0.5, 0.0], &blue).unwrap();
+        assert_eq!(composite.data[0], Color::rgb(0.5, 0.0, 0.5));
+        assert_eq!(composite.data[1], Color::rgb(0.0, 0.0, 1.0));
+        let error = red.over(&[0.5, 0.0], &Image::new(1, 2)).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "size mismatch: 2x1 image over 1x2 background"
+        );
+        assert!(red.over(&[0.5], &blue).is_err());
+    }
+
+    #[test]
+    fn test_png_alpha() {
+        let mut image = Image::new(2, 1);
+        image.data[0] = Color::rgb(1.0, 0.0, 0.0);
+        let png = image.to_png(Some(&[1.0, 0.0]));
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        // Stored deflate blocks keep the scanline verbatim.
+        let scanline = [0, 255, 0, 0, 255, 0, 0, 0, 0];
+        assert!(png.windows(scanline.len()).any(|w| w == scanline));
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(&png[png.len() - 4..], 0xAE42_6082_u32.to_be_bytes());
+    }
 }
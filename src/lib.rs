@@ -0,0 +1,18 @@
+#[macro_use]
+mod approx;
+pub mod background;
+pub mod bvh;
+pub mod error;
+pub mod geometry;
+pub mod image;
+pub mod light;
+pub mod matrix;
+pub mod photon;
+pub mod renderer;
+pub mod rng;
+pub mod sampling;
+pub mod scene;
+pub mod sh;
+pub mod sky;
+pub mod spectrum;
+pub mod texture;
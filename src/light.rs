@@ -0,0 +1,214 @@
+use geometry::{get_area, Material, Object};
+use image::Color;
+use matrix::Vector3f;
+use rng::Rng;
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::FRAC_PI_2;
+
+/// Emissive objects that can be sampled directly, chosen proportional to
+/// their emitted power. Stores indices into the object list it was built
+/// from.
+pub struct LightList {
+    indices: Vec<usize>,
+    cdf: Vec<f32>,
+    /// Selection probability by object id.
+    probabilities: HashMap<u32, f32>,
+    /// Include and exclude sets of receiver ids by emitter object id.
+    links: HashMap<u32, (Option<HashSet<u32>>, HashSet<u32>)>,
+}
+
+impl LightList {
+    pub fn new(objects: &[Object]) -> Self {
+        let powers: Vec<(usize, f32)> = objects
+            .iter()
+            .enumerate()
+            .filter_map(|(i, object)| Some((i, get_power(object)?)))
+            .filter(|&(_, power)| power > 0.0)
+            .collect();
+        let total: f32 = powers.iter().map(|&(_, power)| power).sum();
+        let mut cumulative = 0.0;
+        let cdf = powers
+            .iter()
+            .map(|&(_, power)| {
+                cumulative += power / total;
+                cumulative
+            })
+            .collect();
+        LightList {
+            indices: powers.iter().map(|&(i, _)| i).collect(),
+            cdf,
+            probabilities: powers
+                .iter()
+                .map(|&(i, power)| (objects[i].id(), power / total))
+                .collect(),
+            links: HashMap::new(),
+        }
+    }
+
+    /// Restricts the direct light of the emitter `light` with `include`
+    /// and `exclude` sets of object ids, which work like those of a
+    /// `PointLight`.
+    pub fn link(&mut self, light: &Object, include: Option<HashSet<u32>>, exclude: HashSet<u32>) {
+        self.links.insert(light.id(), (include, exclude));
+    }
+
+    /// Whether the emitter `light` is linked to the object with `object_id`.
+    pub fn illuminates(&self, light: &Object, object_id: u32) -> bool {
+        self.links
+            .get(&light.id())
+            .is_none_or(|(include, exclude)| is_linked(include, exclude, object_id))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Picks a light, returning its object index and selection probability.
+    pub fn sample(&self, rng: &mut Rng) -> Option<(usize, f32)> {
+        if self.is_empty() {
+            return None;
+        }
+        let u = rng.uniform();
+        let i = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.indices.len() - 1);
+        let previous = if i == 0 { 0.0 } else { self.cdf[i - 1] };
+        Some((self.indices[i], self.cdf[i] - previous))
+    }
+
+    /// Probability with which `sample` picks `object`, 0 if it is no light.
+    pub fn probability(&self, object: &Object) -> f32 {
+        self.probabilities.get(&object.id()).cloned().unwrap_or(0.0)
+    }
+}
+
+/// Light without geometry, invisible to camera and BSDF rays. Shadow rays
+/// are aimed at the disk of `radius` facing the shading point, which softens
+/// the shadows cast by it. A radius of 0 is an ideal point light.
+pub struct PointLight {
+    pub position: Vector3f,
+    pub radius: f32,
+    /// Emitted power per solid angle.
+    pub intensity: Color,
+    /// Ids of the only objects lit by the light, all if `None`.
+    pub include: Option<HashSet<u32>>,
+    /// Ids of objects not lit by the light, taking precedence over
+    /// `include`. Excluded objects still cast shadows.
+    pub exclude: HashSet<u32>,
+}
+
+impl PointLight {
+    /// Whether the light is linked to the object with `object_id`.
+    pub fn illuminates(&self, object_id: u32) -> bool {
+        is_linked(&self.include, &self.exclude, object_id)
+    }
+
+    /// Uniformly distributed point on the disk of the light facing `point`.
+    pub fn sample_position(&self, point: &Vector3f, rng: &mut Rng) -> Vector3f {
+        if self.radius <= 0.0 {
+            return self.position;
+        }
+        let axis = (self.position - *point).normalized();
+        let (tangent, bitangent) = axis.orthonormal_basis();
+        let disk = rng.unit_disk_concentric() * self.radius;
+        self.position + tangent * disk.x() + bitangent * disk.y()
+    }
+}
+
+fn is_linked(include: &Option<HashSet<u32>>, exclude: &HashSet<u32>, object_id: u32) -> bool {
+    !exclude.contains(&object_id)
+        && include
+            .as_ref()
+            .is_none_or(|include| include.contains(&object_id))
+}
+
+/// Total emitted power up to a constant factor. Emission restricted to a
+/// cone of half angle theta carries sin^2(theta) of the hemispherical power.
+fn get_power(object: &Object) -> Option<f32> {
+    match object.material.as_ref() {
+        Material::Emissive {
+            radiance,
+            emit_cone_angle,
+        } => {
+            let cone = emit_cone_angle.map_or(1.0, |angle| angle.min(FRAC_PI_2).sin().powi(2));
+            Some(radiance.luminance() * get_area(&object.surface)? * cone)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::Surface;
+
+    fn light(radiance: f32) -> Object {
+        Object::new(
+            Surface::Sphere {
+                center: Vector3f::zeros(),
+                radius: 1.0,
+            },
+            Material::Emissive {
+                radiance: Color::rgb(radiance, radiance, radiance),
+                emit_cone_angle: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_brighter_light_is_sampled_more_often() {
+        let objects = vec![light(1.0), light(10.0)];
+        let lights = LightList::new(&objects);
+        let mut rng = Rng::new(0);
+        let mut counts = [0; 2];
+        for _ in 0..110_000 {
+            let (index, probability) = lights.sample(&mut rng).unwrap();
+            counts[index] += 1;
+            assert_approx!(probability, [1.0 / 11.0, 10.0 / 11.0][index], 1e-5);
+        }
+        let ratio = counts[1] as f32 / counts[0] as f32;
+        assert!((ratio - 10.0).abs() < 0.5, "ratio {}", ratio);
+        assert_approx!(lights.probability(&objects[1]), 10.0 / 11.0, 1e-5);
+    }
+
+    #[test]
+    fn test_point_light_samples_lie_on_facing_disk() {
+        let light = PointLight {
+            position: Vector3f::xyz(0.0, 2.0, 0.0),
+            radius: 0.5,
+            intensity: Color::rgb(1.0, 1.0, 1.0),
+            include: None,
+            exclude: HashSet::new(),
+        };
+        let point = Vector3f::xyz(1.0, 0.0, 3.0);
+        let axis = light.position - point;
+        let mut rng = Rng::new(4);
+        for _ in 0..100 {
+            let offset = light.sample_position(&point, &mut rng) - light.position;
+            assert!(offset.length() <= 0.5 + 1e-5);
+            assert_approx!(offset.dot(&axis), 0.0, 1e-5);
+        }
+        let hard = PointLight {
+            radius: 0.0,
+            ..light
+        };
+        assert_eq!(hard.sample_position(&point, &mut rng), hard.position);
+    }
+
+    #[test]
+    fn test_non_emitters_are_not_lights() {
+        let diffuse = Object::new(
+            Surface::Sphere {
+                center: Vector3f::zeros(),
+                radius: 1.0,
+            },
+            Material::Mirror {
+                tint: Color::rgb(1.0, 1.0, 1.0),
+            },
+        );
+        let lights = LightList::new(&[diffuse]);
+        assert!(lights.is_empty());
+        assert!(lights.sample(&mut Rng::new(0)).is_none());
+    }
+}
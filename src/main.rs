@@ -1,63 +1,243 @@
 #[macro_use]
-mod approx;
-mod geometry;
-mod image;
-mod matrix;
-mod renderer;
-mod rng;
-
-use geometry::{Camera, Material, Object, Surface};
-use matrix::{Vector2i, Vector3f};
-use renderer::Renderer;
+extern crate cpu_path_tracer;
+
+use cpu_path_tracer::background::Background;
+use cpu_path_tracer::bvh::{Accel, Bvh, UniformGrid};
+use cpu_path_tracer::error::Error;
+use cpu_path_tracer::geometry::{Camera, FovAxis};
+use cpu_path_tracer::image::ToneMap;
+use cpu_path_tracer::light::LightList;
+use cpu_path_tracer::matrix::{Vector2i, Vector3f};
+use cpu_path_tracer::renderer::{RenderMode, Renderer, TerminationColor, TileOrder};
+use cpu_path_tracer::sampling::{PixelFilter, SamplePattern};
+use cpu_path_tracer::scene::{MaterialLibrary, Scene};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Instant;
 
+const USAGE: &str = "Usage: cpu_path_tracer [--width N] [--height N] [--spp N] \
+                     [--max-depth N] [--max-diffuse-bounces N] [--max-specular-bounces N] \
+                     [--scene FILE] [--out FILE.ppm|FILE.png] [--seeds N] \
+                     [--threads N] [--photons N] [--accel bvh|grid] [--stats]";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    width: i32,
+    height: i32,
+    samples_per_pixel: u32,
+    max_depth: u32,
+    max_diffuse_bounces: u32,
+    max_specular_bounces: u32,
+    scene: Option<String>,
+    out: String,
+    /// Number of independently seeded renders averaged, at least 1. Each
+    /// adds `samples_per_pixel` samples.
+    seeds: u32,
+    /// Threads building the BVH and rendering the seeds, at least 1.
+    threads: usize,
+    /// Caustic photons per render, 0 disables the photon map.
+    photons: u32,
+    /// Use a uniform grid instead of a BVH.
+    grid: bool,
+    /// Print scene statistics before rendering.
+    stats: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            width: 326,
+            height: 256,
+            samples_per_pixel: 25,
+            max_depth: 50,
+            max_diffuse_bounces: 25,
+            max_specular_bounces: 50,
+            scene: None,
+            out: "test.ppm".to_string(),
+            seeds: 8,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            photons: 0,
+            grid: false,
+            stats: false,
+        }
+    }
+}
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    fn number<T: std::str::FromStr>(flag: &str, value: String) -> Result<T, String> {
+        value
+            .parse()
+            .map_err(|_| format!("invalid value `{}` for {}", value, flag))
+    }
+    fn positive<T: std::str::FromStr + PartialOrd + Default>(
+        flag: &str,
+        value: String,
+    ) -> Result<T, String> {
+        let error = format!("invalid value `{}` for {}", value, flag);
+        match number(flag, value)? {
+            n if n > T::default() => Ok(n),
+            _ => Err(error),
+        }
+    }
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        if flag == "--stats" {
+            options.stats = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--width" => options.width = positive(&flag, value)?,
+            "--height" => options.height = positive(&flag, value)?,
+            "--spp" => options.samples_per_pixel = number(&flag, value)?,
+            "--max-depth" => options.max_depth = number(&flag, value)?,
+            "--max-diffuse-bounces" => options.max_diffuse_bounces = number(&flag, value)?,
+            "--max-specular-bounces" => options.max_specular_bounces = number(&flag, value)?,
+            "--seeds" => options.seeds = positive(&flag, value)?,
+            "--threads" => options.threads = positive(&flag, value)?,
+            "--photons" => options.photons = number(&flag, value)?,
+            "--scene" => options.scene = Some(value),
+            "--out" => options.out = value,
+            "--accel" => {
+                options.grid = match value.as_str() {
+                    "bvh" => false,
+                    "grid" => true,
+                    _ => return Err(format!("invalid value `{}` for {}", value, flag)),
+                }
+            }
+            _ => return Err(format!("unknown argument {}", flag)),
+        }
+    }
+    Ok(options)
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(2);
+}
+
 fn main() {
+    let options = parse_args(std::env::args().skip(1))
+        .unwrap_or_else(|error| exit_with(&format!("{}\n{}", error, USAGE)));
     let now = Instant::now();
     let camera = Camera::new(
         Vector3f::xyz(0.0, 0.0, -1.0),
         Vector3f::xyz(0.0, 1.0, 0.0),
+        0.0,
         100_f32.to_radians(),
-        Vector2i::xy(326, 256),
+        FovAxis::Horizontal,
+        Vector2i::xy(options.width, options.height),
     );
-    let objects: Vec<Object> = vec![
-        Object {
-            surface: Surface::Sphere {
-                center: Vector3f::xyz(0.0, 0.0, -1.0),
-                radius: 0.5,
-            },
-            material: Material::Lambertian {
-                albedo: Vector3f::rgb(0.1, 0.2, 0.5),
-            },
+    let scene = match &options.scene {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(Error::from)
+            .and_then(|text| {
+                if path.ends_with(".obj") {
+                    Scene::parse_obj(&text, &MaterialLibrary::default())
+                } else {
+                    Scene::parse(&text)
+                }
+            })
+            .unwrap_or_else(|error| exit_with(&format!("Failed to load {}: {}", path, error))),
+        None => scene! {
+            sphere(0, 0, -1; 0.5) lambertian(0.1, 0.2, 0.5)
+            sphere(0.8, -0.4, -1; 0.3) lambertian(0.1, 0.9, 0.5)
+            sphere(0, -20.5, 0; 20) lambertian(0.5, 0.2, 0.5)
         },
-        Object {
-            surface: Surface::Sphere {
-                center: Vector3f::xyz(0.8, -0.4, -1.0),
-                radius: 0.3,
-            },
-            material: Material::Lambertian {
-                albedo: Vector3f::rgb(0.1, 0.9, 0.5),
-            },
-        },
-        Object {
-            surface: Surface::Sphere {
-                center: Vector3f::xyz(0.0, -20.5, 0.0),
-                radius: 20.0,
-            },
-            material: Material::Lambertian {
-                albedo: Vector3f::rgb(0.5, 0.2, 0.5),
-            },
-        },
-    ];
+    };
+    if options.stats {
+        println!("Scene: {}", scene.stats());
+    }
+    let accel = if options.grid {
+        Accel::Grid(UniformGrid::new(&scene.objects))
+    } else {
+        Accel::Bvh(Bvh::new_parallel(&scene.objects, options.threads))
+    };
     let renderer = Renderer {
         camera,
-        objects,
-        ambient_light_color: Vector3f::rgb(1.0, 1.0, 1.0),
-        max_depth: 25,
-        samples_per_pixel: 25,
+        lights: LightList::new(&scene.objects),
+        point_lights: vec![],
+        shadow_samples: 1,
+        scene,
+        accel,
+        background: Background::Uniform(Vector3f::rgb(1.0, 1.0, 1.0)),
+        ambient_intensity: 1.0,
+        tone_map: ToneMap::Linear,
+        max_depth: options.max_depth,
+        max_diffuse_bounces: options.max_diffuse_bounces,
+        max_specular_bounces: options.max_specular_bounces,
+        rr_min_bounces: 3,
+        termination_color: TerminationColor::Black,
+        samples_per_pixel: options.samples_per_pixel,
+        sample_pattern: SamplePattern::CorrelatedMultiJitter,
+        pixel_filter: PixelFilter::Tent,
+        render_mode: RenderMode::Uniform,
+        tile_order: TileOrder::Scanline,
+        spectral: false,
+        photon_pass: options.photons,
+        photon_radius: 0.05,
+        reflection_probe: None,
+        max_rays: None,
+        rays_traced: AtomicU64::new(0),
+    };
+    let seeds: Vec<u32> = (1..=options.seeds).collect();
+    let img = renderer.average_render_with_threads(&seeds, options.threads);
+    let saved = if options.out.ends_with(".png") {
+        img.save_png(&options.out, None)
+    } else {
+        img.save(&options.out)
     };
-    let seeds = vec![1, 2, 3, 4, 5, 6, 7, 8];
-    let img = renderer.average_render(&seeds);
-    img.save("test.ppm");
+    if let Err(error) = saved {
+        eprintln!("Failed to save {}: {}", options.out, error);
+        std::process::exit(1);
+    }
     let elapsed = now.elapsed();
     println!("Elapsed: {}ms", elapsed.as_millis());
+    println!("Rays: {}", renderer.rays_traced.load(Ordering::Relaxed));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(args(
+            "--width 64 --spp 4 --scene room.txt --stats --out room.png --seeds 2 --threads 3 --photons 1000 --accel grid \
+             --max-diffuse-bounces 5 --max-specular-bounces 60",
+        ))
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                width: 64,
+                samples_per_pixel: 4,
+                scene: Some("room.txt".to_string()),
+                out: "room.png".to_string(),
+                seeds: 2,
+                threads: 3,
+                photons: 1000,
+                grid: true,
+                stats: true,
+                max_diffuse_bounces: 5,
+                max_specular_bounces: 60,
+                ..Options::default()
+            }
+        );
+        assert_eq!(parse_args(args("")).unwrap(), Options::default());
+        assert!(parse_args(args("--spp many")).is_err());
+        assert!(parse_args(args("--height")).is_err());
+        assert!(parse_args(args("--bogus 1")).is_err());
+        assert!(parse_args(args("--accel octree")).is_err());
+        assert!(parse_args(args("--seeds 0")).is_err());
+        assert!(parse_args(args("--threads 0")).is_err());
+        assert!(parse_args(args("--width 0")).is_err());
+        assert!(parse_args(args("--height -4")).is_err());
+    }
 }
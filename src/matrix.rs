@@ -40,6 +40,8 @@ pub type Vector2i = Vector<i32, 2>;
 pub type Vector2f = Vector<f32, 2>;
 pub type Vector3f = Vector<f32, 3>;
 pub type Vector4f = Vector<f32, 4>;
+pub type Matrix3f = Matrix<f32, 3, 3>;
+pub type Matrix4f = Matrix<f32, 4, 4>;
 
 // Generic matrix operations
 impl<T: Numeric, const R: usize, const C: usize> Matrix<T, R, C> {
@@ -53,11 +55,31 @@ impl<T: Numeric, const R: usize, const C: usize> Matrix<T, R, C> {
         let mut result = Matrix::<T, C, R>::zeros();
         for i in 0..R {
             for j in 0..C {
-                result.data[i][j] = self.data[j][i];
+                result.data[j][i] = self.data[i][j];
             }
         }
         result
     }
+    /// Builds a matrix from elements in row-major order. Returns `None` unless
+    /// the iterator yields exactly R * C elements.
+    pub fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Option<Self> {
+        let mut result = Self::zeros();
+        let mut iter = iter.into_iter();
+        for element in result.iter_mut() {
+            *element = iter.next()?;
+        }
+        match iter.next() {
+            Some(_) => None,
+            None => Some(result),
+        }
+    }
+    /// Iterates over the elements in row-major order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.data.iter().flatten()
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.data.iter_mut().flatten()
+    }
     pub fn at(&self, row: usize, col: usize) -> &T {
         &self.data[row][col]
     }
@@ -174,7 +196,7 @@ impl<T: Numeric, const R: usize, const C: usize> Sub<T> for Matrix<T, R, C> {
     type Output = Matrix<T, R, C>;
 
     fn sub(mut self, rhs: T) -> Matrix<T, R, C> {
-        self.data.iter_mut().flatten().for_each(|x| *x += rhs);
+        self.data.iter_mut().flatten().for_each(|x| *x -= rhs);
         self
     }
 }
@@ -208,6 +230,46 @@ impl<T: Numeric, const S: usize> Matrix<T, S, S> {
     }
 }
 
+impl<const S: usize> Matrix<f32, S, S> {
+    /// Reflection across the hyperplane perpendicular to `v`,
+    /// `I - 2 v v^T / (v^T v)`. `v` need not be normalized.
+    pub fn householder(v: &Vector<f32, S>) -> Self {
+        Self::identity() - v.outer(v) * (2.0 / v.dot(v))
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting. Returns `None` for
+    /// singular matrices.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut m = *self;
+        let mut result = Self::identity();
+        for col in 0..S {
+            let pivot =
+                (col..S).max_by(|&a, &b| m.data[a][col].abs().total_cmp(&m.data[b][col].abs()))?;
+            if m.data[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            m.data.swap(col, pivot);
+            result.data.swap(col, pivot);
+            let scale = 1.0 / m.data[col][col];
+            for j in 0..S {
+                m.data[col][j] *= scale;
+                result.data[col][j] *= scale;
+            }
+            for row in 0..S {
+                if row == col {
+                    continue;
+                }
+                let factor = m.data[row][col];
+                for j in 0..S {
+                    m.data[row][j] -= factor * m.data[col][j];
+                    result.data[row][j] -= factor * result.data[col][j];
+                }
+            }
+        }
+        Some(result)
+    }
+}
+
 // Column vector operations
 impl<T: Numeric, const R: usize> Matrix<T, R, 1> {
     pub fn dot(&self, rhs: &Matrix<T, R, 1>) -> T {
@@ -217,6 +279,33 @@ impl<T: Numeric, const R: usize> Matrix<T, R, 1> {
         }
         result
     }
+    pub fn to_array(&self) -> [T; R] {
+        self.data.map(|[x]| x)
+    }
+    /// The matrix product `self * rhs^T`.
+    pub fn outer<const C: usize>(&self, rhs: &Vector<T, C>) -> Matrix<T, R, C> {
+        self.mat_mul(&rhs.transpose())
+    }
+}
+
+impl<T: Numeric, const R: usize> From<[T; R]> for Vector<T, R> {
+    fn from(array: [T; R]) -> Self {
+        Self::new(array.map(|x| [x]))
+    }
+}
+
+// Arrays are foreign, so the conversion back is only implemented for the
+// concrete element types.
+impl<const R: usize> From<Vector<f32, R>> for [f32; R] {
+    fn from(vector: Vector<f32, R>) -> Self {
+        vector.to_array()
+    }
+}
+
+impl<const R: usize> From<Vector<i32, R>> for [i32; R] {
+    fn from(vector: Vector<i32, R>) -> Self {
+        vector.to_array()
+    }
 }
 
 impl<T: Numeric> Vector<T, 3> {
@@ -239,6 +328,9 @@ impl<T: Numeric> Vector<T, 3> {
     pub fn z(&self) -> T {
         self.data[2][0]
     }
+    pub fn reflect(&self, normal: &Self) -> Self {
+        *self - *normal * (T::from(2) * self.dot(normal))
+    }
     pub fn homogeneous(&self) -> Vector<T, 4> {
         let mut result = Vector::zeros();
         for i in 0..3 {
@@ -263,6 +355,15 @@ impl<T: Numeric> Vector<T, 2> {
 
 // f32 specific operations
 
+/// Approximates `1.0 / x.sqrt()` with the bit trick from Quake III followed by
+/// one Newton iteration. The relative error is below 0.2% for positive,
+/// normal `x`.
+pub fn fast_inverse_sqrt(x: f32) -> f32 {
+    let i = 0x5f37_59df - (x.to_bits() >> 1);
+    let y = f32::from_bits(i);
+    y * (1.5 - 0.5 * x * y * y)
+}
+
 impl Vector4f {
     pub fn hnormalized(&self) -> Vector3f {
         let mut result = Matrix::zeros();
@@ -273,6 +374,67 @@ impl Vector4f {
     }
 }
 
+impl Vector3f {
+    /// Polar angle `theta` from the +y axis and azimuth `phi` in [-pi, pi],
+    /// measured from -z towards +x.
+    pub fn to_spherical(&self) -> (f32, f32) {
+        let phi = self.x().atan2(-self.z());
+        let theta = (self.y() / self.length()).clamp(-1.0, 1.0).acos();
+        (theta, phi)
+    }
+    /// Unit direction for the angles returned by `to_spherical`.
+    pub fn from_spherical(theta: f32, phi: f32) -> Self {
+        Vector3f::xyz(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+    /// Tangent and bitangent completing the unit vector `self` to a right
+    /// handed orthonormal basis, without a special case for any direction
+    /// (Duff et al., "Building an Orthonormal Basis, Revisited", 2017).
+    pub fn orthonormal_basis(&self) -> (Vector3f, Vector3f) {
+        let (x, y, z) = (self.x(), self.y(), self.z());
+        let sign = 1.0_f32.copysign(z);
+        let a = -1.0 / (sign + z);
+        let b = x * y * a;
+        (
+            Vector3f::xyz(1.0 + sign * x * x * a, sign * b, -sign * x),
+            Vector3f::xyz(b, sign + y * y * a, -y),
+        )
+    }
+}
+
+impl Matrix4f {
+    pub fn transform_point(&self, p: &Vector3f) -> Vector3f {
+        self.mat_mul(&p.homogeneous()).hnormalized()
+    }
+    pub fn transform_direction(&self, d: &Vector3f) -> Vector3f {
+        let mut result = Vector3f::zeros();
+        for i in 0..3 {
+            for j in 0..3 {
+                result.data[i][0] += self.data[i][j] * d.data[j][0];
+            }
+        }
+        result
+    }
+    /// Upper-left 3x3 block: rotation, scale and shear without translation.
+    pub fn linear_part(&self) -> Matrix3f {
+        let mut result = Matrix3f::zeros();
+        for i in 0..3 {
+            for j in 0..3 {
+                result.data[i][j] = self.data[i][j];
+            }
+        }
+        result
+    }
+    /// Inverse transpose of the linear part, which keeps normals
+    /// perpendicular to transformed surfaces. `None` if it is singular.
+    pub fn normal_matrix(&self) -> Option<Matrix3f> {
+        Some(self.linear_part().inverse()?.transpose())
+    }
+}
+
 impl<const R: usize> Matrix<f32, R, 1> {
     pub fn length(&self) -> f32 {
         self.dot(self).sqrt()
@@ -289,22 +451,66 @@ impl<const R: usize> Matrix<f32, R, 1> {
     pub fn normalized(&self) -> Self {
         *self / self.length()
     }
+    /// Less accurate but faster `normalized`, see `fast_inverse_sqrt`.
+    pub fn fast_normalized(&self) -> Self {
+        *self * fast_inverse_sqrt(self.squared_length())
+    }
     pub fn cos_angle(&self, rhs: &Self) -> f32 {
         self.dot(rhs) / (self.length() * rhs.length())
     }
-    pub fn clamp(&self, min: f32, max: f32) -> Self {
+    pub fn component_min(&self, rhs: &Self) -> Self {
+        let mut result = Matrix::zeros();
+        for i in 0..R {
+            result.data[i][0] = self.data[i][0].min(rhs.data[i][0]);
+        }
+        result
+    }
+    pub fn component_max(&self, rhs: &Self) -> Self {
         let mut result = Matrix::zeros();
         for i in 0..R {
-            result.data[i][0] = self.data[i][0].max(min).min(max);
+            result.data[i][0] = self.data[i][0].max(rhs.data[i][0]);
         }
         result
     }
 }
 
+impl<const R: usize, const C: usize> Matrix<f32, R, C> {
+    /// Element-wise clamp, also used for vectors.
+    pub fn clamp(&self, min: f32, max: f32) -> Self {
+        let mut result = *self;
+        result.iter_mut().for_each(|e| *e = e.max(min).min(max));
+        result
+    }
+    /// Replaces NaN and infinite elements by 0, e.g. before summing
+    /// samples.
+    pub fn sanitize(&self) -> Self {
+        let mut result = *self;
+        result
+            .iter_mut()
+            .filter(|e| !e.is_finite())
+            .for_each(|e| *e = 0.0);
+        result
+    }
+    /// Element-wise clamp to [0, 1].
+    pub fn saturate(&self) -> Self {
+        self.clamp(0.0, 1.0)
+    }
+    /// Whether all elements differ by at most `tolerance`.
+    pub fn approx_eq(&self, other: &Self, tolerance: f32) -> bool {
+        self.iter()
+            .zip(other.iter())
+            .all(|(a, b)| (a - b).abs() <= tolerance)
+    }
+    /// Element-wise linear interpolation, `t = 0` yields `self`.
+    pub fn lerp(&self, rhs: &Self, t: f32) -> Self {
+        *self * (1.0 - t) + *rhs * t
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    pub type Matrix4f = Matrix<f32, 4, 4>;
+    use std::f32::consts::PI;
 
     #[test]
     fn test_multiply_by_scalar() {
@@ -391,9 +597,27 @@ mod test {
     #[test]
     fn test_normalized() {
         let a = Vector3f::xyz(1.0, 2.0, 3.0);
-        let expected = Vector3f::xyz(0.26726124, 0.5345225, 0.8017837);
-        assert_eq!(a.normalized(), expected);
+        let expected = Vector3f::xyz(0.267261, 0.534522, 0.801784);
+        assert_matrix_approx!(a.normalized(), expected, 1e-6);
     }
+    #[test]
+    fn test_fast_inverse_sqrt_relative_error() {
+        let mut x = 1e-6_f32;
+        while x < 1e6 {
+            let exact = 1.0 / x.sqrt();
+            let relative_error = ((fast_inverse_sqrt(x) - exact) / exact).abs();
+            assert!(relative_error < 2e-3, "x = {}", x);
+            x *= 1.01;
+        }
+    }
+
+    #[test]
+    fn test_fast_normalized() {
+        let a = Vector3f::xyz(1.0, 2.0, 3.0);
+        assert_approx!(a.fast_normalized().length(), 1.0, 2e-3);
+        assert_approx!(a.fast_normalized().cos_angle(&a), 1.0, 1e-6);
+    }
+
     #[test]
     fn test_multiply4x4_with_vector3() {
         let m = Matrix4f::new([
@@ -412,6 +636,37 @@ mod test {
         assert_approx!(result.z(), expected.z(), tol);
     }
 
+    #[test]
+    fn test_transform_point() {
+        let m = Matrix4f::new([
+            [1.0, 2.0, 3.0, 4.0],
+            [5.0, 6.0, 7.0, 8.0],
+            [9.0, 10.0, 11.0, 12.0],
+            [13.0, 14.0, 15.0, 16.0],
+        ]);
+        let v = Vector3f::xyz(1.0, 2.0, 3.0);
+        let w = 102.0;
+        let expected = Vector3f::xyz(18.0 / w, 46.0 / w, 74.0 / w);
+        let result = m.transform_point(&v);
+        let tol = 1e-6;
+        assert_approx!(result.x(), expected.x(), tol);
+        assert_approx!(result.y(), expected.y(), tol);
+        assert_approx!(result.z(), expected.z(), tol);
+    }
+
+    #[test]
+    fn test_transform_direction_ignores_translation() {
+        let m = Matrix4f::new([
+            [1.0, 2.0, 3.0, 4.0],
+            [5.0, 6.0, 7.0, 8.0],
+            [9.0, 10.0, 11.0, 12.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let v = Vector3f::xyz(1.0, 2.0, 3.0);
+        let expected = Vector3f::xyz(14.0, 38.0, 62.0);
+        assert_eq!(m.transform_direction(&v), expected);
+    }
+
     #[test]
     fn test_add_matrices() {
         let a = Matrix4f::new([
@@ -450,4 +705,179 @@ mod test {
         let expected = Vector3f::xyz(-3.0, -3.0, -3.0);
         assert_eq!(a - b, expected);
     }
+
+    #[test]
+    fn test_subtract_scalar() {
+        let a = Vector3f::xyz(1.0, 2.0, 3.0);
+        assert_eq!(a - 1.0, Vector3f::xyz(0.0, 1.0, 2.0));
+    }
+
+    #[test]
+    fn test_iter_round_trip() {
+        let m = Matrix::<f32, 3, 3>::new([[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]]);
+        let elements: Vec<f32> = m.iter().cloned().collect();
+        assert_eq!(elements, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        assert_eq!(Matrix::<f32, 3, 3>::from_iter(elements), Some(m));
+    }
+
+    #[test]
+    fn test_from_iter_length_mismatch() {
+        assert_eq!(Matrix::<f32, 2, 2>::from_iter(vec![1.0, 2.0, 3.0]), None);
+        assert_eq!(
+            Matrix::<f32, 2, 2>::from_iter(vec![1.0, 2.0, 3.0, 4.0, 5.0]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut m = Matrix::<i32, 2, 2>::new([[1, 2], [3, 4]]);
+        m.iter_mut().for_each(|x| *x *= 10);
+        assert_eq!(m, Matrix::new([[10, 20], [30, 40]]));
+    }
+
+    #[test]
+    fn test_reflect() {
+        let v = Vector3f::xyz(1.0, -2.0, 3.0);
+        let normal = Vector3f::xyz(0.0, 1.0, 0.0);
+        assert_eq!(v.reflect(&normal), Vector3f::xyz(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn test_outer() {
+        let outer = Vector2f::xy(1.0, 2.0).outer(&Vector3f::xyz(3.0, 4.0, 5.0));
+        assert_eq!(outer, Matrix::new([[3.0, 4.0, 5.0], [6.0, 8.0, 10.0]]));
+    }
+
+    #[test]
+    fn test_householder() {
+        let v = Vector3f::xyz(0.0, 2.0, 0.0);
+        let h = Matrix3f::householder(&v);
+        let p = Vector3f::xyz(1.0, -2.0, 3.0);
+        assert_eq!(h.mat_mul(&p), p.reflect(&v.normalized()));
+        let v = Vector3f::xyz(1.0, 1.0, -2.0);
+        let h = Matrix3f::householder(&v);
+        // Flips the component along `v` and keeps the perpendicular ones.
+        assert_matrix_approx!(h.mat_mul(&v), v * -1.0, 1e-6);
+        let perpendicular = Vector3f::xyz(1.0, 1.0, 1.0);
+        assert_matrix_approx!(h.mat_mul(&perpendicular), perpendicular, 1e-6);
+        assert_matrix_approx!(h.mat_mul(&h), Matrix3f::identity(), 1e-6);
+    }
+
+    #[test]
+    fn test_orthonormal_basis() {
+        let normals = [
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            Vector3f::xyz(0.0, -1.0, 0.0),
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(1e-7, 0.0, -1.0).normalized(),
+            Vector3f::xyz(1.0, 2.0, 3.0).normalized(),
+            Vector3f::xyz(-0.3, 0.9, -0.1).normalized(),
+        ];
+        for normal in normals {
+            let (tangent, bitangent) = normal.orthonormal_basis();
+            assert_approx!(tangent.length(), 1.0, 1e-6);
+            assert_approx!(bitangent.length(), 1.0, 1e-6);
+            assert_approx!(tangent.dot(&bitangent), 0.0, 1e-6);
+            assert_approx!(tangent.dot(&normal), 0.0, 1e-6);
+            assert_approx!(bitangent.dot(&normal), 0.0, 1e-6);
+            assert_matrix_approx!(tangent.cross(&bitangent), normal, 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_clamp_matrix() {
+        let m = Matrix::new([[-2.0, 0.5], [3.0, 1.0]]);
+        assert_eq!(m.clamp(-1.0, 2.0), Matrix::new([[-1.0, 0.5], [2.0, 1.0]]));
+        assert_eq!(m.saturate(), Matrix::new([[0.0, 0.5], [1.0, 1.0]]));
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let v = Vector3f::xyz(f32::NAN, 2.0, f32::NEG_INFINITY);
+        assert_eq!(v.sanitize().clamp(0.0, 1.0), Vector3f::xyz(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_lerp_matrix() {
+        let a = Matrix::new([[0.0, 2.0], [4.0, -4.0]]);
+        let b = Matrix::new([[2.0, 2.0], [0.0, 4.0]]);
+        assert_eq!(a.lerp(&b, 0.0), a);
+        assert_eq!(a.lerp(&b, 0.5), Matrix::new([[1.0, 2.0], [2.0, 0.0]]));
+    }
+
+    #[test]
+    fn test_inverse() {
+        let m = Matrix::new([[0.0, 2.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 4.0]]);
+        let inverse = m.inverse().unwrap();
+        assert_matrix_approx!(m.mat_mul(&inverse), Matrix::<f32, 3, 3>::identity(), 1e-6);
+        let singular = Matrix::new([[1.0, 2.0], [2.0, 4.0]]);
+        assert_eq!(singular.inverse(), None);
+    }
+
+    #[test]
+    fn test_normal_matrix_under_non_uniform_scale() {
+        let m = Matrix4f::new([
+            [2.0, 0.0, 0.0, 5.0],
+            [0.0, 0.0, -1.0, 1.0],
+            [0.0, 0.5, 0.0, -3.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert_eq!(
+            m.linear_part(),
+            Matrix::new([[2.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 0.5, 0.0]])
+        );
+        let normal = Vector3f::xyz(1.0, 1.0, 0.0).normalized();
+        let tangent = Vector3f::xyz(1.0, -1.0, 2.0);
+        let transformed_normal = m.normal_matrix().unwrap().mat_mul(&normal);
+        let transformed_tangent = m.linear_part().mat_mul(&tangent);
+        assert_approx!(transformed_normal.dot(&transformed_tangent), 0.0, 1e-6);
+        // The linear part alone tilts the normal off the surface.
+        assert!(
+            m.linear_part()
+                .mat_mul(&normal)
+                .dot(&transformed_tangent)
+                .abs()
+                > 0.1
+        );
+        assert_eq!(Matrix4f::zeros().normal_matrix(), None);
+    }
+
+    #[test]
+    fn test_spherical_round_trip() {
+        let directions = [
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(1.0, 0.0, 0.0),
+            Vector3f::xyz(0.3, -0.8, 0.5),
+            Vector3f::xyz(-0.6, 0.2, 0.7),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+        ];
+        assert_eq!(directions[0].to_spherical(), (PI / 2.0, 0.0));
+        for direction in directions {
+            let direction = direction.normalized();
+            let (theta, phi) = direction.to_spherical();
+            let round_trip = Vector3f::from_spherical(theta, phi);
+            assert!(round_trip.distance(&direction) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_approx_eq() {
+        let a = Matrix::new([[1.0, 2.0], [3.0, 4.0]]);
+        let b = Matrix::new([[1.0, 2.0], [3.0, 4.001]]);
+        assert!(a.approx_eq(&b, 1e-2));
+        assert!(!a.approx_eq(&b, 1e-4));
+    }
+
+    #[test]
+    fn test_array_round_trip() {
+        let vector = Vector3f::from([1.0, 2.0, 3.0]);
+        assert_eq!(vector, Vector3f::xyz(1.0, 2.0, 3.0));
+        let array: [f32; 3] = vector.into();
+        assert_eq!(array, [1.0, 2.0, 3.0]);
+        let size: Vector2i = [640, 480].into();
+        assert_eq!(size, Vector2i::xy(640, 480));
+        assert_eq!(<[i32; 2]>::from(size), [640, 480]);
+        assert_eq!(Vector2f::from([0.5, 0.25]).to_array(), [0.5, 0.25]);
+    }
 }
@@ -0,0 +1,232 @@
+use bvh::Accel;
+use geometry::{
+    get_area, get_emission, get_outward_normal, get_scatter, get_surface_sample, get_transmittance,
+    get_uv, offset_origin, Object, Ray,
+};
+use image::Color;
+use light::LightList;
+use matrix::Vector3f;
+use rng::Rng;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+/// Safety limit on the number of specular bounces of a photon.
+const MAX_PHOTON_BOUNCES: u32 = 16;
+
+struct Photon {
+    position: Vector3f,
+    power: Color,
+}
+
+/// Caustic photon map: photons emitted from the lights that reached a
+/// diffuse surface via at least one specular bounce.
+///
+/// Looking up the photon density replaces the light-specular-diffuse paths
+/// a path tracer rarely finds with an estimate that has far less noise, but
+/// is biased: caustics are blurred over `radius` and darkened near their
+/// edges. More photons allow a smaller radius and sharper caustics.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    /// Photon indices by grid cell of size `radius`.
+    cells: HashMap<(i32, i32, i32), Vec<usize>>,
+    radius: f32,
+}
+
+impl PhotonMap {
+    /// Emits `count` photons from `lights`, which must be built from
+    /// `objects` like `accel`.
+    pub fn new(
+        objects: &[Object],
+        accel: &Accel,
+        lights: &LightList,
+        count: u32,
+        radius: f32,
+        rng: &mut Rng,
+    ) -> Self {
+        let mut map = PhotonMap {
+            photons: Vec::new(),
+            cells: HashMap::new(),
+            radius,
+        };
+        for _ in 0..count {
+            if let Some(photon) = trace_photon(objects, accel, lights, count, rng) {
+                map.cells
+                    .entry(map.cell(&photon.position))
+                    .or_default()
+                    .push(map.photons.len());
+                map.photons.push(photon);
+            }
+        }
+        map
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Incident power per area at `point`, averaged over a disk of `radius`.
+    pub fn flux_density(&self, point: &Vector3f) -> Color {
+        let (x, y, z) = self.cell(point);
+        let mut flux = Color::rgb(0.0, 0.0, 0.0);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let indices = match self.cells.get(&(x + dx, y + dy, z + dz)) {
+                        Some(indices) => indices,
+                        None => continue,
+                    };
+                    for &i in indices {
+                        let photon = &self.photons[i];
+                        if photon.position.distance(point) < self.radius {
+                            flux += photon.power;
+                        }
+                    }
+                }
+            }
+        }
+        flux / (PI * self.radius * self.radius)
+    }
+
+    fn cell(&self, point: &Vector3f) -> (i32, i32, i32) {
+        let index = |c: f32| (c / self.radius).floor() as i32;
+        (index(point.x()), index(point.y()), index(point.z()))
+    }
+}
+
+/// Follows one photon through specular bounces, returning where it lands on
+/// a diffuse surface if it bounced at least once.
+fn trace_photon(
+    objects: &[Object],
+    accel: &Accel,
+    lights: &LightList,
+    count: u32,
+    rng: &mut Rng,
+) -> Option<Photon> {
+    let (index, probability) = lights.sample(rng)?;
+    let light = &objects[index];
+    let area = get_area(&light.surface)?;
+    let (origin, normal) = get_surface_sample(&light.surface, rng)?;
+    let direction = rng.cosine_direction(&normal);
+    // Cosine weighted emission carries pi times the radiance per area.
+    let mut power = get_emission(&light.material, &normal, &(direction * -1.0))
+        * (PI * area / (probability * count as f32));
+    let mut ray = Ray {
+        origin: offset_origin(&origin, &normal, &direction),
+        direction,
+    };
+    for bounce in 0..MAX_PHOTON_BOUNCES {
+        let (point, object) = accel.closest_hit(objects, &ray)?;
+        let outward_normal = get_outward_normal(&object.surface, &point);
+        let front_face = outward_normal.dot(&ray.direction) < 0.0;
+        let material = object.material_at(&point);
+        let normal = if front_face {
+            outward_normal
+        } else {
+            outward_normal * -1.0
+        };
+        if !front_face {
+            power = power * get_transmittance(material, point.distance(&ray.origin));
+        }
+        let uv = get_uv(&object.surface, &point);
+        let scatter = get_scatter(&ray, &normal, front_face, &uv, material, rng);
+        if !scatter.specular {
+            return (bounce > 0).then_some(Photon {
+                position: point,
+                power,
+            });
+        }
+        if scatter.attenuation == Color::rgb(0.0, 0.0, 0.0) {
+            return None;
+        }
+        power = power * scatter.attenuation;
+        ray = Ray {
+            origin: offset_origin(&point, &normal, &scatter.direction),
+            direction: scatter.direction,
+        };
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::{Material, Surface};
+    use texture::Texture;
+
+    fn room(floor: Material) -> Vec<Object> {
+        vec![
+            Object::new(
+                Surface::Sphere {
+                    center: Vector3f::xyz(0.0, 5.0, 0.0),
+                    radius: 0.5,
+                },
+                Material::Emissive {
+                    radiance: Color::rgb(1.0, 1.0, 1.0),
+                    emit_cone_angle: None,
+                },
+            ),
+            Object::new(
+                Surface::Box {
+                    min: Vector3f::xyz(-50.0, -1.0, -50.0),
+                    max: Vector3f::xyz(50.0, 0.0, 50.0),
+                },
+                floor,
+            ),
+            Object::new(
+                Surface::Box {
+                    min: Vector3f::xyz(-50.0, 10.0, -50.0),
+                    max: Vector3f::xyz(50.0, 11.0, 50.0),
+                },
+                Material::Lambertian {
+                    albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+                },
+            ),
+        ]
+    }
+
+    fn photon_map(objects: &[Object]) -> PhotonMap {
+        let lights = LightList::new(objects);
+        PhotonMap::new(
+            objects,
+            &Accel::Linear,
+            &lights,
+            50_000,
+            1.5,
+            &mut Rng::new(0),
+        )
+    }
+
+    #[test]
+    fn test_only_specular_paths_deposit_photons() {
+        let diffuse_floor = room(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        });
+        assert!(photon_map(&diffuse_floor).is_empty());
+    }
+
+    #[test]
+    fn test_mirror_floor_caustic() {
+        let mirror_floor = room(Material::Mirror {
+            tint: Color::rgb(1.0, 1.0, 1.0),
+        });
+        let map = photon_map(&mirror_floor);
+        assert!(!map.is_empty());
+        // The ceiling is lit by the mirror image of the light at y = -5.
+        let point = Vector3f::xyz(4.0, 10.0, 0.0);
+        let offset = point - Vector3f::xyz(0.0, -5.0, 0.0);
+        let cos_theta = offset.y() / offset.length();
+        let expected = PI * 0.5 * 0.5 / offset.squared_length() * cos_theta;
+        let flux = map.flux_density(&point).r();
+        assert!(
+            (flux - expected).abs() < 0.25 * expected,
+            "{} vs {}",
+            flux,
+            expected
+        );
+        assert_eq!(map.flux_density(&Vector3f::xyz(0.0, -20.0, 0.0)).r(), 0.0);
+    }
+}
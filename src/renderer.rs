@@ -1,95 +1,2486 @@
+use background::{cube_direction, Background, Environment, ReflectionProbe};
+use bvh::Accel;
+use error::Error;
 use geometry::{
-    get_attenuation, get_intersection, get_normal, get_scatter_direction, Camera, Material, Object,
-    Ray,
+    apply_normal_map, get_albedo, get_area, get_emission, get_intersection, get_ior, get_normal,
+    get_outward_normal, get_pdf_toward, get_sample_toward, get_scatter, get_scatter_pdf,
+    get_surface_sample, get_tangent, get_transmittance, get_uv, offset_origin, Camera, Material,
+    Object, Ray,
 };
-use image::{gamma_correct, Color, Image};
-use matrix::Vector3f;
+use image::{gamma_correct, Accumulator, Color, Image, ToneMap};
+use light::{LightList, PointLight};
+use matrix::{Vector2f, Vector3f};
+use photon::PhotonMap;
 use rng::Rng;
+use sampling::{PixelFilter, SamplePattern};
+use scene::Scene;
+use sh::ShIrradiance;
+use spectrum::{rgb_weight, sample_wavelength};
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
+/// Path tracer for `scene` seen through `camera`. Methods rendering a path
+/// traced image take a `seed` as their first argument: the same seed
+/// reproduces an image exactly, and different seeds give independent
+/// images, e.g. to average. Methods rendering several passes themselves,
+/// like `render_animation` and `progressive`, pick the seed of each pass.
 pub struct Renderer {
     pub camera: Camera,
-    pub objects: Vec<Object>,
-    pub ambient_light_color: Color,
+    pub scene: Scene,
+    /// Index structure over `scene.objects`, e.g. `Bvh::new(&scene.objects)`
+    /// wrapped in `Accel::Bvh`. Rebuild it when the objects change.
+    pub accel: Accel,
+    /// Emitters for next-event estimation, from
+    /// `LightList::new(&scene.objects)`.
+    pub lights: LightList,
+    pub point_lights: Vec<PointLight>,
+    /// Shadow rays per diffuse vertex toward the emitters in `lights`, and
+    /// per point light with a radius. More give smoother penumbrae at a
+    /// fixed number of samples per pixel.
+    pub shadow_samples: u32,
+    pub background: Background,
+    /// Scales all light arriving from `background`, 0 leaves a black void.
+    pub ambient_intensity: f32,
+    /// Applied by `to_display` after the exposure of `camera`.
+    pub tone_map: ToneMap,
+    /// Safety limit on the total number of ray segments in a path.
     pub max_depth: u32,
+    /// Number of diffuse scatter events in a path.
+    pub max_diffuse_bounces: u32,
+    /// Number of specular scatter events in a path, counted separately so
+    /// that mirror-in-mirror scenes can recurse deeper than diffuse light.
+    pub max_specular_bounces: u32,
+    /// Scatter events always continued before Russian roulette starts
+    /// terminating paths with a probability of one minus the largest
+    /// component of the scatter attenuation.
+    pub rr_min_bounces: u32,
+    /// Radiance of paths cut off by `max_depth`.
+    pub termination_color: TerminationColor,
     pub samples_per_pixel: u32,
+    pub sample_pattern: SamplePattern,
+    /// Distributes the samples of `sample_pattern` around each pixel.
+    pub pixel_filter: PixelFilter,
+    pub render_mode: RenderMode,
+    pub tile_order: TileOrder,
+    /// Traces every path at a single random wavelength, so that dispersive
+    /// dielectrics split white light into colors. Noisier than RGB
+    /// rendering, since each path carries one color.
+    pub spectral: bool,
+    /// Number of photons traced from the lights before every render pass,
+    /// 0 disables the caustic photon map. See `PhotonMap` for the tradeoff.
+    pub photon_pass: u32,
+    /// Radius over which photons are gathered.
+    pub photon_radius: f32,
+    /// Mirrors look up the radiance they reflect in this probe instead of
+    /// tracing further, which is fast but ignores where on the mirror the
+    /// reflection starts. See `bake_reflection_probe`.
+    pub reflection_probe: Option<ReflectionProbe>,
+    /// Budget for `rays_traced`. A render pass stops at the first pixel
+    /// after it is used up, leaving the remaining pixels black with a
+    /// sample count of 0. Reset `rays_traced` to start a new budget.
+    pub max_rays: Option<u64>,
+    /// Rays intersected with the scene so far, counting camera, bounce and
+    /// shadow rays of all renders. Photon map construction is not counted.
+    pub rays_traced: AtomicU64,
+}
+
+/// What the path since the last diffuse vertex means for caustic photons.
+#[derive(Clone, Copy, PartialEq)]
+enum PathKind {
+    /// Emission found by this path is not covered by the photon map.
+    Direct,
+    /// The last vertex gathered caustic photons.
+    Diffuse,
+    /// Only specular vertices since the last photon gathering vertex, any
+    /// emission found is already part of the photon map.
+    Caustic,
+}
+
+pub enum TerminationColor {
+    /// No radiance, which darkens scenes needing many bounces.
+    Black,
+    /// The background in the direction of the last ray, as if the path
+    /// escaped. Less biased for open scenes, too bright for closed ones.
+    Background,
+}
+
+pub enum RenderMode {
+    /// Every pixel receives the same number of samples.
+    Uniform,
+    /// A first pass traces one ray through every pixel center to find depth
+    /// and normal discontinuities. Pixels next to one receive
+    /// `edge_sample_factor` times as many samples.
+    EdgeAdaptive { edge_sample_factor: u32 },
+}
+
+/// Order in which a render pass visits the tiles of `TILE_SIZE` pixels
+/// squared. Only matters for passes that stop early, the pixels of a full
+/// pass do not depend on it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TileOrder {
+    /// Rows of tiles from the top left.
+    Scanline,
+    /// By distance from the image center, so the subject appears first.
+    CenterOut,
+    /// Along a Hilbert curve, keeping consecutive tiles adjacent.
+    Hilbert,
+}
+
+/// Side length in pixels of the tiles ordered by `TileOrder`.
+pub const TILE_SIZE: u32 = 16;
+
+impl TileOrder {
+    /// Visits every tile of a `tiles_x` by `tiles_y` grid once, as (column,
+    /// row) pairs.
+    pub fn tiles(&self, tiles_x: u32, tiles_y: u32) -> Vec<(u32, u32)> {
+        let scanline = (0..tiles_y).flat_map(|y| (0..tiles_x).map(move |x| (x, y)));
+        match self {
+            TileOrder::Scanline => scanline.collect(),
+            TileOrder::CenterOut => {
+                let mut tiles: Vec<_> = scanline.collect();
+                // In doubled coordinates to stay in integers.
+                let distance = |&(x, y): &(u32, u32)| {
+                    let dx = (2 * x + 1) as i64 - tiles_x as i64;
+                    let dy = (2 * y + 1) as i64 - tiles_y as i64;
+                    dx * dx + dy * dy
+                };
+                tiles.sort_by_key(distance);
+                tiles
+            }
+            TileOrder::Hilbert => {
+                let side = tiles_x.max(tiles_y).next_power_of_two();
+                (0..side * side)
+                    .map(|d| hilbert_point(side, d))
+                    .filter(|&(x, y)| x < tiles_x && y < tiles_y)
+                    .collect()
+            }
+        }
+    }
+
+    /// Indices of the pixels of a `width` by `height` image, tile by tile.
+    fn pixel_order(&self, width: u32, height: u32) -> Vec<usize> {
+        let tiles = self.tiles(width.div_ceil(TILE_SIZE), height.div_ceil(TILE_SIZE));
+        tiles
+            .into_iter()
+            .flat_map(|(tile_x, tile_y)| {
+                let xs = tile_x * TILE_SIZE..((tile_x + 1) * TILE_SIZE).min(width);
+                let ys = tile_y * TILE_SIZE..((tile_y + 1) * TILE_SIZE).min(height);
+                ys.flat_map(move |y| xs.clone().map(move |x| (y * width + x) as usize))
+            })
+            .collect()
+    }
+}
+
+/// Point at distance `d` along the Hilbert curve filling a `side` by `side`
+/// grid, `side` being a power of two.
+fn hilbert_point(side: u32, d: u32) -> (u32, u32) {
+    let (mut x, mut y, mut t) = (0, 0, d);
+    let mut s = 1;
+    while s < side {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+/// Relative depth difference between neighbors that marks an edge.
+const EDGE_DEPTH_THRESHOLD: f32 = 0.1;
+/// Cosine between neighboring normals below which they form an edge.
+const EDGE_NORMAL_THRESHOLD: f32 = 0.9;
+
+/// Result of `compute_color_for_pixel`, with the auxiliary outputs taken
+/// from the first camera ray so that they need no extra ray.
+struct PixelEstimate {
+    color: Color,
+    alpha: f32,
+    variance: Color,
+    object_id: u32,
+    normal: Vector3f,
+    depth: f32,
+}
+
+pub struct RenderOutput {
+    /// Linear radiance, before any display transform.
+    pub image: Image,
+    pub sample_counts: Vec<u32>,
+    /// Id of the object hit by the first camera ray of each pixel, 0 if
+    /// none.
+    pub object_id: Vec<u32>,
+    /// Normal at that hit, facing the camera, zero if none.
+    pub normal: Vec<Vector3f>,
+    /// Distance from the camera to that hit, infinite if none or beyond the
+    /// far plane.
+    pub depth: Vec<f32>,
+    /// Per channel variance of the radiance samples of each pixel. Divide
+    /// by `sample_counts` for the variance of the pixel value.
+    pub variance: Vec<Color>,
+    /// Fraction of camera rays hitting an object with a transparent
+    /// background, 1 otherwise. `image` is not premultiplied by it.
+    pub alpha: Vec<f32>,
+    /// Rays traced during the pass, including other renders running at the
+    /// same time with the same renderer.
+    pub rays_traced: u64,
+}
+
+impl RenderOutput {
+    pub fn sample_heatmap(&self) -> Image {
+        let max = self.sample_counts.iter().cloned().max().unwrap_or(0);
+        Image::from_counts(
+            self.image.width,
+            self.image.height,
+            &self.sample_counts,
+            max,
+        )
+    }
+
+    /// Normals mapped from [-1, 1] to [0, 1], black where nothing is hit.
+    pub fn normal_image(&self) -> Image {
+        Image {
+            width: self.image.width,
+            height: self.image.height,
+            data: self
+                .normal
+                .iter()
+                .map(|normal| {
+                    if *normal == Vector3f::zeros() {
+                        *normal
+                    } else {
+                        (*normal + Vector3f::xyz(1.0, 1.0, 1.0)) * 0.5
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Depth mapped from [`near`, `far`] to gray levels, white where
+    /// nothing is hit. An empty range separates depths at `near` into black
+    /// and white.
+    pub fn depth_image(&self, near: f32, far: f32) -> Image {
+        Image {
+            width: self.image.width,
+            height: self.image.height,
+            data: self
+                .depth
+                .iter()
+                .map(|&depth| {
+                    let value = if depth >= far {
+                        1.0
+                    } else if depth <= near {
+                        0.0
+                    } else {
+                        (depth - near) / (far - near)
+                    };
+                    Color::rgb(value, value, value)
+                })
+                .collect(),
+        }
+    }
 }
 
 impl Renderer {
     pub fn render(&self, seed: u32) -> Image {
+        self.to_display(&self.render_output(seed).image)
+    }
+
+    pub fn render_output(&self, seed: u32) -> RenderOutput {
+        self.render_pass(&self.camera, seed, self.samples_per_pixel, None, None)
+    }
+
+    /// Like `render_output`, but stops early once `cancel` is set. Pixels
+    /// not rendered by then stay black with a sample count of 0.
+    pub fn render_cancellable(&self, seed: u32, cancel: &AtomicBool) -> RenderOutput {
+        self.render_pass(
+            &self.camera,
+            seed,
+            self.samples_per_pixel,
+            Some(cancel),
+            None,
+        )
+    }
+
+    /// Renders only the scanlines with `y % num_fields == field`, leaving
+    /// the others black with a sample count of 0. Pixels are seeded
+    /// independently, so the fields of one `seed` together equal
+    /// `render_output(seed)`. Returns `None` unless `field < num_fields`.
+    pub fn render_interlaced(
+        &self,
+        seed: u32,
+        field: u32,
+        num_fields: u32,
+    ) -> Option<RenderOutput> {
+        if field >= num_fields {
+            return None;
+        }
+        let interlace = Some((field, num_fields));
+        Some(self.render_pass(&self.camera, seed, self.samples_per_pixel, None, interlace))
+    }
+
+    /// Albedo and normal guide buffers for external denoisers, from the
+    /// first hit of the ray through every pixel center, so free of noise.
+    /// Normals are in world space with components in [-1, 1], facing the
+    /// camera. Both are black where nothing is hit.
+    pub fn render_guides(&self) -> (Image, Image) {
+        let size = self.camera.sensor_size_px;
+        let mut albedo = Image::new(size.x() as u32, size.y() as u32);
+        let mut normal = Image::new(size.x() as u32, size.y() as u32);
+        let width = albedo.width;
+        for i in 0..albedo.data.len() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            let ray = self.camera.back_project(x as f32, y as f32);
+            if let Some((point, object)) = self.closest_hit(&ray) {
+                let uv = get_uv(&object.surface, &point);
+                albedo.data[i] = get_albedo(object.material_at(&point), &uv);
+                normal.data[i] = get_normal(&object.surface, &point, &ray.direction);
+            }
+        }
+        (albedo, normal)
+    }
+
+    /// Renders the radiance arriving at `position` onto six cube faces of
+    /// `resolution` texels squared, with `samples_per_pixel` paths per
+    /// texel. Wrap the result in a `ReflectionProbe` to use it.
+    pub fn bake_reflection_probe(&self, position: Vector3f, resolution: u32) -> [Image; 6] {
+        let mut faces: [Image; 6] = std::array::from_fn(|_| Image::new(resolution, resolution));
+        for (face, image) in faces.iter_mut().enumerate() {
+            for (i, color) in image.data.iter_mut().enumerate() {
+                let (x, y) = (i as u32 % resolution, i as u32 / resolution);
+                let mut rng = Rng::for_pixel(face as u32, x, y);
+                let mut sum = Color::rgb(0.0, 0.0, 0.0);
+                for _ in 0..self.samples_per_pixel {
+                    let u = (x as f32 + rng.uniform()) / resolution as f32;
+                    let v = (y as f32 + rng.uniform()) / resolution as f32;
+                    let ray = Ray {
+                        origin: position,
+                        direction: cube_direction(face, u, v).normalized(),
+                    };
+                    sum += self.compute_color_for_ray(
+                        &ray,
+                        &mut rng,
+                        self.max_depth,
+                        self.max_diffuse_bounces,
+                        self.max_specular_bounces,
+                        None,
+                        None,
+                        None,
+                    );
+                }
+                *color = sum * (1.0 / self.samples_per_pixel.max(1) as f32);
+            }
+        }
+        faces
+    }
+
+    /// Writes `<out_prefix>_beauty.ppm`, `<out_prefix>_normal.ppm` and
+    /// `<out_prefix>_depth.ppm` from a single render pass. Depth is
+    /// normalized between the near and far planes of the camera, or the far
+    /// side of the scene's bounding sphere for an infinite far plane.
+    pub fn render_all(&self, seed: u32, out_prefix: &str) -> Result<(), Error> {
+        let output = self.render_output(seed);
+        let far = if self.camera.far.is_finite() {
+            self.camera.far
+        } else {
+            self.scene
+                .bounding_sphere()
+                .map_or(1.0, |(center, radius)| {
+                    center.distance(&self.camera.position) + radius
+                })
+        };
+        self.to_display(&output.image)
+            .save(&format!("{}_beauty.ppm", out_prefix))?;
+        output
+            .normal_image()
+            .save(&format!("{}_normal.ppm", out_prefix))?;
+        output
+            .depth_image(self.camera.near, far)
+            .save(&format!("{}_depth.ppm", out_prefix))
+    }
+
+    /// Display images for the left and right eye, `ipd` apart.
+    pub fn render_stereo(&self, seed: u32, ipd: f32) -> (Image, Image) {
+        let (left, right) = self.camera.stereo_pair(ipd);
+        let render = |camera| {
+            let output = self.render_pass(camera, seed, self.samples_per_pixel, None, None);
+            self.to_display(&output.image)
+        };
+        (render(&left), render(&right))
+    }
+
+    /// Renders a turntable animation orbiting the bounding sphere of the
+    /// scene. Every `{}` in `out_pattern` is replaced by the frame number,
+    /// starting at 1 and zero padded to four digits.
+    pub fn render_animation(&self, frames: u32, out_pattern: &str) -> Result<(), Error> {
+        let (center, radius) = self
+            .scene
+            .bounding_sphere()
+            .unwrap_or((Vector3f::zeros(), 1.0));
+        for frame in 0..frames {
+            let azimuth = 2.0 * PI * frame as f32 / frames as f32;
+            let camera = self.camera.frame_sphere(center, radius, azimuth);
+            let output = self.render_pass(&camera, frame, self.samples_per_pixel, None, None);
+            let filename = out_pattern.replace("{}", &format!("{:04}", frame + 1));
+            self.to_display(&output.image).save(&filename)?;
+        }
+        Ok(())
+    }
+
+    /// Yields successively refined display images. Every pass adds one
+    /// sample per pixel from a new seed to the running average.
+    pub fn progressive(&self) -> impl Iterator<Item = Image> + '_ {
         let image_size = self.camera.sensor_size_px;
+        let mut accumulator = Accumulator::new(image_size.x() as u32, image_size.y() as u32);
+        (0..).map(move |seed| {
+            accumulator.add(&self.render_pass(&self.camera, seed, 1, None, None).image);
+            self.to_display(&accumulator.average())
+        })
+    }
+
+    pub fn to_display(&self, image: &Image) -> Image {
+        let exposure = self.camera.exposure_multiplier();
+        Image {
+            width: image.width,
+            height: image.height,
+            data: image
+                .data
+                .iter()
+                .map(|color| gamma_correct(self.tone_map.apply(*color * exposure)).saturate())
+                .collect(),
+        }
+    }
+
+    /// Fast preview tracing one ray through every pixel center. Lambertian
+    /// surfaces are lit by the background projected onto spherical
+    /// harmonics, without shadows, interreflections or emitters in the
+    /// scene. Specular surfaces are followed.
+    pub fn render_sh_preview(&self) -> Image {
+        let irradiance = ShIrradiance::from_background(&self.background);
+        let size = self.camera.sensor_size_px;
+        let mut image = Image::new(size.x() as u32, size.y() as u32);
+        let mut rng = Rng::new(0);
+        let width = image.width;
+        for (i, color) in image.data.iter_mut().enumerate() {
+            let x = i as u32 % width;
+            let y = i as u32 / width;
+            let ray = self.camera.back_project(x as f32, y as f32);
+            *color = self.preview_color_for_ray(&ray, &irradiance, &mut rng, self.max_depth);
+        }
+        self.to_display(&image)
+    }
+
+    fn preview_color_for_ray(
+        &self,
+        ray: &Ray,
+        irradiance: &ShIrradiance,
+        rng: &mut Rng,
+        max_depth: u32,
+    ) -> Color {
+        if max_depth == 0 {
+            return Color::rgb(0.0, 0.0, 0.0);
+        }
+        let (point, object) = match self.closest_hit(ray) {
+            Some(hit) => hit,
+            None => return self.background.color(ray) * self.ambient_intensity,
+        };
+        let outward_normal = get_outward_normal(&object.surface, &point);
+        let front_face = outward_normal.dot(&ray.direction) < 0.0;
+        let normal = if front_face {
+            outward_normal
+        } else {
+            outward_normal * -1.0
+        };
+        let material = object.material_at(&point);
+        let emitted = get_emission(material, &normal, &ray.direction);
+        let uv = get_uv(&object.surface, &point);
+        if let Material::Lambertian { albedo } = material {
+            return emitted
+                + albedo.sample(&uv)
+                    * irradiance.irradiance(&normal)
+                    * (self.ambient_intensity / PI);
+        }
+        let scatter = get_scatter(ray, &normal, front_face, &uv, material, rng);
+        if scatter.attenuation == Color::rgb(0.0, 0.0, 0.0) {
+            return emitted;
+        }
+        let ray = Ray {
+            origin: offset_origin(&point, &normal, &scatter.direction),
+            direction: scatter.direction,
+        };
+        emitted
+            + self.preview_color_for_ray(&ray, irradiance, rng, max_depth - 1) * scatter.attenuation
+    }
+
+    /// Exposure multiplier mapping the log-average luminance of a quick, low
+    /// resolution render to middle gray. Scale `camera.shutter_time` by its
+    /// ratio to `Camera::exposure_multiplier` to apply it.
+    pub fn auto_exposure(&self) -> f32 {
+        const STRIDE: usize = 4;
+        const MIDDLE_GRAY: f32 = 0.18;
+        const DELTA: f32 = 1e-4;
+        let mut rng = Rng::new(0);
+        let size = self.camera.sensor_size_px;
+        let mut log_sum = 0.0;
+        let mut count = 0;
+        for y in (0..size.y() as u32).step_by(STRIDE) {
+            for x in (0..size.x() as u32).step_by(STRIDE) {
+                let color = self
+                    .compute_color_for_pixel(&self.camera, x, y, 1, &mut rng, None)
+                    .color;
+                log_sum += (DELTA + color.luminance()).ln();
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return 1.0;
+        }
+        MIDDLE_GRAY / (log_sum / count as f32).exp()
+    }
+
+    fn render_pass(
+        &self,
+        camera: &Camera,
+        seed: u32,
+        samples_per_pixel: u32,
+        cancel: Option<&AtomicBool>,
+        interlace: Option<(u32, u32)>,
+    ) -> RenderOutput {
+        let image_size = camera.sensor_size_px;
         let mut rng = Rng::new(seed);
+        let photons = (self.photon_pass > 0).then(|| {
+            PhotonMap::new(
+                &self.scene.objects,
+                &self.accel,
+                &self.lights,
+                self.photon_pass,
+                self.photon_radius,
+                &mut rng,
+            )
+        });
         let mut image = Image::new(image_size.x() as u32, image_size.y() as u32);
+        let mut sample_counts = vec![0; image.data.len()];
+        let mut object_id = vec![0; image.data.len()];
+        let mut normal = vec![Vector3f::zeros(); image.data.len()];
+        let mut depth = vec![f32::INFINITY; image.data.len()];
+        let mut variance = vec![Color::rgb(0.0, 0.0, 0.0); image.data.len()];
+        let mut alpha = vec![0.0; image.data.len()];
         let width = image.width;
-        image.data.iter_mut().enumerate().for_each(|(i, color)| {
+        let edges = match self.render_mode {
+            RenderMode::Uniform => None,
+            RenderMode::EdgeAdaptive { edge_sample_factor } => {
+                Some((self.edge_mask(camera), edge_sample_factor))
+            }
+        };
+        let rays_before = self.rays_traced.load(Ordering::Relaxed);
+        for i in self.tile_order.pixel_order(width, image.height) {
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                break;
+            }
+            if self
+                .max_rays
+                .is_some_and(|max_rays| self.rays_traced.load(Ordering::Relaxed) >= max_rays)
+            {
+                break;
+            }
             let x = i as u32 % width;
             let y = i as u32 / width;
-            *color = self.compute_color_for_pixel(x, y, &mut rng);
-        });
-        image
+            if interlace.is_some_and(|(field, num_fields)| y % num_fields != field) {
+                continue;
+            }
+            let samples = match &edges {
+                Some((mask, factor)) if mask[i] => samples_per_pixel * factor,
+                _ => samples_per_pixel,
+            };
+            let mut rng = Rng::for_pixel(seed, x, y);
+            let estimate =
+                self.compute_color_for_pixel(camera, x, y, samples, &mut rng, photons.as_ref());
+            (image.data[i], alpha[i], variance[i]) =
+                (estimate.color, estimate.alpha, estimate.variance);
+            (object_id[i], normal[i], depth[i]) =
+                (estimate.object_id, estimate.normal, estimate.depth);
+            sample_counts[i] = samples;
+        }
+        RenderOutput {
+            image,
+            sample_counts,
+            object_id,
+            normal,
+            depth,
+            alpha,
+            variance,
+            rays_traced: self.rays_traced.load(Ordering::Relaxed) - rays_before,
+        }
     }
 
+    /// Averages the renders of all `seeds` on all available cores.
     pub fn average_render(&self, seeds: &[u32]) -> Image {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        self.average_render_with_threads(seeds, threads)
+    }
+
+    /// Averages the renders of all `seeds`. Seeds are split into one chunk
+    /// per thread, at most `threads`, and every thread sums its renders into
+    /// a single buffer, so memory does not grow with the number of seeds.
+    pub fn average_render_with_threads(&self, seeds: &[u32], threads: usize) -> Image {
         let image_size = self.camera.sensor_size_px;
-        let weight = 1.0 / seeds.len() as f32;
-
-        std::thread::scope(|s| {
-            let mut threads = Vec::new();
-            for seed in seeds {
-                threads.push(s.spawn(move || self.render(*seed)));
-            }
-            let mut average_image = Image::new(image_size.x() as u32, image_size.y() as u32);
-            for thread in threads {
-                let image = thread.join().unwrap();
-                for i in 0..image.data.len() {
-                    average_image.data[i] += image.data[i] * weight;
-                }
+        let threads = threads.min(seeds.len()).max(1);
+        let chunk_size = seeds.len().div_ceil(threads).max(1);
+        let images: Vec<Image> = std::thread::scope(|s| {
+            let threads: Vec<_> = seeds
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move || {
+                        let mut sum = self.render(chunk[0]);
+                        for seed in &chunk[1..] {
+                            add_image(&mut sum, &self.render(*seed));
+                        }
+                        sum
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect()
+        });
+        match pairwise_sum(&images) {
+            Some(mut sum) => {
+                let weight = 1.0 / seeds.len() as f32;
+                sum.data
+                    .iter_mut()
+                    .for_each(|color| *color = *color * weight);
+                sum
             }
-            average_image
-        })
+            None => Image::new(image_size.x() as u32, image_size.y() as u32),
+        }
+    }
+
+    /// Marks pixels whose center ray differs from a 4-neighbor's in hit
+    /// distance, normal or whether it hits anything at all.
+    fn edge_mask(&self, camera: &Camera) -> Vec<bool> {
+        let width = camera.sensor_size_px.x() as usize;
+        let height = camera.sensor_size_px.y() as usize;
+        let gbuffer: Vec<Option<(f32, Vector3f)>> = (0..width * height)
+            .map(|i| {
+                let ray = camera.back_project((i % width) as f32, (i / width) as f32);
+                self.closest_hit(&ray).map(|(point, object)| {
+                    let normal = get_normal(&object.surface, &point, &ray.direction);
+                    (point.distance(&camera.position), normal)
+                })
+            })
+            .collect();
+        let differs = |a: usize, b: usize| match (gbuffer[a], gbuffer[b]) {
+            (Some((depth_a, normal_a)), Some((depth_b, normal_b))) => {
+                (depth_a - depth_b).abs() > EDGE_DEPTH_THRESHOLD * depth_a.min(depth_b)
+                    || normal_a.dot(&normal_b) < EDGE_NORMAL_THRESHOLD
+            }
+            (None, None) => false,
+            _ => true,
+        };
+        (0..width * height)
+            .map(|i| {
+                let x = i % width;
+                let y = i / width;
+                (x > 0 && differs(i, i - 1))
+                    || (x + 1 < width && differs(i, i + 1))
+                    || (y > 0 && differs(i, i - width))
+                    || (y + 1 < height && differs(i, i + width))
+            })
+            .collect()
     }
 
-    fn compute_color_for_pixel(&self, x: u32, y: u32, rng: &mut Rng) -> Color {
+    fn compute_color_for_pixel(
+        &self,
+        camera: &Camera,
+        x: u32,
+        y: u32,
+        samples_per_pixel: u32,
+        rng: &mut Rng,
+        photons: Option<&PhotonMap>,
+    ) -> PixelEstimate {
         let x = x as f32;
         let y = y as f32;
+        let mut estimate = PixelEstimate {
+            color: Color::rgb(0.0, 0.0, 0.0),
+            alpha: 1.0,
+            variance: Color::rgb(0.0, 0.0, 0.0),
+            object_id: 0,
+            normal: Vector3f::zeros(),
+            depth: f32::INFINITY,
+        };
 
+        let transparent = matches!(self.background, Background::Transparent(_));
         let mut color = Color::rgb(0.0, 0.0, 0.0);
-        for _ in 0..self.samples_per_pixel {
-            let x = x + (rng.uniform() - 0.5);
-            let y = y + (rng.uniform() - 0.5);
-            let ray = self.camera.back_project(x, y);
-            color += self.compute_color_for_ray(&ray, rng, self.max_depth);
+        let mut squares = Color::rgb(0.0, 0.0, 0.0);
+        let mut covered = 0;
+        let pixel_samples = self.sample_pattern.generate(samples_per_pixel, rng);
+        let antithetic = self.sample_pattern == SamplePattern::Antithetic;
+        // Lens samples follow the pattern too, in an order independent of
+        // the film samples. Antithetic pairs mirror the uniforms instead.
+        let lens_samples = (camera.aperture_radius > 0.0 && !antithetic).then(|| {
+            self.sample_pattern
+                .generate_shuffled(samples_per_pixel, rng)
+        });
+        let film_size = camera.sensor_size_px;
+        for (i, pixel_sample) in pixel_samples.into_iter().enumerate() {
+            if antithetic && i % 2 == 0 {
+                rng.record();
+            } else if antithetic {
+                rng.mirror();
+            }
+            let offset = self.pixel_filter.offset(&pixel_sample);
+            let film_sample = Vector2f::xy(
+                (x + 0.5 + offset.x()) / film_size.x() as f32,
+                (y + 0.5 + offset.y()) / film_size.y() as f32,
+            );
+            let lens_sample = match &lens_samples {
+                Some(lens_samples) => lens_samples[i],
+                None if camera.aperture_radius > 0.0 => Vector2f::xy(rng.uniform(), rng.uniform()),
+                None => Vector2f::xy(0.5, 0.5),
+            };
+            let ray = camera.generate_ray(film_sample, lens_sample);
+            let hit = self.closest_hit(&ray);
+            if let Some((point, object)) = hit {
+                covered += 1;
+                if i == 0 {
+                    estimate.object_id = object.id();
+                    estimate.normal = get_normal(&object.surface, &point, &ray.direction);
+                    let distance = point.distance(&camera.position);
+                    if distance <= camera.far {
+                        estimate.depth = distance;
+                    }
+                }
+            }
+            let caustics = photons.map(|photons| (photons, PathKind::Direct));
+            let wavelength = self.spectral.then(|| sample_wavelength(rng.uniform()));
+            let sample = self.compute_color_for_hit(
+                &ray,
+                hit,
+                rng,
+                self.max_depth,
+                self.max_diffuse_bounces,
+                self.max_specular_bounces,
+                None,
+                caustics,
+                wavelength,
+            );
+            let sample = match wavelength {
+                Some(wavelength) => sample * rgb_weight(wavelength),
+                None => sample,
+            };
+            // A single NaN sample would spoil the whole pixel.
+            let sample = sample.sanitize();
+            color += sample;
+            squares += sample * sample;
+        }
+        rng.independent();
+        let n = samples_per_pixel as f32;
+        if samples_per_pixel > 1 {
+            estimate.variance =
+                ((squares - color * color / n) / (n - 1.0)).clamp(0.0, f32::INFINITY);
         }
-        gamma_correct(color / (self.samples_per_pixel as f32)).clamp(0.0, 1.0)
+        (estimate.color, estimate.alpha) = if !transparent {
+            (color / n, 1.0)
+        } else if covered == 0 {
+            (color, 0.0)
+        } else {
+            (color / covered as f32, covered as f32 / n)
+        };
+        estimate
+    }
+
+    /// `bsdf_pdf` is the solid angle pdf with which `ray` was scattered, or
+    /// `None` for camera rays and specular scatters. `caustics` holds the
+    /// photon map, if any, with the kind of path so far. `wavelength` in nm
+    /// is set in spectral mode and selects the ior of dispersive dielectrics.
+    #[allow(clippy::too_many_arguments)]
+    fn compute_color_for_ray(
+        &self,
+        ray: &Ray,
+        rng: &mut Rng,
+        max_depth: u32,
+        max_diffuse_bounces: u32,
+        max_specular_bounces: u32,
+        bsdf_pdf: Option<f32>,
+        caustics: Option<(&PhotonMap, PathKind)>,
+        wavelength: Option<f32>,
+    ) -> Color {
+        let hit = if max_depth == 0 {
+            None
+        } else {
+            self.closest_hit(ray)
+        };
+        self.compute_color_for_hit(
+            ray,
+            hit,
+            rng,
+            max_depth,
+            max_diffuse_bounces,
+            max_specular_bounces,
+            bsdf_pdf,
+            caustics,
+            wavelength,
+        )
     }
 
-    fn compute_color_for_ray(&self, ray: &Ray, rng: &mut Rng, max_depth: u32) -> Color {
+    /// Like `compute_color_for_ray` with the closest `hit` of `ray` already
+    /// found.
+    #[allow(clippy::too_many_arguments)]
+    fn compute_color_for_hit(
+        &self,
+        ray: &Ray,
+        hit: Option<(Vector3f, &Object)>,
+        rng: &mut Rng,
+        max_depth: u32,
+        max_diffuse_bounces: u32,
+        max_specular_bounces: u32,
+        bsdf_pdf: Option<f32>,
+        caustics: Option<(&PhotonMap, PathKind)>,
+        wavelength: Option<f32>,
+    ) -> Color {
         if max_depth == 0 {
+            return match self.termination_color {
+                TerminationColor::Black => Color::rgb(0.0, 0.0, 0.0),
+                TerminationColor::Background => {
+                    self.background.indirect_color(ray) * self.ambient_intensity
+                }
+            };
+        }
+
+        if let Some((intersection_point, object)) = hit {
+            let outward_normal = get_outward_normal(&object.surface, &intersection_point);
+            let front_face = outward_normal.dot(&ray.direction) < 0.0;
+            let normal = if front_face {
+                outward_normal
+            } else {
+                outward_normal * -1.0
+            };
+            let material = object.material_at(&intersection_point);
+            let mut emitted = get_emission(material, &normal, &ray.direction);
+            let light_probability = self.lights.probability(object);
+            if let (Some(bsdf_pdf), true) = (bsdf_pdf, light_probability > 0.0) {
+                let light_pdf = match get_pdf_toward(&object.surface, &ray.origin, &ray.direction) {
+                    Some(pdf) => light_probability * pdf,
+                    None => {
+                        let distance = intersection_point.distance(&ray.origin);
+                        let cos_light = normal.cos_angle(&ray.direction).abs();
+                        let area = get_area(&object.surface).unwrap_or(1.0);
+                        light_probability * distance * distance / (cos_light * area)
+                    }
+                };
+                // Light sampling draws `shadow_samples` directions.
+                let light_pdf = light_pdf * self.shadow_samples.max(1) as f32;
+                emitted = emitted * power_heuristic(bsdf_pdf, light_pdf);
+            }
+            if let Some((_, PathKind::Caustic)) = caustics {
+                if light_probability > 0.0 {
+                    emitted = Color::rgb(0.0, 0.0, 0.0);
+                }
+            }
+            let uv = get_uv(&object.surface, &intersection_point);
+            let geometric_normal = normal;
+            let (material, normal) = match material {
+                Material::NormalMapped { base, normal_map } => {
+                    let tangent = get_tangent(&object.surface, &intersection_point);
+                    let texel = normal_map.sample(&uv);
+                    (base.as_ref(), apply_normal_map(&normal, &tangent, &texel))
+                }
+                material => (material, normal),
+            };
+            let dispersed;
+            let material = match (material, wavelength) {
+                (
+                    Material::Dielectric {
+                        ior,
+                        absorption,
+                        dispersion,
+                    },
+                    Some(wavelength),
+                ) if *dispersion != 0.0 => {
+                    dispersed = Material::Dielectric {
+                        ior: get_ior(*ior, *dispersion, wavelength),
+                        absorption: *absorption,
+                        dispersion: 0.0,
+                    };
+                    &dispersed
+                }
+                (material, _) => material,
+            };
+            let scatter = get_scatter(ray, &normal, front_face, &uv, material, rng);
+            if scatter.attenuation == Color::rgb(0.0, 0.0, 0.0) {
+                return emitted;
+            }
+            let (diffuse_bounces, specular_bounces) = match scatter.specular {
+                true if max_specular_bounces == 0 => return Color::rgb(0.0, 0.0, 0.0),
+                true => (max_diffuse_bounces, max_specular_bounces - 1),
+                false if max_diffuse_bounces == 0 => return Color::rgb(0.0, 0.0, 0.0),
+                false => (max_diffuse_bounces - 1, max_specular_bounces),
+            };
+            if let (Material::Mirror { .. }, Some(probe)) = (material, &self.reflection_probe) {
+                return emitted + probe.lookup(&scatter.direction) * scatter.attenuation;
+            }
+            // Only BSDF samples at vertices with light sampling are MIS weighted.
+            let (direct_light, scatter_pdf) = match material {
+                Material::Lambertian { albedo } => {
+                    let mut direct_light = Color::rgb(0.0, 0.0, 0.0);
+                    if let Background::Environment(environment) = &self.background {
+                        direct_light += self.sample_environment(
+                            environment,
+                            &intersection_point,
+                            &normal,
+                            &geometric_normal,
+                            material,
+                            rng,
+                        ) * self.ambient_intensity;
+                    }
+                    direct_light += self.sample_lights(
+                        object,
+                        &intersection_point,
+                        &normal,
+                        &geometric_normal,
+                        material,
+                        rng,
+                    );
+                    direct_light += self.shade_point_lights(
+                        object,
+                        &intersection_point,
+                        &normal,
+                        &geometric_normal,
+                        rng,
+                    );
+                    if let Some((photons, _)) = caustics {
+                        direct_light += photons.flux_density(&intersection_point) / PI;
+                    }
+                    let samples_lights = !self.lights.is_empty()
+                        || matches!(self.background, Background::Environment(_));
+                    (
+                        direct_light * albedo.sample(&uv),
+                        scatter.pdf.filter(|_| samples_lights),
+                    )
+                }
+                _ => (Color::rgb(0.0, 0.0, 0.0), None),
+            };
+            let caustics = caustics.map(|(photons, kind)| {
+                let kind = match (material, scatter.specular, kind) {
+                    (Material::Lambertian { .. }, _, _) => PathKind::Diffuse,
+                    (_, false, _) => PathKind::Direct,
+                    (_, true, PathKind::Direct) => PathKind::Direct,
+                    (_, true, _) => PathKind::Caustic,
+                };
+                (photons, kind)
+            });
+            let segment_length = intersection_point.distance(&ray.origin);
+            let ray = Ray {
+                origin: offset_origin(&intersection_point, &geometric_normal, &scatter.direction),
+                direction: scatter.direction,
+            };
+            let attenuation = scatter.attenuation;
+            let survival = if self.max_depth - max_depth >= self.rr_min_bounces {
+                attenuation
+                    .r()
+                    .max(attenuation.g())
+                    .max(attenuation.b())
+                    .min(1.0)
+            } else {
+                1.0
+            };
+            let indirect = if survival < 1.0 && rng.uniform() >= survival {
+                Color::rgb(0.0, 0.0, 0.0)
+            } else {
+                let hit = if max_depth > 1 {
+                    self.closest_hit(&ray)
+                } else {
+                    None
+                };
+                // Emitters only emit, so all light of a BSDF sample hitting
+                // an emitter linked out of this object is direct light.
+                let linked_out = scatter_pdf.is_some()
+                    && hit.is_some_and(|(_, light)| !self.lights.illuminates(light, object.id()));
+                if linked_out {
+                    Color::rgb(0.0, 0.0, 0.0)
+                } else {
+                    self.compute_color_for_hit(
+                        &ray,
+                        hit,
+                        rng,
+                        max_depth - 1,
+                        diffuse_bounces,
+                        specular_bounces,
+                        scatter_pdf,
+                        caustics,
+                        wavelength,
+                    ) * (attenuation * (1.0 / survival))
+                }
+            };
+            let radiance = emitted + direct_light + indirect;
+            if front_face {
+                radiance
+            } else {
+                // The segment leading here ran through the medium.
+                radiance * get_transmittance(material, segment_length)
+            }
+        } else {
+            if max_depth == self.max_depth {
+                if let Background::Transparent(_) = self.background {
+                    return Color::rgb(0.0, 0.0, 0.0);
+                }
+            }
+            let weight = match (&self.background, bsdf_pdf) {
+                (Background::Environment(environment), Some(bsdf_pdf)) => {
+                    power_heuristic(bsdf_pdf, environment.pdf(&ray.direction))
+                }
+                _ => 1.0,
+            };
+            let color = if max_diffuse_bounces < self.max_diffuse_bounces {
+                self.background.indirect_color(ray)
+            } else {
+                self.background.color(ray)
+            };
+            color * weight * self.ambient_intensity
+        }
+    }
+
+    fn closest_hit(&self, ray: &Ray) -> Option<(Vector3f, &Object)> {
+        self.rays_traced.fetch_add(1, Ordering::Relaxed);
+        self.accel.closest_hit(&self.scene.objects, ray)
+    }
+
+    /// Whether the ray is blocked before `t_max`, in units of the ray
+    /// direction.
+    fn occluded(&self, ray: &Ray, t_max: f32) -> bool {
+        self.rays_traced.fetch_add(1, Ordering::Relaxed);
+        self.accel.intersect_any(&self.scene.objects, ray, t_max)
+    }
+
+    /// Autofocus: sets the focus distance of `camera` to the depth of the
+    /// nearest hit through the center of pixel (x, y), measured from the
+    /// camera position like the lens. Returns the new focus distance, or
+    /// `None` if nothing is hit, in which case the focus is left unchanged.
+    pub fn focus_camera_on_pixel(&self, camera: &mut Camera, x: u32, y: u32) -> Option<f32> {
+        let ray = camera.back_project(x as f32, y as f32);
+        let (point, _) = self.closest_hit(&ray)?;
+        camera.focus_distance = (point - camera.position).dot(&camera.forward());
+        Some(camera.focus_distance)
+    }
+
+    /// Distance along the ray to the nearest hit, in units of the ray direction.
+    pub fn closest_hit_distance(&self, ray: &Ray) -> Option<f32> {
+        self.closest_hit(ray)
+            .map(|(point, _)| ray.origin.distance(&point) / ray.direction.length())
+    }
+
+    /// Estimates the direct light from the emitters in `lights` linked to
+    /// `object` at a point of its Lambertian `material`, divided by the
+    /// albedo, averaged over `shadow_samples` light samples. The shading
+    /// `normal` enters the cosine terms, shadow rays leave along the
+    /// `geometric_normal`.
+    fn sample_lights(
+        &self,
+        object: &Object,
+        point: &Vector3f,
+        normal: &Vector3f,
+        geometric_normal: &Vector3f,
+        material: &Material,
+        rng: &mut Rng,
+    ) -> Color {
+        let samples = self.shadow_samples.max(1);
+        let mut sum = Color::rgb(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            sum += self.sample_light(object, point, normal, geometric_normal, material, rng);
+        }
+        sum / samples as f32
+    }
+
+    /// One sample of `sample_lights`, weighted against BSDF sampling with
+    /// the power heuristic.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_light(
+        &self,
+        object: &Object,
+        point: &Vector3f,
+        normal: &Vector3f,
+        geometric_normal: &Vector3f,
+        material: &Material,
+        rng: &mut Rng,
+    ) -> Color {
+        let black = Color::rgb(0.0, 0.0, 0.0);
+        let (index, probability) = match self.lights.sample(rng) {
+            Some(sample) => sample,
+            None => return black,
+        };
+        let light = &self.scene.objects[index];
+        if !self.lights.illuminates(light, object.id()) {
+            return black;
+        }
+        // Solid angle pdf of the light sample, from the cone a sphere
+        // subtends or converted from area sampling.
+        let (light_point, light_normal, pdf) = match get_sample_toward(&light.surface, point, rng) {
+            Some((direction, pdf)) => {
+                let ray = Ray {
+                    origin: *point,
+                    direction,
+                };
+                match get_intersection(&light.surface, &ray) {
+                    Some(hit) => (hit, get_outward_normal(&light.surface, &hit), pdf),
+                    None => return black,
+                }
+            }
+            None => match (
+                get_area(&light.surface),
+                get_surface_sample(&light.surface, rng),
+            ) {
+                (Some(area), Some((light_point, light_normal))) => {
+                    let offset = light_point - *point;
+                    let cos_light = light_normal.cos_angle(&offset).abs();
+                    let pdf = offset.squared_length() / (cos_light * area);
+                    (light_point, light_normal, pdf)
+                }
+                _ => return black,
+            },
+        };
+        let offset = light_point - *point;
+        let distance = offset.length();
+        let direction = offset / distance;
+        let cos_theta = normal.dot(&direction);
+        let cos_light = light_normal.dot(&direction);
+        if cos_theta <= 0.0 || cos_light == 0.0 {
+            return black;
+        }
+        let shadow_ray = Ray {
+            origin: offset_origin(point, geometric_normal, &direction),
+            direction,
+        };
+        // Stops short of the light point so that only blockers count,
+        // including the near side of the light itself.
+        if self.occluded(&shadow_ray, distance - 1e-3 * distance.max(1.0)) {
+            return black;
+        }
+        let facing_normal = light_normal * -cos_light.signum();
+        let radiance = get_emission(&light.material, &facing_normal, &direction);
+        let light_pdf = probability * pdf;
+        let bsdf_pdf = get_scatter_pdf(material, &direction, normal).unwrap_or(0.0);
+        let weight = power_heuristic(light_pdf * self.shadow_samples.max(1) as f32, bsdf_pdf);
+        radiance * (weight * cos_theta / (PI * light_pdf))
+    }
+
+    /// Direct light from the `point_lights` linked to `object` at a point
+    /// of its diffuse surface, divided by the albedo. The visible fraction
+    /// of every light is estimated from `shadow_samples` rays.
+    fn shade_point_lights(
+        &self,
+        object: &Object,
+        point: &Vector3f,
+        normal: &Vector3f,
+        geometric_normal: &Vector3f,
+        rng: &mut Rng,
+    ) -> Color {
+        let mut color = Color::rgb(0.0, 0.0, 0.0);
+        for light in &self.point_lights {
+            if !light.illuminates(object.id()) {
+                continue;
+            }
+            let offset = light.position - *point;
+            let distance = offset.length();
+            let cos_theta = normal.dot(&offset) / distance;
+            if cos_theta <= 0.0 {
+                continue;
+            }
+            let samples = if light.radius > 0.0 {
+                self.shadow_samples.max(1)
+            } else {
+                1
+            };
+            let visible = (0..samples)
+                .filter(|_| {
+                    let target = light.sample_position(point, rng);
+                    let direction = (target - *point).normalized();
+                    let shadow_ray = Ray {
+                        origin: offset_origin(point, geometric_normal, &direction),
+                        direction,
+                    };
+                    !self.occluded(&shadow_ray, shadow_ray.origin.distance(&target))
+                })
+                .count();
+            let visibility = visible as f32 / samples as f32;
+            color += light.intensity * (visibility * cos_theta / (PI * distance * distance));
+        }
+        color
+    }
+
+    /// Next-event estimation of the environment at a Lambertian hit, without
+    /// the albedo. Weighted against BSDF sampling with the power heuristic.
+    fn sample_environment(
+        &self,
+        environment: &Environment,
+        point: &Vector3f,
+        normal: &Vector3f,
+        geometric_normal: &Vector3f,
+        material: &Material,
+        rng: &mut Rng,
+    ) -> Color {
+        let (direction, light_pdf) = match environment.sample(rng) {
+            Some(sample) => sample,
+            None => return Color::rgb(0.0, 0.0, 0.0),
+        };
+        let cos_theta = normal.dot(&direction);
+        if cos_theta <= 0.0 {
+            return Color::rgb(0.0, 0.0, 0.0);
+        }
+        let shadow_ray = Ray {
+            origin: offset_origin(point, geometric_normal, &direction),
+            direction,
+        };
+        if self.occluded(&shadow_ray, f32::INFINITY) {
             return Color::rgb(0.0, 0.0, 0.0);
         }
+        let bsdf_pdf = get_scatter_pdf(material, &direction, normal).unwrap_or(0.0);
+        let weight = power_heuristic(light_pdf, bsdf_pdf);
+        environment.lookup_indirect(&direction) * (weight * cos_theta / (PI * light_pdf))
+    }
+}
+
+impl Camera {
+    /// Autofocus on the surface seen through the center of pixel (x, y) of
+    /// the scene of `renderer`, see `Renderer::focus_camera_on_pixel`.
+    pub fn focus_on_pixel(&mut self, renderer: &Renderer, x: u32, y: u32) -> Option<f32> {
+        renderer.focus_camera_on_pixel(self, x, y)
+    }
+}
+
+/// Sums equally sized images as a balanced tree, merging the halves in
+/// parallel. The rounding error grows with the depth of the tree instead of
+/// the number of images.
+fn pairwise_sum(images: &[Image]) -> Option<Image> {
+    match images {
+        [] => None,
+        [image] => Some(image.clone()),
+        _ => {
+            let (left, right) = images.split_at(images.len() / 2);
+            let (left, right) = std::thread::scope(|s| {
+                let left = s.spawn(|| pairwise_sum(left));
+                let right = pairwise_sum(right);
+                (left.join().unwrap(), right)
+            });
+            let mut sum = left?;
+            add_image(&mut sum, &right?);
+            Some(sum)
+        }
+    }
+}
+
+fn add_image(sum: &mut Image, image: &Image) {
+    for (a, b) in sum.data.iter_mut().zip(&image.data) {
+        *a += *b;
+    }
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::{FovAxis, ImageOrigin, Surface};
+    use matrix::Vector2i;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use texture::Texture;
+
+    fn renderer_with(objects: Vec<Object>) -> Renderer {
+        Renderer {
+            camera: Camera::new(
+                Vector3f::xyz(0.0, 0.0, -1.0),
+                Vector3f::xyz(0.0, 1.0, 0.0),
+                0.0,
+                90_f32.to_radians(),
+                FovAxis::Horizontal,
+                Vector2i::xy(8, 8),
+            ),
+            lights: LightList::new(&objects),
+            point_lights: vec![],
+            shadow_samples: 1,
+            scene: Scene { objects },
+            accel: Accel::Linear,
+            background: Background::Uniform(Color::rgb(1.0, 1.0, 1.0)),
+            ambient_intensity: 1.0,
+            tone_map: ToneMap::Linear,
+            max_depth: 8,
+            max_diffuse_bounces: 8,
+            max_specular_bounces: 8,
+            rr_min_bounces: u32::MAX,
+            termination_color: TerminationColor::Black,
+            samples_per_pixel: 1,
+            sample_pattern: SamplePattern::Random,
+            pixel_filter: PixelFilter::Box,
+            render_mode: RenderMode::Uniform,
+            tile_order: TileOrder::Scanline,
+            spectral: false,
+            photon_pass: 0,
+            photon_radius: 0.1,
+            reflection_probe: None,
+            max_rays: None,
+            rays_traced: AtomicU64::new(0),
+        }
+    }
+
+    fn sphere_in_front(material: Material) -> Object {
+        Object::new(
+            Surface::Sphere {
+                center: Vector3f::xyz(0.0, 0.0, -3.0),
+                radius: 1.0,
+            },
+            material,
+        )
+    }
+
+    fn forward_ray() -> Ray {
+        Ray {
+            origin: Vector3f::zeros(),
+            direction: Vector3f::xyz(0.0, 0.0, -1.0),
+        }
+    }
+
+    #[test]
+    fn test_specular_does_not_consume_diffuse_bounces() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Mirror {
+            tint: Color::rgb(0.5, 0.5, 0.5),
+        })]);
+        let mut rng = Rng::new(0);
+        let color =
+            renderer.compute_color_for_ray(&forward_ray(), &mut rng, 2, 0, 8, None, None, None);
+        assert_eq!(color, Color::rgb(0.5, 0.5, 0.5));
+        let color =
+            renderer.compute_color_for_ray(&forward_ray(), &mut rng, 1, 0, 8, None, None, None);
+        assert_eq!(color, Color::rgb(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_diffuse_consumes_bounces() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        let mut rng = Rng::new(0);
+        let color =
+            renderer.compute_color_for_ray(&forward_ray(), &mut rng, 8, 0, 8, None, None, None);
+        assert_eq!(color, Color::rgb(0.0, 0.0, 0.0));
+        let color =
+            renderer.compute_color_for_ray(&forward_ray(), &mut rng, 8, 1, 8, None, None, None);
+        assert_eq!(color, Color::rgb(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_hall_of_mirrors_follows_specular_budget() {
+        let mirror = |min_x: f32| {
+            Object::new(
+                Surface::Box {
+                    min: Vector3f::xyz(min_x, -1.0, -1.0),
+                    max: Vector3f::xyz(min_x + 1.0, 1.0, 1.0),
+                },
+                Material::Mirror {
+                    tint: Color::rgb(1.0, 1.0, 1.0),
+                },
+            )
+        };
+        let mut renderer = renderer_with(vec![mirror(-2.0), mirror(1.0)]);
+        renderer.max_depth = 100;
+        let across = Ray {
+            origin: Vector3f::zeros(),
+            direction: Vector3f::xyz(1.0, 0.0, 0.0),
+        };
+        let mut rng = Rng::new(0);
+        for budget in [5, 50] {
+            renderer.rays_traced.store(0, Ordering::Relaxed);
+            let color =
+                renderer.compute_color_for_ray(&across, &mut rng, 100, 0, budget, None, None, None);
+            // The camera ray and one ray per followed reflection.
+            assert_eq!(
+                renderer.rays_traced.load(Ordering::Relaxed),
+                budget as u64 + 1
+            );
+            assert_eq!(color, Color::rgb(0.0, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn test_environment_sampling_is_unbiased() {
+        let mut image = Image::new(32, 16);
+        image
+            .data
+            .iter_mut()
+            .for_each(|c| *c = Color::rgb(1.0, 1.0, 1.0));
+        image.data[4 * 32 + 1] = Color::rgb(50.0, 50.0, 50.0);
+        let mut renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        renderer.background = Background::Environment(Environment::new(image).unwrap());
+
+        let mut rng = Rng::new(1);
+        let num_samples = 20000;
+        let mut nee = 0.0;
+        let mut bsdf_only = 0.0;
+        for _ in 0..num_samples {
+            nee += renderer
+                .compute_color_for_ray(&forward_ray(), &mut rng, 8, 1, 8, None, None, None)
+                .r();
+            let normal = Vector3f::xyz(0.0, 0.0, 1.0);
+            let scattered = Ray {
+                origin: Vector3f::xyz(0.0, 0.0, -2.0),
+                direction: rng.cosine_direction(&normal),
+            };
+            bsdf_only += 0.5 * renderer.background.color(&scattered).r();
+        }
+        let nee = nee / num_samples as f32;
+        let bsdf_only = bsdf_only / num_samples as f32;
+        assert_approx!(nee, bsdf_only, 0.05 * bsdf_only);
+    }
+
+    #[test]
+    fn test_progressive_accumulates_passes() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        let frames: Vec<Image> = renderer.progressive().take(3).collect();
+        assert_eq!(frames.len(), 3);
+
+        let mut accumulator = Accumulator::new(8, 8);
+        for seed in 0..3 {
+            accumulator.add(
+                &renderer
+                    .render_pass(&renderer.camera, seed, 1, None, None)
+                    .image,
+            );
+        }
+        let expected = renderer.to_display(&accumulator.average());
+        assert_eq!(frames[2].data, expected.data);
+        assert!(frames[0].data.iter().all(|c| c.r() <= 1.0 && c.r() >= 0.0));
+    }
+
+    #[test]
+    fn test_render_animation_writes_frames() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        let directory = std::env::temp_dir().join("cpu_path_tracer_animation");
+        std::fs::create_dir_all(&directory).unwrap();
+        let pattern = directory.join("frame_{}.ppm");
+        renderer
+            .render_animation(3, pattern.to_str().unwrap())
+            .unwrap();
+        for frame in 1..=3 {
+            let filename = directory.join(format!("frame_{:04}.ppm", frame));
+            assert!(filename.exists());
+        }
+        assert!(!directory.join("frame_0004.ppm").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_render_all_writes_buffers() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        let directory = std::env::temp_dir().join("cpu_path_tracer_render_all");
+        std::fs::create_dir_all(&directory).unwrap();
+        let prefix = directory.join("out");
+        renderer.render_all(0, prefix.to_str().unwrap()).unwrap();
+        for buffer in ["beauty", "normal", "depth"] {
+            assert!(directory.join(format!("out_{}.ppm", buffer)).exists());
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let output = renderer.render_output(0);
+        let normals = output.normal_image();
+        let center = 4 * output.image.width as usize + 4;
+        assert_ne!(normals.data[center], Color::rgb(0.0, 0.0, 0.0));
+        assert_eq!(normals.data[0], Color::rgb(0.0, 0.0, 0.0));
+        assert!(output.depth[center] > 2.0 && output.depth[center] < 2.5);
+        assert_eq!(
+            output.depth_image(0.0, 4.0).data[0],
+            Color::rgb(1.0, 1.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn test_camera_near_and_far() {
+        let wall = |z: f32| {
+            Object::new(
+                Surface::Box {
+                    min: Vector3f::xyz(-10.0, -10.0, z - 0.5),
+                    max: Vector3f::xyz(10.0, 10.0, z),
+                },
+                Material::Lambertian {
+                    albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+                },
+            )
+        };
+        let mut renderer = renderer_with(vec![wall(-2.0), wall(-8.0)]);
+        let center = 4 * 8 + 4;
+        let depth = |renderer: &Renderer| renderer.render_output(0).depth[center];
+        let front = depth(&renderer);
+        assert!(front > 2.0 && front < 2.1, "{}", front);
+
+        // The front wall is skipped.
+        renderer.camera.near = 4.0;
+        let back = depth(&renderer);
+        assert!(back > 8.0 && back < 8.4, "{}", back);
+
+        // The back wall is beyond the far plane, like the sky.
+        renderer.camera.far = 6.0;
+        let output = renderer.render_output(0);
+        assert_eq!(output.depth[center], f32::INFINITY);
+        let far_depth = output.depth_image(renderer.camera.near, renderer.camera.far);
+        assert_eq!(far_depth.data[center], Color::rgb(1.0, 1.0, 1.0));
+
+        renderer.camera.near = 0.0;
+        let output = renderer.render_output(0);
+        let gray = output.depth_image(0.0, 6.0).data[center];
+        assert_approx!(gray.r(), front / 6.0, 1e-5);
+        let gray = output.depth_image(0.0, f32::INFINITY).data[center];
+        assert_eq!(gray.r(), 0.0);
+        for (near, expected) in [(1.0, 1.0), (3.0, 0.0)] {
+            let gray = output.depth_image(near, near).data[center];
+            assert_eq!(gray.r(), expected);
+        }
+    }
 
-        let compare = |a: &Vector3f, b: &Vector3f| {
-            (ray.origin.squared_distance(&a))
-                .partial_cmp(&ray.origin.squared_distance(&b))
-                .unwrap_or(std::cmp::Ordering::Equal)
+    #[test]
+    fn test_variance_output() {
+        let gray = || Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
         };
-        if let Some((intersection_point, object)) = self
-            .objects
+        let floor = Object::new(
+            Surface::Box {
+                min: Vector3f::xyz(-10.0, -2.0, -10.0),
+                max: Vector3f::xyz(10.0, -1.0, 10.0),
+            },
+            gray(),
+        );
+        let mut renderer = renderer_with(vec![sphere_in_front(gray()), floor]);
+        renderer.samples_per_pixel = 64;
+        let output = renderer.render_output(0);
+        // The uniform background is the same along every path.
+        assert!(output.variance[0].r() < 1e-6);
+        // Paths from the sphere escape or bounce off the floor.
+        let center = 4 * output.image.width as usize + 4;
+        assert!(output.variance[center].r() > 1e-3);
+    }
+
+    #[test]
+    fn test_termination_color() {
+        let mut renderer = renderer_with(vec![sphere_in_front(Material::Mirror {
+            tint: Color::rgb(1.0, 1.0, 1.0),
+        })]);
+        renderer.background = Background::Uniform(Color::rgb(0.5, 0.5, 0.5));
+        let mut rng = Rng::new(0);
+        // The path is cut off right after the mirror bounce.
+        let black =
+            renderer.compute_color_for_ray(&forward_ray(), &mut rng, 1, 8, 8, None, None, None);
+        assert_eq!(black, Color::rgb(0.0, 0.0, 0.0));
+        renderer.termination_color = TerminationColor::Background;
+        let background =
+            renderer.compute_color_for_ray(&forward_ray(), &mut rng, 1, 8, 8, None, None, None);
+        assert_eq!(background, Color::rgb(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_sh_preview_of_white_furnace() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(1.0, 1.0, 1.0)),
+        })]);
+        let preview = renderer.render_sh_preview();
+        for color in preview.data {
+            assert_approx!(color.r(), 1.0, 1e-2);
+        }
+    }
+
+    #[test]
+    fn test_white_furnace() {
+        let mut renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(1.0, 1.0, 1.0)),
+        })]);
+        renderer.max_diffuse_bounces = 100;
+        renderer.max_depth = 100;
+        renderer.samples_per_pixel = 4;
+        let image = renderer.render_output(0).image;
+        for color in image.data {
+            assert_approx!(color.r(), 1.0, 1e-5);
+            assert_approx!(color.g(), 1.0, 1e-5);
+            assert_approx!(color.b(), 1.0, 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_object_id_output() {
+        let sphere = |x: f32| {
+            Object::new(
+                Surface::Sphere {
+                    center: Vector3f::xyz(x, 0.0, -3.0),
+                    radius: 1.0,
+                },
+                Material::Lambertian {
+                    albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+                },
+            )
+        };
+        let renderer = renderer_with(vec![sphere(-1.5), sphere(1.5)]);
+        let ids: Vec<u32> = renderer.scene.objects.iter().map(|o| o.id()).collect();
+        assert_ne!(ids[0], ids[1]);
+
+        let output = renderer.render_output(0);
+        let width = output.image.width as usize;
+        assert_eq!(output.object_id[4 * width + 1], ids[0]);
+        assert_eq!(output.object_id[4 * width + 6], ids[1]);
+        assert_eq!(output.object_id[0], 0);
+    }
+
+    #[test]
+    fn test_guides_are_noise_free() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.2, 0.4, 0.6)),
+        })]);
+        assert_ne!(
+            renderer.render_output(0).image.data,
+            renderer.render_output(1).image.data
+        );
+        let (albedo, normal) = renderer.render_guides();
+        let (albedo_again, normal_again) = renderer.render_guides();
+        assert_eq!(albedo.data, albedo_again.data);
+        assert_eq!(normal.data, normal_again.data);
+        let center = (albedo.height / 2 * albedo.width + albedo.width / 2) as usize;
+        assert_eq!(albedo.data[center], Color::rgb(0.2, 0.4, 0.6));
+        assert_approx!(normal.data[center].length(), 1.0, 1e-5);
+        assert!(normal.data[center].z() > 0.0);
+        assert_eq!(albedo.data[0], Color::rgb(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_reflection_probe_of_constant_environment() {
+        let mut renderer = renderer_with(vec![sphere_in_front(Material::Mirror {
+            tint: Color::rgb(0.5, 0.5, 0.5),
+        })]);
+        renderer.background = Background::Uniform(Color::rgb(0.25, 0.5, 1.0));
+        let faces = renderer.bake_reflection_probe(Vector3f::xyz(0.0, 0.0, 10.0), 4);
+        for face in &faces {
+            assert_eq!(face.width, 4);
+            assert!(face.data.iter().all(|&c| c == Color::rgb(0.25, 0.5, 1.0)));
+        }
+        let traced = renderer.render_output(0).image;
+        renderer.reflection_probe = Some(ReflectionProbe { faces });
+        assert_eq!(renderer.render_output(0).image.data, traced.data);
+    }
+
+    #[test]
+    fn test_image_origin_flips_vertically() {
+        let mut renderer = renderer_with(vec![Object::new(
+            Surface::Sphere {
+                center: Vector3f::xyz(0.5, 1.0, -3.0),
+                radius: 1.0,
+            },
+            Material::Lambertian {
+                albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+            },
+        )]);
+        let (top_left, _) = renderer.render_guides();
+        renderer.camera.image_origin = ImageOrigin::BottomLeft;
+        let (bottom_left, _) = renderer.render_guides();
+        assert_ne!(top_left, top_left.flip_vertical());
+        assert_eq!(bottom_left, top_left.flip_vertical());
+    }
+
+    #[test]
+    fn test_nan_samples_are_dropped() {
+        let mut renderer = renderer_with(vec![]);
+        renderer.samples_per_pixel = 4;
+        renderer.background = Background::Procedural(Box::new(|ray: &Ray| {
+            if ray.direction.x() < 0.0 {
+                Color::rgb(f32::NAN, 1.0, 1.0)
+            } else {
+                Color::rgb(1.0, 1.0, 1.0)
+            }
+        }));
+        let output = renderer.render_output(0);
+        assert!(output
+            .image
+            .data
             .iter()
-            .filter_map(|object| {
-                get_intersection(&object.surface, ray).and_then(|hit| Some((hit, object)))
-            })
-            .min_by(|(a, _), (b, _)| compare(a, b))
-        {
-            let normal = get_normal(&object.surface, &intersection_point, &ray.direction);
-            let scatter_direction = get_scatter_direction(&normal, &object.material, rng);
-            let attenuation = get_attenuation(&object.material);
+            .flat_map(|c| c.iter())
+            .all(|c| c.is_finite()));
+        assert!(output.image.data.iter().all(|c| c.g() == 1.0));
+    }
+
+    #[test]
+    fn test_spectral_rendering_keeps_white_white() {
+        let mut renderer = renderer_with(vec![sphere_in_front(Material::Dielectric {
+            ior: 1.5,
+            absorption: Vector3f::zeros(),
+            dispersion: 0.0042,
+        })]);
+        renderer.spectral = true;
+        renderer.samples_per_pixel = 1024;
+        let image = renderer.render_output(0).image;
+        let mean = image
+            .data
+            .iter()
+            .fold(Color::rgb(0.0, 0.0, 0.0), |sum, &c| sum + c)
+            / image.data.len() as f32;
+        assert_matrix_approx!(mean, Color::rgb(1.0, 1.0, 1.0), 0.05);
+    }
+
+    #[test]
+    fn test_tile_orders() {
+        let orders = [
+            TileOrder::Scanline,
+            TileOrder::CenterOut,
+            TileOrder::Hilbert,
+        ];
+        for order in orders {
+            let mut tiles = order.tiles(5, 3);
+            tiles.sort_by_key(|&(x, y)| (y, x));
+            assert_eq!(tiles, TileOrder::Scanline.tiles(5, 3));
+        }
+        assert_eq!(TileOrder::CenterOut.tiles(5, 3)[0], (2, 1));
+        // Consecutive tiles of a full Hilbert curve are neighbors.
+        let hilbert = TileOrder::Hilbert.tiles(4, 4);
+        for pair in hilbert.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1);
+        }
+
+        let mut renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        renderer.camera = Camera::new(
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            60_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(2 * TILE_SIZE as i32 + 3, TILE_SIZE as i32 + 1),
+        );
+        let expected = renderer.render_output(3);
+        for order in orders {
+            renderer.tile_order = order;
+            let output = renderer.render_output(3);
+            assert_eq!(output.image, expected.image);
+            assert_eq!(output.sample_counts, expected.sample_counts);
+        }
+    }
+
+    #[test]
+    fn test_focus_camera_on_pixel() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        let mut camera = renderer.camera.clone();
+        let distance = renderer.focus_camera_on_pixel(&mut camera, 4, 4).unwrap();
+        let ray = camera.back_project(4.0, 4.0);
+        let expected = (Vector3f::xyz(0.0, 0.0, -3.0) - ray.origin).length() - 1.0;
+        assert_approx!(distance, expected, 0.1);
+        assert_eq!(camera.focus_distance, distance);
+
+        assert!(renderer.focus_camera_on_pixel(&mut camera, 0, 0).is_none());
+        assert_eq!(camera.focus_distance, distance);
+
+        // Camera rays start at `near`, the focus distance does not.
+        camera.near = 0.5;
+        let clipped = camera.focus_on_pixel(&renderer, 4, 4).unwrap();
+        assert_approx!(clipped, distance, 1e-5);
+        assert_eq!(camera.focus_distance, clipped);
+    }
+
+    #[test]
+    fn test_emitter_seen_directly() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Emissive {
+            radiance: Color::rgb(4.0, 2.0, 1.0),
+            emit_cone_angle: None,
+        })]);
+        let mut rng = Rng::new(0);
+        let color =
+            renderer.compute_color_for_ray(&forward_ray(), &mut rng, 8, 8, 8, None, None, None);
+        assert_eq!(color, Color::rgb(4.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn test_edge_adaptive_sampling() {
+        let ground = Object::new(
+            Surface::Box {
+                min: Vector3f::xyz(-50.0, -2.0, -50.0),
+                max: Vector3f::xyz(50.0, -1.0, 0.0),
+            },
+            Material::Lambertian {
+                albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+            },
+        );
+        let sphere = sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        });
+        let mut renderer = renderer_with(vec![ground, sphere]);
+        renderer.camera = Camera::new(
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            90_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(32, 32),
+        );
+        renderer.max_depth = 2;
+        renderer.render_mode = RenderMode::EdgeAdaptive {
+            edge_sample_factor: 4,
+        };
+        let output = renderer.render_output(0);
+        let counts = &output.sample_counts;
+        let center = 16 * 32 + 16;
+        let ground_interior = 31 * 32;
+        assert_eq!(counts[center], 1);
+        assert_eq!(counts[ground_interior], 1);
+        let edge_pixels = counts.iter().filter(|&&count| count == 4).count();
+        assert!(edge_pixels > 0 && edge_pixels < counts.len() / 2);
+    }
+
+    #[test]
+    fn test_ambient_intensity_scales_background() {
+        let mut renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        renderer.ambient_intensity = 0.25;
+        let mut rng = Rng::new(0);
+        let miss = Ray {
+            origin: Vector3f::zeros(),
+            direction: Vector3f::xyz(0.0, 0.0, 1.0),
+        };
+        let color = renderer.compute_color_for_ray(&miss, &mut rng, 8, 8, 8, None, None, None);
+        assert_eq!(color, Color::rgb(0.25, 0.25, 0.25));
+
+        renderer.ambient_intensity = 0.0;
+        let color =
+            renderer.compute_color_for_ray(&forward_ray(), &mut rng, 8, 8, 8, None, None, None);
+        assert_eq!(color, Color::rgb(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_cancelled_render_stops_early() {
+        let mut renderer = renderer_with(vec![]);
+        let cancel = AtomicBool::new(true);
+        let output = renderer.render_cancellable(0, &cancel);
+        assert!(output.sample_counts.iter().all(|&count| count == 0));
+        assert!(output
+            .image
+            .data
+            .iter()
+            .all(|&c| c == Color::rgb(0.0, 0.0, 0.0)));
+
+        // The background raises the flag partway through the render, which
+        // stops before the next pixel: pixels rendered so far are kept, the
+        // rest stay empty.
+        let cancel = Arc::new(AtomicBool::new(false));
+        let lookups = Arc::new(AtomicU64::new(0));
+        let (flag, counter) = (Arc::clone(&cancel), Arc::clone(&lookups));
+        renderer.background = Background::Procedural(Box::new(move |ray: &Ray| {
+            if counter.fetch_add(1, Ordering::Relaxed) == 20 {
+                flag.store(true, Ordering::Relaxed);
+            }
+            ray.direction * 0.5 + 0.5
+        }));
+        let full = renderer.render_output(0);
+        cancel.store(false, Ordering::Relaxed);
+        lookups.store(0, Ordering::Relaxed);
+        let output = renderer.render_cancellable(0, &cancel);
+        let rendered = output
+            .sample_counts
+            .iter()
+            .take_while(|&&count| count > 0)
+            .count();
+        assert!(rendered > 0 && rendered < output.sample_counts.len());
+        assert_eq!(output.image.data[..rendered], full.image.data[..rendered]);
+        for i in rendered..output.sample_counts.len() {
+            assert_eq!(output.sample_counts[i], 0);
+            assert_eq!(output.image.data[i], Color::rgb(0.0, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn test_interlaced_fields_reconstruct_full_render() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        let full = renderer.render_output(7);
+        let fields = [
+            renderer.render_interlaced(7, 0, 2).unwrap(),
+            renderer.render_interlaced(7, 1, 2).unwrap(),
+        ];
+        let width = full.image.width as usize;
+        for (i, color) in full.image.data.iter().enumerate() {
+            let field = &fields[(i / width) % 2];
+            let other = &fields[1 - (i / width) % 2];
+            assert_eq!(field.image.data[i], *color);
+            assert_eq!(field.sample_counts[i], full.sample_counts[i]);
+            assert_eq!(other.sample_counts[i], 0);
+        }
+    }
+
+    #[test]
+    fn test_interlaced_field_out_of_range() {
+        let renderer = renderer_with(vec![]);
+        assert!(renderer.render_interlaced(0, 2, 2).is_none());
+        assert!(renderer.render_interlaced(0, 0, 0).is_none());
+    }
+
+    #[test]
+    fn test_face_materials() {
+        let glow = |face: usize| Material::Emissive {
+            radiance: Color::rgb(face as f32, 0.0, 0.0),
+            emit_cone_angle: None,
+        };
+        let die = Object::with_face_materials(
+            Vector3f::xyz(-1.0, -1.0, -1.0),
+            Vector3f::xyz(1.0, 1.0, 1.0),
+            [glow(0), glow(1), glow(2), glow(3), glow(4), glow(5)],
+        );
+        let renderer = renderer_with(vec![die]);
+        let mut rng = Rng::new(0);
+        let mut seen_along = |direction: Vector3f| {
             let ray = Ray {
-                origin: intersection_point,
-                direction: scatter_direction,
+                origin: direction * -5.0,
+                direction,
             };
-            return self.compute_color_for_ray(&ray, rng, max_depth - 1) * attenuation;
-        } else {
-            self.ambient_light_color
+            renderer
+                .compute_color_for_ray(&ray, &mut rng, 8, 8, 8, None, None, None)
+                .r()
+        };
+        assert_eq!(seen_along(Vector3f::xyz(-1.0, 0.0, 0.0)), 0.0);
+        assert_eq!(seen_along(Vector3f::xyz(1.0, 0.0, 0.0)), 1.0);
+        assert_eq!(seen_along(Vector3f::xyz(0.0, 0.0, 1.0)), 5.0);
+    }
+
+    #[test]
+    fn test_ray_count_and_budget() {
+        let mut renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        // Narrow enough for every camera ray to hit the sphere.
+        renderer.camera = Camera::new(
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            10_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(2, 2),
+        );
+        renderer.samples_per_pixel = 3;
+        // Per sample a camera ray and a bounce escaping the convex sphere,
+        // and no shadow rays without lights.
+        let output = renderer.render_output(0);
+        assert_eq!(output.rays_traced, 4 * 3 * 2);
+        assert_eq!(renderer.rays_traced.load(Ordering::Relaxed), 24);
+
+        renderer.rays_traced.store(0, Ordering::Relaxed);
+        renderer.max_rays = Some(10);
+        let output = renderer.render_output(0);
+        assert_eq!(output.sample_counts, vec![3, 3, 0, 0]);
+        assert_eq!(output.rays_traced, 12);
+    }
+
+    #[test]
+    fn test_russian_roulette_after_min_bounces() {
+        // Inside a dark sphere, so paths only end by roulette or max_depth.
+        let mut renderer = renderer_with(vec![Object::new(
+            Surface::Sphere {
+                center: Vector3f::zeros(),
+                radius: 5.0,
+            },
+            Material::Lambertian {
+                albedo: Texture::Solid(Color::rgb(0.1, 0.1, 0.1)),
+            },
+        )]);
+        renderer.camera.sensor_size_px = Vector2i::xy(2, 2);
+        renderer.samples_per_pixel = 3;
+        // Per sample `max_depth` segments.
+        let full = 4 * 3 * 8;
+        for rr_min_bounces in [7, u32::MAX] {
+            renderer.rr_min_bounces = rr_min_bounces;
+            assert_eq!(renderer.render_output(0).rays_traced, full);
         }
+        renderer.rr_min_bounces = 2;
+        let rays = renderer.render_output(0).rays_traced;
+        assert!(rays < full);
+        assert!(rays >= 4 * 3 * 3);
+    }
+
+    #[test]
+    fn test_pairwise_sum_matches_naive_sum() {
+        let mut rng = Rng::new(3);
+        let images: Vec<Image> = (0..37)
+            .map(|_| {
+                let mut image = Image::new(4, 2);
+                for color in image.data.iter_mut() {
+                    *color = Color::rgb(rng.uniform(), rng.uniform(), 1.0);
+                }
+                image
+            })
+            .collect();
+        let mut naive = Image::new(4, 2);
+        for image in &images {
+            for (a, b) in naive.data.iter_mut().zip(&image.data) {
+                *a += *b;
+            }
+        }
+        let sum = pairwise_sum(&images).unwrap();
+        for (a, b) in sum.data.iter().zip(&naive.data) {
+            assert!(a.distance(b) < 1e-4);
+        }
+        assert_eq!(sum.data[0].b(), 37.0);
+        assert!(pairwise_sum(&[]).is_none());
+    }
+
+    #[test]
+    fn test_average_render_of_many_seeds() {
+        let renderer = renderer_with(vec![sphere_in_front(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        })]);
+        let seeds: Vec<u32> = (0..64).collect();
+        let average = renderer.average_render(&seeds);
+        let mut expected = Image::new(8, 8);
+        for seed in &seeds {
+            add_image(&mut expected, &renderer.render(*seed));
+        }
+        for (a, b) in average.data.iter().zip(&expected.data) {
+            assert!(a.distance(&(*b / 64.0)) < 1e-5);
+        }
+        let single_thread = renderer.average_render_with_threads(&seeds, 1);
+        for (a, b) in single_thread.data.iter().zip(&average.data) {
+            assert!(a.distance(b) < 1e-5);
+        }
+        assert_eq!(renderer.average_render(&[]), Image::new(8, 8));
+    }
+
+    #[test]
+    fn test_transparent_background() {
+        let mut renderer = renderer_with(vec![]);
+        renderer.background = Background::Transparent(Color::rgb(1.0, 1.0, 1.0));
+        let output = renderer.render_output(0);
+        assert!(output.alpha.iter().all(|&alpha| alpha == 0.0));
+        assert!(output
+            .image
+            .data
+            .iter()
+            .all(|&c| c == Color::rgb(0.0, 0.0, 0.0)));
+
+        // Bounced rays still see the background color.
+        let mut renderer = renderer_with(vec![sphere_in_front(Material::Mirror {
+            tint: Color::rgb(1.0, 1.0, 1.0),
+        })]);
+        renderer.background = Background::Transparent(Color::rgb(0.5, 0.5, 0.5));
+        let output = renderer.render_output(0);
+        let center = 4 * 8 + 4;
+        assert_eq!(output.alpha[center], 1.0);
+        assert_eq!(output.image.data[center], Color::rgb(0.5, 0.5, 0.5));
+        assert_eq!(output.alpha[0], 0.0);
+    }
+
+    #[test]
+    fn test_absorption_grows_with_path_length() {
+        let glass = |radius: f32| {
+            Object::new(
+                Surface::Sphere {
+                    center: Vector3f::xyz(0.0, 0.0, -5.0),
+                    radius,
+                },
+                // An ior of 1 passes rays straight through.
+                Material::Dielectric {
+                    ior: 1.0,
+                    absorption: Vector3f::rgb(0.5, 0.0, 0.0),
+                    dispersion: 0.0,
+                },
+            )
+        };
+        let mut rng = Rng::new(0);
+        let thin = renderer_with(vec![glass(1.0)]);
+        let thin = thin.compute_color_for_ray(&forward_ray(), &mut rng, 8, 8, 8, None, None, None);
+        let thick = renderer_with(vec![glass(2.0)]);
+        let thick =
+            thick.compute_color_for_ray(&forward_ray(), &mut rng, 8, 8, 8, None, None, None);
+        assert!(((thin.r() - (-1.0_f32).exp()).abs()) < 1e-4);
+        assert!(((thick.r() - (-2.0_f32).exp()).abs()) < 1e-4);
+        assert_eq!(thick.g(), 1.0);
+    }
+
+    #[test]
+    fn test_light_sampling_is_unbiased() {
+        let scene = || {
+            vec![
+                Object::new(
+                    Surface::Box {
+                        min: Vector3f::xyz(-10.0, -2.0, -10.0),
+                        max: Vector3f::xyz(10.0, -1.0, 10.0),
+                    },
+                    Material::Lambertian {
+                        albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+                    },
+                ),
+                Object::new(
+                    Surface::Sphere {
+                        center: Vector3f::xyz(0.5, 1.0, 0.0),
+                        radius: 1.0,
+                    },
+                    Material::Emissive {
+                        radiance: Color::rgb(4.0, 4.0, 4.0),
+                        emit_cone_angle: None,
+                    },
+                ),
+            ]
+        };
+        let mut with_lights = renderer_with(scene());
+        let mut without_lights = renderer_with(scene());
+        without_lights.lights = LightList::new(&[]);
+        for renderer in [&mut with_lights, &mut without_lights] {
+            renderer.background = Background::Uniform(Color::rgb(0.0, 0.0, 0.0));
+            renderer.max_diffuse_bounces = 1;
+        }
+        let down = Ray {
+            origin: Vector3f::xyz(0.0, 0.0, 0.0),
+            direction: Vector3f::xyz(0.0, -1.0, 0.0),
+        };
+        let mut rng = Rng::new(1);
+        let mean = |renderer: &Renderer, rng: &mut Rng| {
+            let samples = 20_000;
+            (0..samples)
+                .map(|_| {
+                    renderer
+                        .compute_color_for_ray(&down, rng, 8, 1, 8, None, None, None)
+                        .r()
+                })
+                .sum::<f32>()
+                / samples as f32
+        };
+        let reference = mean(&without_lights, &mut rng);
+        for shadow_samples in [1, 4] {
+            with_lights.shadow_samples = shadow_samples;
+            let sampled = mean(&with_lights, &mut rng);
+            assert!(
+                (sampled - reference).abs() < 0.03 * reference,
+                "{} vs {}",
+                sampled,
+                reference
+            );
+        }
+    }
+
+    #[test]
+    fn test_photon_map_matches_path_traced_caustic() {
+        let renderer = renderer_with(vec![
+            Object::new(
+                Surface::Sphere {
+                    center: Vector3f::xyz(0.0, 5.0, 0.0),
+                    radius: 2.0,
+                },
+                Material::Emissive {
+                    radiance: Color::rgb(1.0, 1.0, 1.0),
+                    emit_cone_angle: None,
+                },
+            ),
+            Object::new(
+                Surface::Box {
+                    min: Vector3f::xyz(-50.0, -1.0, -50.0),
+                    max: Vector3f::xyz(50.0, 0.0, 50.0),
+                },
+                Material::Mirror {
+                    tint: Color::rgb(1.0, 1.0, 1.0),
+                },
+            ),
+            Object::new(
+                Surface::Box {
+                    min: Vector3f::xyz(-50.0, 10.0, -50.0),
+                    max: Vector3f::xyz(50.0, 11.0, 50.0),
+                },
+                Material::Lambertian {
+                    albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+                },
+            ),
+        ]);
+        let mut rng = Rng::new(1);
+        let photons = PhotonMap::new(
+            &renderer.scene.objects,
+            &renderer.accel,
+            &renderer.lights,
+            50_000,
+            1.0,
+            &mut rng,
+        );
+        let up = Ray {
+            origin: Vector3f::xyz(4.0, 9.0, 0.0),
+            direction: Vector3f::xyz(0.0, 1.0, 0.0),
+        };
+        let mut mean = |caustics: Option<(&PhotonMap, PathKind)>| {
+            let samples = 50_000;
+            (0..samples)
+                .map(|_| {
+                    renderer
+                        .compute_color_for_ray(&up, &mut rng, 8, 1, 8, None, caustics, None)
+                        .r()
+                })
+                .sum::<f32>()
+                / samples as f32
+        };
+        let estimated = mean(Some((&photons, PathKind::Direct)));
+        let reference = mean(None);
+        assert!(
+            (estimated - reference).abs() < 0.05 * reference,
+            "{} vs {}",
+            estimated,
+            reference
+        );
+    }
+
+    #[test]
+    fn test_point_light_shadows() {
+        let plate = Object::new(
+            Surface::Box {
+                min: Vector3f::xyz(-10.0, 1.0, -10.0),
+                max: Vector3f::xyz(0.0, 1.1, 10.0),
+            },
+            Material::Lambertian {
+                albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+            },
+        );
+        let mut renderer = renderer_with(vec![plate]);
+        renderer.shadow_samples = 64;
+        let light = PointLight {
+            position: Vector3f::xyz(0.0, 2.0, 0.0),
+            radius: 0.0,
+            intensity: Color::rgb(PI, PI, PI),
+            include: None,
+            exclude: HashSet::new(),
+        };
+        let up = Vector3f::xyz(0.0, 1.0, 0.0);
+        let umbra = Vector3f::xyz(-0.2, 0.0, 0.0);
+        let lit = Vector3f::xyz(0.2, 0.0, 0.0);
+        let mut rng = Rng::new(2);
+        let mut shade = |renderer: &Renderer, point: &Vector3f| {
+            let plate = &renderer.scene.objects[0];
+            renderer
+                .shade_point_lights(plate, point, &up, &up, &mut rng)
+                .r()
+        };
+
+        renderer.point_lights = vec![light];
+        assert_eq!(shade(&renderer, &umbra), 0.0);
+        let offset = renderer.point_lights[0].position - lit;
+        let unshadowed = offset.y() / offset.length().powi(3);
+        assert_approx!(shade(&renderer, &lit), unshadowed, 1e-5);
+
+        renderer.point_lights[0].radius = 0.5;
+        let penumbra = shade(&renderer, &umbra);
+        assert!(penumbra > 0.0 && penumbra < unshadowed, "{}", penumbra);
+    }
+
+    #[test]
+    fn test_no_shadow_acne_with_normal_mapped_shading_normal() {
+        let floor = Object::new(
+            Surface::Box {
+                min: Vector3f::xyz(-1.0, -1.0, -1.0),
+                max: Vector3f::xyz(1.0, 0.0, 1.0),
+            },
+            Material::Lambertian {
+                albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+            },
+        );
+        let mut renderer = renderer_with(vec![floor]);
+        renderer.point_lights = vec![PointLight {
+            position: Vector3f::xyz(2.0, 2.0, 0.0),
+            radius: 0.0,
+            intensity: Color::rgb(PI, PI, PI),
+            include: None,
+            exclude: HashSet::new(),
+        }];
+        let up = Vector3f::xyz(0.0, 1.0, 0.0);
+        // A normal map may tilt the shading normal below the surface while
+        // the light is still in front of it.
+        let shading_normal = Vector3f::xyz(1.0, -0.05, 0.0).normalized();
+        let mut rng = Rng::new(5);
+        let floor = &renderer.scene.objects[0];
+        let light =
+            renderer.shade_point_lights(floor, &Vector3f::zeros(), &shading_normal, &up, &mut rng);
+        assert!(light.r() > 0.0);
+    }
+
+    #[test]
+    fn test_light_linking() {
+        let floor = |x: f32| {
+            Object::new(
+                Surface::Box {
+                    min: Vector3f::xyz(x, -1.0, -1.0),
+                    max: Vector3f::xyz(x + 1.0, 0.0, 1.0),
+                },
+                Material::Lambertian {
+                    albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+                },
+            )
+        };
+        let (excluded, neighbor) = (floor(-1.0), floor(0.0));
+        let mut exclude = HashSet::new();
+        exclude.insert(excluded.id());
+        let mut renderer = renderer_with(vec![excluded, neighbor]);
+        renderer.point_lights = vec![PointLight {
+            position: Vector3f::xyz(0.0, 2.0, 0.0),
+            radius: 0.0,
+            intensity: Color::rgb(PI, PI, PI),
+            include: None,
+            exclude,
+        }];
+        let up = Vector3f::xyz(0.0, 1.0, 0.0);
+        let mut rng = Rng::new(3);
+        let objects = &renderer.scene.objects;
+        let excluded_light = renderer.shade_point_lights(
+            &objects[0],
+            &Vector3f::xyz(-0.5, 0.0, 0.0),
+            &up,
+            &up,
+            &mut rng,
+        );
+        let neighbor_light = renderer.shade_point_lights(
+            &objects[1],
+            &Vector3f::xyz(0.5, 0.0, 0.0),
+            &up,
+            &up,
+            &mut rng,
+        );
+        assert_eq!(excluded_light.r(), 0.0);
+        assert!(neighbor_light.r() > 0.0);
+
+        let mut include = HashSet::new();
+        include.insert(objects[0].id());
+        let light = PointLight {
+            position: Vector3f::xyz(0.0, 2.0, 0.0),
+            radius: 0.0,
+            intensity: Color::rgb(PI, PI, PI),
+            include: Some(include),
+            exclude: HashSet::new(),
+        };
+        assert!(light.illuminates(objects[0].id()));
+        assert!(!light.illuminates(objects[1].id()));
+    }
+
+    #[test]
+    fn test_light_linking_of_emitters() {
+        let floor = |x: f32| {
+            Object::new(
+                Surface::Box {
+                    min: Vector3f::xyz(x, -1.0, -1.0),
+                    max: Vector3f::xyz(x + 1.0, 0.0, 1.0),
+                },
+                Material::Lambertian {
+                    albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+                },
+            )
+        };
+        let lamp = Object::new(
+            Surface::Sphere {
+                center: Vector3f::xyz(0.0, 2.0, 0.0),
+                radius: 0.5,
+            },
+            Material::Emissive {
+                radiance: Color::rgb(4.0, 4.0, 4.0),
+                emit_cone_angle: None,
+            },
+        );
+        let (excluded, neighbor) = (floor(-1.0), floor(0.0));
+        let mut exclude = HashSet::new();
+        exclude.insert(excluded.id());
+        let mut renderer = renderer_with(vec![excluded, neighbor, lamp]);
+        renderer.background = Background::Uniform(Color::rgb(0.0, 0.0, 0.0));
+        renderer
+            .lights
+            .link(&renderer.scene.objects[2], None, exclude);
+        let mut rng = Rng::new(8);
+        // Both light sampling and BSDF samples hitting the lamp count.
+        let mut light_at = |x: f32| {
+            let ray = Ray {
+                origin: Vector3f::xyz(x, 1.0, 0.0),
+                direction: Vector3f::xyz(0.0, -1.0, 0.0),
+            };
+            (0..200)
+                .map(|_| {
+                    renderer
+                        .compute_color_for_ray(&ray, &mut rng, 2, 8, 8, None, None, None)
+                        .r()
+                })
+                .sum::<f32>()
+        };
+        assert_eq!(light_at(-0.5), 0.0);
+        assert!(light_at(0.5) > 0.0);
+    }
+
+    #[test]
+    fn test_shadow_samples_reduce_penumbra_variance() {
+        let light = Object::new(
+            Surface::Sphere {
+                center: Vector3f::xyz(0.0, 3.0, 0.0),
+                radius: 1.0,
+            },
+            Material::Emissive {
+                radiance: Color::rgb(4.0, 4.0, 4.0),
+                emit_cone_angle: None,
+            },
+        );
+        // Covers half of the light as seen from the origin.
+        let plate = Object::new(
+            Surface::Box {
+                min: Vector3f::xyz(-10.0, 1.5, -10.0),
+                max: Vector3f::xyz(0.0, 1.6, 10.0),
+            },
+            Material::Lambertian {
+                albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+            },
+        );
+        let mut renderer = renderer_with(vec![light, plate]);
+        let material = Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.5, 0.5, 0.5)),
+        };
+        let up = Vector3f::xyz(0.0, 1.0, 0.0);
+        let mut rng = Rng::new(6);
+        let mut statistics = |renderer: &Renderer| {
+            let plate = &renderer.scene.objects[1];
+            let values: Vec<f32> = (0..2000)
+                .map(|_| {
+                    renderer
+                        .sample_lights(plate, &Vector3f::zeros(), &up, &up, &material, &mut rng)
+                        .r()
+                })
+                .collect();
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            let variance =
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32;
+            (mean, variance)
+        };
+        let (mean_single, variance_single) = statistics(&renderer);
+        renderer.shadow_samples = 16;
+        let (_, variance_many) = statistics(&renderer);
+        assert!(mean_single > 0.0);
+        assert!(variance_many < 0.25 * variance_single);
+    }
+
+    #[test]
+    fn test_auto_exposure() {
+        let mut renderer = renderer_with(vec![]);
+        renderer.background = Background::Uniform(Color::rgb(4.0, 4.0, 4.0));
+        let bright = renderer.auto_exposure();
+        renderer.background = Background::Uniform(Color::rgb(0.1, 0.1, 0.1));
+        let dim = renderer.auto_exposure();
+        assert!(bright < dim);
+        assert_approx!(bright * 4.0, 0.18, 1e-3);
     }
 }
@@ -1,13 +1,66 @@
-use matrix::Vector3f;
+use matrix::{Vector2f, Vector3f};
+use sampling::concentric_disk;
+use std::f32::consts::PI;
 
 pub struct Rng {
     pub seed: u32,
+    antithetic: Antithetic,
+}
+
+/// Pairing of uniforms for antithetic sampling, see `Rng::record`.
+enum Antithetic {
+    Off,
+    Record(Vec<f32>),
+    /// Recorded uniforms and the index of the next one to mirror.
+    Mirror(Vec<f32>, usize),
 }
 
 impl Rng {
     pub fn new(seed: u32) -> Self {
-        Self { seed }
+        Self {
+            seed,
+            antithetic: Antithetic::Off,
+        }
+    }
+
+    /// Independent generator for pixel (x, y) of the render with `seed`. The
+    /// inputs are hashed so that neighboring pixels do not start from
+    /// similar states, which shows up as structured noise at low sample
+    /// counts.
+    pub fn for_pixel(seed: u32, x: u32, y: u32) -> Self {
+        Self::new(hash(hash(hash(seed) ^ x) ^ y))
+    }
+
+    /// Full generator state, to be restored with `from_state`.
+    pub fn state(&self) -> u32 {
+        self.seed
     }
+
+    pub fn from_state(state: u32) -> Self {
+        Self::new(state)
+    }
+
+    /// Starts recording the uniforms drawn, for `mirror` to replay.
+    pub fn record(&mut self) {
+        self.antithetic = Antithetic::Record(Vec::new());
+    }
+
+    /// Replays the uniforms recorded since `record` as `1 - u` in the same
+    /// order, after which uniforms are independent again. A sample computed
+    /// from the replay is negatively correlated with the recorded one, so
+    /// their average has less variance for monotonic integrands.
+    pub fn mirror(&mut self) {
+        self.antithetic = match std::mem::replace(&mut self.antithetic, Antithetic::Off) {
+            Antithetic::Record(values) => Antithetic::Mirror(values, 0),
+            _ => Antithetic::Off,
+        };
+    }
+
+    /// Stops recording or mirroring.
+    pub fn independent(&mut self) {
+        self.antithetic = Antithetic::Off;
+    }
+
     pub fn unit_sphere(&mut self) -> Vector3f {
         let mut p = Vector3f::xyz(1.0, 1.0, 1.0);
         while p.squared_length() >= 1.0 {
@@ -20,24 +73,133 @@ impl Rng {
         p
     }
 
+    /// Uniformly distributed point on the unit sphere, without rejection.
+    pub fn unit_sphere_surface(&mut self) -> Vector3f {
+        let z = 2.0 * self.uniform() - 1.0;
+        let phi = 2.0 * PI * self.uniform();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Vector3f::xyz(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /// Cosine-weighted direction in the hemisphere around `normal`, with
+    /// solid angle pdf `cos(theta) / pi`.
+    pub fn cosine_direction(&mut self, normal: &Vector3f) -> Vector3f {
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        let u = self.uniform();
+        let phi = 2.0 * PI * self.uniform();
+        let r = u.sqrt();
+        let z = (1.0 - u).max(0.0).sqrt();
+        (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + *normal * z).normalized()
+    }
+
+    /// Uniformly distributed point in the unit disk using Shirley's
+    /// concentric mapping of the square, without rejection.
+    pub fn unit_disk_concentric(&mut self) -> Vector2f {
+        let u = self.uniform();
+        let v = self.uniform();
+        concentric_disk(&Vector2f::xy(u, v))
+    }
+
     pub fn unit_vector(&mut self) -> Vector3f {
-        self.unit_sphere().normalized()
+        self.unit_sphere_surface()
     }
 
     pub fn uniform(&mut self) -> f32 {
-        let mut x = self.seed + 42;
+        let mut x = self.seed.wrapping_add(42);
         x ^= x << 13;
         x ^= x >> 17;
         x ^= x << 5;
         self.seed = x;
-        self.seed as f32 / std::u32::MAX as f32
+        let u = self.seed as f32 / u32::MAX as f32;
+        match &mut self.antithetic {
+            Antithetic::Off => u,
+            Antithetic::Record(values) => {
+                values.push(u);
+                u
+            }
+            Antithetic::Mirror(values, next) => match values.get(*next) {
+                Some(value) => {
+                    *next += 1;
+                    1.0 - value
+                }
+                None => u,
+            },
+        }
     }
 }
 
+/// Murmur3 finalizer, which flips about half of the output bits for any
+/// input bit flipped.
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x85eb_ca6b);
+    x ^= x >> 13;
+    x = x.wrapping_mul(0xc2b2_ae35);
+    x ^ (x >> 16)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_antithetic_pairs_reduce_variance() {
+        let f = |u: f32| u.exp();
+        let trials = 2000;
+        let variance = |antithetic: bool| {
+            let mut rng = Rng::new(5);
+            let estimates: Vec<f32> = (0..trials)
+                .map(|_| {
+                    if antithetic {
+                        rng.record();
+                    }
+                    let a = f(rng.uniform());
+                    if antithetic {
+                        rng.mirror();
+                    }
+                    let b = f(rng.uniform());
+                    rng.independent();
+                    0.5 * (a + b)
+                })
+                .collect();
+            let mean = estimates.iter().sum::<f32>() / trials as f32;
+            assert_approx!(mean, 1_f32.exp() - 1.0, 0.02);
+            estimates.iter().map(|e| (e - mean).powi(2)).sum::<f32>() / trials as f32
+        };
+        let independent = variance(false);
+        let antithetic = variance(true);
+        assert!(
+            antithetic < 0.1 * independent,
+            "{} vs {}",
+            antithetic,
+            independent
+        );
+    }
+
+    #[test]
+    fn test_adjacent_pixels_are_uncorrelated() {
+        // Correlation of the first uniforms of horizontal neighbors.
+        let pairs: Vec<(f32, f32)> = (0..100)
+            .flat_map(|y| (0..100).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                (
+                    Rng::for_pixel(1, x, y).uniform(),
+                    Rng::for_pixel(1, x + 1, y).uniform(),
+                )
+            })
+            .collect();
+        let n = pairs.len() as f32;
+        let mean = |f: &dyn Fn(&(f32, f32)) -> f32| pairs.iter().map(f).sum::<f32>() / n;
+        let (mean_a, mean_b) = (mean(&|p| p.0), mean(&|p| p.1));
+        let covariance = mean(&|p| (p.0 - mean_a) * (p.1 - mean_b));
+        let variance_a = mean(&|p| (p.0 - mean_a).powi(2));
+        let variance_b = mean(&|p| (p.1 - mean_b).powi(2));
+        let correlation = covariance / (variance_a * variance_b).sqrt();
+        assert!(correlation.abs() < 0.05, "correlation {}", correlation);
+        assert_approx!(mean_a, 0.5, 0.02);
+        assert_ne!(Rng::for_pixel(1, 2, 3).seed, Rng::for_pixel(1, 3, 2).seed);
+    }
+
     #[test]
     fn test_unit_sphere() {
         let mut rng = Rng::new(0);
@@ -50,6 +212,84 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_unit_sphere_surface_is_uniform() {
+        let mut rng = Rng::new(0);
+        let num_samples = 100000;
+        let num_bins = 10;
+        let mut mean = Vector3f::zeros();
+        // By Archimedes' theorem each coordinate of a uniform point on the
+        // sphere is uniform in [-1, 1].
+        let mut histograms = vec![vec![0; num_bins]; 3];
+        for _ in 0..num_samples {
+            let point = rng.unit_sphere_surface();
+            assert_approx!(point.length(), 1.0, 1e-5);
+            mean += point / num_samples as f32;
+            for (axis, histogram) in histograms.iter_mut().enumerate() {
+                let t = (point.data[axis][0] + 1.0) / 2.0;
+                histogram[((t * num_bins as f32) as usize).min(num_bins - 1)] += 1;
+            }
+        }
+        assert!(mean.length() < 0.01);
+        let expected = num_samples as f32 / num_bins as f32;
+        for histogram in histograms {
+            for count in histogram {
+                assert_approx!(count as f32, expected, 0.05 * expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unit_disk_concentric_is_uniform() {
+        let mut rng = Rng::new(0);
+        let num_samples = 100000;
+        let mut inner = 0;
+        let mut quadrants = [0; 4];
+        for _ in 0..num_samples {
+            let point = rng.unit_disk_concentric();
+            let squared_length = point.x() * point.x() + point.y() * point.y();
+            assert!(squared_length <= 1.0 + 1e-6);
+            if squared_length < 0.25 {
+                inner += 1;
+            }
+            quadrants[(point.x() < 0.0) as usize * 2 + (point.y() < 0.0) as usize] += 1;
+        }
+        // The disk of radius 0.5 covers a quarter of the area.
+        assert_approx!(inner as f32 / num_samples as f32, 0.25, 0.01);
+        for count in quadrants {
+            assert_approx!(count as f32 / num_samples as f32, 0.25, 0.01);
+        }
+    }
+
+    #[test]
+    fn test_cosine_direction() {
+        let mut rng = Rng::new(0);
+        let normal = Vector3f::xyz(1.0, 2.0, -1.0).normalized();
+        let num_samples = 100000;
+        let mut mean_cos = 0.0;
+        for _ in 0..num_samples {
+            let direction = rng.cosine_direction(&normal);
+            assert_approx!(direction.length(), 1.0, 1e-5);
+            assert!(direction.dot(&normal) >= 0.0);
+            mean_cos += direction.dot(&normal) / num_samples as f32;
+        }
+        // E[cos] = integral of cos^2 / pi over the hemisphere.
+        assert_approx!(mean_cos, 2.0 / 3.0, 0.005);
+    }
+
+    #[test]
+    fn test_restore_state() {
+        let mut rng = Rng::new(5);
+        for _ in 0..10 {
+            rng.uniform();
+        }
+        let mut restored = Rng::from_state(rng.state());
+        for _ in 0..100 {
+            assert_eq!(rng.uniform(), restored.uniform());
+        }
+        assert_eq!(rng.state(), restored.state());
+    }
+
     #[test]
     fn test_uniform() {
         let mut rng = Rng::new(0);
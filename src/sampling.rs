@@ -0,0 +1,232 @@
+use matrix::Vector2f;
+use rng::Rng;
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplePattern {
+    Random,
+    Stratified,
+    CorrelatedMultiJitter,
+    /// Random positions in pairs mirrored through the pixel center. The
+    /// renderer also mirrors all other random decisions of the second path
+    /// of each pair, such as lens and hemisphere samples.
+    Antithetic,
+}
+
+impl SamplePattern {
+    /// Like `generate`, but in random order, for pairing with samples of
+    /// another dimension without correlating their strata.
+    pub fn generate_shuffled(&self, count: u32, rng: &mut Rng) -> Vec<Vector2f> {
+        let samples = self.generate(count, rng);
+        permutation(count, rng)
+            .into_iter()
+            .map(|i| samples[i as usize])
+            .collect()
+    }
+
+    /// Generates `count` sample positions in the unit square.
+    pub fn generate(&self, count: u32, rng: &mut Rng) -> Vec<Vector2f> {
+        let (m, n) = grid_size(count);
+        match self {
+            SamplePattern::Random => (0..count)
+                .map(|_| Vector2f::xy(rng.uniform(), rng.uniform()))
+                .collect(),
+            SamplePattern::Stratified => (0..count)
+                .map(|s| {
+                    let (i, j) = ((s % m) as f32, (s / m) as f32);
+                    Vector2f::xy(
+                        (i + rng.uniform()) / m as f32,
+                        (j + rng.uniform()) / n as f32,
+                    )
+                })
+                .collect(),
+            SamplePattern::Antithetic => {
+                let mut samples = Vec::with_capacity(count as usize);
+                while samples.len() < count as usize {
+                    let sample = Vector2f::xy(rng.uniform(), rng.uniform());
+                    samples.push(sample);
+                    samples.push(Vector2f::xy(1.0 - sample.x(), 1.0 - sample.y()));
+                }
+                samples.truncate(count as usize);
+                samples
+            }
+            SamplePattern::CorrelatedMultiJitter => {
+                // Kensler, "Correlated Multi-Jittered Sampling", 2013. The same
+                // permutation is shared by all columns (rows), which keeps the
+                // canonical N-rooks arrangement while decorrelating x and y.
+                let permutation_x = permutation(m, rng);
+                let permutation_y = permutation(n, rng);
+                (0..count)
+                    .map(|s| {
+                        let (i, j) = (s % m, s / m);
+                        let sx = permutation_x[i as usize] as f32;
+                        let sy = permutation_y[j as usize] as f32;
+                        Vector2f::xy(
+                            (i as f32 + (sy + rng.uniform()) / n as f32) / m as f32,
+                            (j as f32 + (sx + rng.uniform()) / m as f32) / n as f32,
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Distribution of the film position of a sample around the pixel center.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFilter {
+    /// Uniform over the pixel, which blurs more than needed.
+    Box,
+    /// Triangular with a radius of one pixel, sharper than `Box` at the
+    /// same amount of aliasing.
+    Tent,
+}
+
+impl PixelFilter {
+    /// Maps a sample of the unit square to an offset from the pixel
+    /// center in pixels. Keeps strata and mirrored pairs intact.
+    pub fn offset(&self, sample: &Vector2f) -> Vector2f {
+        match self {
+            PixelFilter::Box => Vector2f::xy(sample.x() - 0.5, sample.y() - 0.5),
+            PixelFilter::Tent => Vector2f::xy(tent(sample.x()), tent(sample.y())),
+        }
+    }
+}
+
+/// Maps a uniform `u` in [0, 1] to the triangular distribution on [-1, 1]
+/// by inverting its cumulative distribution.
+pub fn tent(u: f32) -> f32 {
+    let u = 2.0 * u;
+    if u < 1.0 {
+        u.sqrt() - 1.0
+    } else {
+        1.0 - (2.0 - u).max(0.0).sqrt()
+    }
+}
+
+/// Maps a point of the unit square to the unit disk with Shirley's
+/// concentric mapping, which keeps strata intact and (0.5, 0.5) at the
+/// center.
+pub fn concentric_disk(sample: &Vector2f) -> Vector2f {
+    let a = 2.0 * sample.x() - 1.0;
+    let b = 2.0 * sample.y() - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return Vector2f::xy(0.0, 0.0);
+    }
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    Vector2f::xy(r * phi.cos(), r * phi.sin())
+}
+
+/// Returns the most square `m` x `n` grid with exactly `count` cells and
+/// `m >= n`, so that every stratum receives a sample. Prime counts give a
+/// single row.
+fn grid_size(count: u32) -> (u32, u32) {
+    let count = count.max(1);
+    let m = ((count as f32).sqrt().ceil() as u32..=count)
+        .find(|m| count.is_multiple_of(*m))
+        .unwrap_or(count);
+    (m, count / m)
+}
+
+fn permutation(len: u32, rng: &mut Rng) -> Vec<u32> {
+    let mut result: Vec<u32> = (0..len).collect();
+    for i in (1..result.len()).rev() {
+        let j = ((rng.uniform() * (i + 1) as f32) as usize).min(i);
+        result.swap(i, j);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmj_covers_every_stratum_once() {
+        let mut rng = Rng::new(3);
+        let m = 4;
+        let count = m * m;
+        let samples = SamplePattern::CorrelatedMultiJitter.generate(count, &mut rng);
+        assert_eq!(samples.len(), count as usize);
+
+        let mut cells = vec![0; count as usize];
+        let mut columns = vec![0; count as usize];
+        let mut rows = vec![0; count as usize];
+        for sample in &samples {
+            let cell_x = (sample.x() * m as f32) as usize;
+            let cell_y = (sample.y() * m as f32) as usize;
+            cells[cell_y * m as usize + cell_x] += 1;
+            columns[(sample.x() * count as f32) as usize] += 1;
+            rows[(sample.y() * count as f32) as usize] += 1;
+        }
+        assert!(cells.iter().all(|&c| c == 1));
+        assert!(columns.iter().all(|&c| c == 1));
+        assert!(rows.iter().all(|&c| c == 1));
+    }
+
+    #[test]
+    fn test_non_square_counts_cover_every_stratum() {
+        let mut rng = Rng::new(4);
+        for count in [2, 3, 5, 6, 8, 12] {
+            let (m, n) = grid_size(count);
+            assert_eq!(m * n, count);
+            for pattern in [
+                SamplePattern::Stratified,
+                SamplePattern::CorrelatedMultiJitter,
+            ] {
+                let mut cells = vec![0; count as usize];
+                for sample in pattern.generate(count, &mut rng) {
+                    let cell_x = (sample.x() * m as f32) as usize;
+                    let cell_y = (sample.y() * n as f32) as usize;
+                    cells[cell_y * m as usize + cell_x] += 1;
+                }
+                assert!(cells.iter().all(|&c| c == 1), "{:?} {}", pattern, count);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tent_density_is_triangular() {
+        let mut rng = Rng::new(5);
+        let count = 200_000;
+        let bins = 8;
+        let mut histogram = vec![0; bins];
+        for _ in 0..count {
+            let x = tent(rng.uniform());
+            assert!((-1.0..=1.0).contains(&x));
+            histogram[(((x + 1.0) / 2.0 * bins as f32) as usize).min(bins - 1)] += 1;
+        }
+        for (i, &n) in histogram.iter().enumerate() {
+            // Integral of 1 - |x| over the bin.
+            let (a, b) = (
+                -1.0 + 2.0 * i as f32 / bins as f32,
+                -1.0 + 2.0 * (i + 1) as f32 / bins as f32,
+            );
+            let cdf = |x: f32| {
+                if x < 0.0 {
+                    (1.0 + x).powi(2) / 2.0
+                } else {
+                    1.0 - (1.0 - x).powi(2) / 2.0
+                }
+            };
+            assert_approx!(n as f32 / count as f32, cdf(b) - cdf(a), 0.005);
+        }
+        assert_eq!(tent(0.5), 0.0);
+        assert_approx!(tent(0.1), -tent(0.9), 1e-6);
+    }
+
+    #[test]
+    fn test_stratified_samples_in_unit_square() {
+        let mut rng = Rng::new(3);
+        let samples = SamplePattern::Stratified.generate(10, &mut rng);
+        assert_eq!(samples.len(), 10);
+        for sample in samples {
+            assert!((0.0..=1.0).contains(&sample.x()));
+            assert!((0.0..=1.0).contains(&sample.y()));
+        }
+    }
+}
@@ -0,0 +1,689 @@
+use error::Error;
+use geometry::{get_bounds, Material, Object, Surface, Transform};
+use image::Color;
+use matrix::{Vector2f, Vector3f};
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use texture::Texture;
+
+pub struct Scene {
+    pub objects: Vec<Object>,
+}
+
+impl Scene {
+    /// Union of the bounding boxes of all objects as (min, max) corners.
+    /// Infinite planes are left out, a scene without other objects has an
+    /// empty box at the origin.
+    pub fn bounds(&self) -> (Vector3f, Vector3f) {
+        self.finite_bounds()
+            .unwrap_or((Vector3f::zeros(), Vector3f::zeros()))
+    }
+
+    /// Sphere enclosing `bounds`, as (center, radius).
+    pub fn bounding_sphere(&self) -> Option<(Vector3f, f32)> {
+        let (min, max) = self.finite_bounds()?;
+        Some(((min + max) * 0.5, min.distance(&max) * 0.5))
+    }
+
+    fn finite_bounds(&self) -> Option<(Vector3f, Vector3f)> {
+        self.objects
+            .iter()
+            .filter(|object| !matches!(object.surface, Surface::Plane { .. }))
+            .map(|object| get_bounds(&object.surface))
+            .reduce(|(min_a, max_a), (min_b, max_b)| {
+                (min_a.component_min(&min_b), max_a.component_max(&max_b))
+            })
+    }
+
+    /// Moves the objects of `other` into this scene, placed by `transform`
+    /// if given. Transformed objects become instances, which light sampling
+    /// cannot sample yet, so transformed emitters are only found by
+    /// scattered rays.
+    pub fn extend(&mut self, other: Scene, transform: Option<&Transform>) {
+        for mut object in other.objects {
+            if let Some(transform) = transform {
+                let surface = mem::replace(&mut object.surface, Surface::Compound(Vec::new()));
+                object.surface = match surface {
+                    Surface::Instance {
+                        geometry,
+                        transform: inner,
+                    } => Surface::Instance {
+                        geometry,
+                        transform: inner.then(transform),
+                    },
+                    surface => Surface::Instance {
+                        geometry: Arc::new(surface),
+                        transform: *transform,
+                    },
+                };
+            }
+            self.objects.push(object);
+        }
+    }
+
+    /// Parses scene files holding one `shape(...) material(...)` pair in the
+    /// syntax of `scene!` per line. Text after `#` is ignored.
+    ///
+    /// Lines `material NAME material(...)` name a material, which later
+    /// objects can use by writing `shape(...) NAME`.
+    pub fn parse(text: &str) -> Result<Scene, Error> {
+        Scene::parse_with_library(text, MaterialLibrary::default())
+    }
+
+    /// Like `parse`, with objects also referring to the materials of
+    /// `library`, for example loaded from a `.mtl` file.
+    pub fn parse_with_library(text: &str, mut library: MaterialLibrary) -> Result<Scene, Error> {
+        let mut objects = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || Error::Parse {
+                line: number + 1,
+                msg: format!("cannot parse `{}`", line),
+            };
+            if let Some(definition) = line.strip_prefix("material ") {
+                let (name, material) = definition
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(error)?;
+                let material = parse_material(material).ok_or_else(error)?;
+                library
+                    .materials
+                    .insert(name.to_string(), Arc::new(material));
+                continue;
+            }
+            let (surface, material) = parse_surface(line).ok_or_else(error)?;
+            let material = if material.contains('(') {
+                Arc::new(parse_material(material).ok_or_else(error)?)
+            } else {
+                library
+                    .materials
+                    .get(material)
+                    .cloned()
+                    .ok_or_else(|| Error::Parse {
+                        line: number + 1,
+                        msg: format!("unknown material `{}`", material),
+                    })?
+            };
+            objects.push(Object::new(surface, material));
+        }
+        Ok(Scene { objects })
+    }
+
+    /// Parses the vertices, texture coordinates and faces of a Wavefront
+    /// `.obj` file into one triangle object per triangle, splitting larger
+    /// faces into fans. `usemtl` selects a material of `library`, faces
+    /// before the first one are light gray. Other statements are ignored.
+    pub fn parse_obj(text: &str, library: &MaterialLibrary) -> Result<Scene, Error> {
+        let mut positions = Vec::new();
+        let mut uvs = Vec::new();
+        let mut material = Arc::new(Material::Lambertian {
+            albedo: Texture::Solid(Color::rgb(0.8, 0.8, 0.8)),
+        });
+        let mut objects = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let error = || Error::Parse {
+                line: number + 1,
+                msg: format!("cannot parse `{}`", line),
+            };
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("v") => {
+                    let values = parse_words(words).ok_or_else(error)?;
+                    match values.as_slice() {
+                        // An optional w component is ignored.
+                        &[x, y, z] | &[x, y, z, _] => positions.push(Vector3f::xyz(x, y, z)),
+                        _ => return Err(error()),
+                    }
+                }
+                Some("vt") => {
+                    let values = parse_words(words).ok_or_else(error)?;
+                    match values.as_slice() {
+                        &[u, v] | &[u, v, _] => uvs.push(Vector2f::xy(u, v)),
+                        _ => return Err(error()),
+                    }
+                }
+                Some("usemtl") => {
+                    let name = words.next().ok_or_else(error)?;
+                    material =
+                        library
+                            .materials
+                            .get(name)
+                            .cloned()
+                            .ok_or_else(|| Error::Parse {
+                                line: number + 1,
+                                msg: format!("unknown material `{}`", name),
+                            })?;
+                }
+                Some("f") => {
+                    // Each corner is `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+                    let corners = words
+                        .map(|word| {
+                            let mut indices = word.split('/');
+                            let position = obj_index(indices.next()?, &positions)?;
+                            let uv = match indices.next() {
+                                Some(index) if !index.is_empty() => *obj_index(index, &uvs)?,
+                                _ => Vector2f::zeros(),
+                            };
+                            Some((*position, uv))
+                        })
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(error)?;
+                    if corners.len() < 3 {
+                        return Err(error());
+                    }
+                    for i in 1..corners.len() - 1 {
+                        let [a, b, c] = [corners[0], corners[i], corners[i + 1]];
+                        objects.push(Object::new(
+                            Surface::Triangle {
+                                vertices: [a.0, b.0, c.0],
+                                uvs: [a.1, b.1, c.1],
+                            },
+                            Arc::clone(&material),
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(Scene { objects })
+    }
+
+    /// Counts and sizes of the scene, e.g. to estimate the render time.
+    pub fn stats(&self) -> SceneStats {
+        let (min, max) = self.bounds();
+        SceneStats {
+            objects: self.objects.len(),
+            triangles: self
+                .objects
+                .iter()
+                .map(|object| count_triangles(&object.surface))
+                .sum(),
+            memory_bytes: self
+                .objects
+                .iter()
+                .map(|object| {
+                    let faces = object
+                        .face_materials
+                        .as_ref()
+                        .map_or(0, |faces| mem::size_of_val(faces.as_ref()));
+                    mem::size_of::<Object>() + heap_size(&object.surface) + faces
+                })
+                .sum(),
+            bounds_size: max - min,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SceneStats {
+    pub objects: usize,
+    /// Triangles of all objects, including those inside compounds and
+    /// instances.
+    pub triangles: usize,
+    /// Estimated size of the objects, not counting textures or the
+    /// acceleration structure. Instanced geometry is counted once per
+    /// instance.
+    pub memory_bytes: usize,
+    /// Extent of `Scene::bounds`.
+    pub bounds_size: Vector3f,
+}
+
+impl fmt::Display for SceneStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} objects, {} triangles, {:.1} KiB, bounds {:.3} x {:.3} x {:.3}",
+            self.objects,
+            self.triangles,
+            self.memory_bytes as f32 / 1024.0,
+            self.bounds_size.x(),
+            self.bounds_size.y(),
+            self.bounds_size.z()
+        )
+    }
+}
+
+fn count_triangles(surface: &Surface) -> usize {
+    match surface {
+        Surface::Triangle { .. } => 1,
+        Surface::Compound(surfaces) => surfaces.iter().map(count_triangles).sum(),
+        Surface::Instance { geometry, .. } => count_triangles(geometry),
+        _ => 0,
+    }
+}
+
+/// Bytes allocated by `surface` beyond its own size.
+fn heap_size(surface: &Surface) -> usize {
+    match surface {
+        Surface::Compound(surfaces) => surfaces
+            .iter()
+            .map(|surface| mem::size_of::<Surface>() + heap_size(surface))
+            .sum(),
+        Surface::Instance { geometry, .. } => mem::size_of::<Surface>() + heap_size(geometry),
+        _ => 0,
+    }
+}
+
+/// Resolves a 1-based or, if negative, relative Wavefront index.
+fn obj_index<'a, T>(text: &str, elements: &'a [T]) -> Option<&'a T> {
+    let index: i64 = text.parse().ok()?;
+    let index = if index < 0 {
+        elements.len() as i64 + index
+    } else {
+        index - 1
+    };
+    if index < 0 {
+        return None;
+    }
+    elements.get(index as usize)
+}
+
+/// Materials by name, shared by the objects of a scene file. Objects refer
+/// to the same allocation instead of holding copies.
+#[derive(Default)]
+pub struct MaterialLibrary {
+    pub materials: HashMap<String, Arc<Material>>,
+}
+
+impl MaterialLibrary {
+    /// Parses the diffuse color `Kd` of every `newmtl` in a Wavefront `.mtl`
+    /// file into a Lambertian material. Other statements are ignored.
+    pub fn parse_mtl(text: &str) -> Result<MaterialLibrary, Error> {
+        let mut library = MaterialLibrary::default();
+        let mut current = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let error = || Error::Parse {
+                line: number + 1,
+                msg: format!("cannot parse `{}`", line),
+            };
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("newmtl") => {
+                    let name = words.next().ok_or_else(error)?.to_string();
+                    library.materials.insert(
+                        name.clone(),
+                        Arc::new(Material::Lambertian {
+                            albedo: Texture::Solid(Color::rgb(0.8, 0.8, 0.8)),
+                        }),
+                    );
+                    current = Some(name);
+                }
+                Some("Kd") => {
+                    let values = parse_words(words).ok_or_else(error)?;
+                    let albedo = match values.as_slice() {
+                        &[r, g, b] => Color::rgb(r, g, b),
+                        _ => return Err(error()),
+                    };
+                    let name = current.as_ref().ok_or_else(error)?;
+                    library.materials.insert(
+                        name.clone(),
+                        Arc::new(Material::Lambertian {
+                            albedo: Texture::Solid(albedo),
+                        }),
+                    );
+                }
+                _ => {}
+            }
+        }
+        Ok(library)
+    }
+}
+
+/// Splits `shape(...) rest` into the surface and the trimmed rest.
+fn parse_surface(line: &str) -> Option<(Surface, &str)> {
+    let (shape, rest) = line.split_once('(')?;
+    let (shape_args, rest) = rest.split_once(')')?;
+    let groups = shape_args
+        .split(';')
+        .map(parse_numbers)
+        .collect::<Option<Vec<_>>>()?;
+    let vector = |v: &[f32]| Vector3f::xyz(v[0], v[1], v[2]);
+    let surface = match (shape.trim(), groups.as_slice()) {
+        ("sphere", [center, radius]) if center.len() == 3 && radius.len() == 1 => Surface::Sphere {
+            center: vector(center),
+            radius: radius[0],
+        },
+        ("cuboid", [min, max]) if min.len() == 3 && max.len() == 3 => Surface::Box {
+            min: vector(min),
+            max: vector(max),
+        },
+        _ => return None,
+    };
+    Some((surface, rest.trim()))
+}
+
+/// Parses `material(r, g, b)`.
+fn parse_material(text: &str) -> Option<Material> {
+    let (material, rest) = text.split_once('(')?;
+    let (color, rest) = rest.split_once(')')?;
+    if !rest.trim().is_empty() {
+        return None;
+    }
+    let color = match parse_numbers(color)?.as_slice() {
+        &[r, g, b] => Color::rgb(r, g, b),
+        _ => return None,
+    };
+    let material = match material.trim() {
+        "lambertian" => Material::Lambertian {
+            albedo: Texture::Solid(color),
+        },
+        "mirror" => Material::Mirror { tint: color },
+        "emissive" => Material::Emissive {
+            radiance: color,
+            emit_cone_angle: None,
+        },
+        _ => return None,
+    };
+    Some(material)
+}
+
+fn parse_numbers(text: &str) -> Option<Vec<f32>> {
+    text.split(',').map(|n| n.trim().parse().ok()).collect()
+}
+
+fn parse_words<'a, I: Iterator<Item = &'a str>>(words: I) -> Option<Vec<f32>> {
+    words.map(|word| word.parse().ok()).collect()
+}
+
+/// Builds a `Scene` from a compact list of `shape(...) material(...)` pairs:
+///
+/// ```
+/// # #[macro_use] extern crate cpu_path_tracer;
+/// # fn main() {
+/// let scene = scene! {
+///     sphere(0, 0, -1; 0.5) lambertian(0.1, 0.2, 0.5)
+///     cuboid(-1, -1, -3; 1, 1, -2) mirror(0.9, 0.9, 0.9)
+///     sphere(0, 3, -2; 0.5) emissive(4, 4, 4)
+/// };
+/// assert_eq!(scene.objects.len(), 3);
+/// # }
+/// ```
+///
+/// Spheres take a center and radius, cuboids their min and max corners.
+#[macro_export]
+macro_rules! scene {
+    (@surface sphere $x:expr, $y:expr, $z:expr; $radius:expr) => {
+        $crate::geometry::Surface::Sphere {
+            center: $crate::matrix::Vector3f::xyz($x as f32, $y as f32, $z as f32),
+            radius: $radius as f32,
+        }
+    };
+    (@surface cuboid $x0:expr, $y0:expr, $z0:expr; $x1:expr, $y1:expr, $z1:expr) => {
+        $crate::geometry::Surface::Box {
+            min: $crate::matrix::Vector3f::xyz($x0 as f32, $y0 as f32, $z0 as f32),
+            max: $crate::matrix::Vector3f::xyz($x1 as f32, $y1 as f32, $z1 as f32),
+        }
+    };
+    (@color $r:expr, $g:expr, $b:expr) => {
+        $crate::image::Color::rgb($r as f32, $g as f32, $b as f32)
+    };
+    (@material lambertian $($color:tt)*) => {
+        $crate::geometry::Material::Lambertian {
+            albedo: $crate::texture::Texture::Solid($crate::scene!(@color $($color)*)),
+        }
+    };
+    (@material mirror $($color:tt)*) => {
+        $crate::geometry::Material::Mirror {
+            tint: $crate::scene!(@color $($color)*),
+        }
+    };
+    (@material emissive $($color:tt)*) => {
+        $crate::geometry::Material::Emissive {
+            radiance: $crate::scene!(@color $($color)*),
+            emit_cone_angle: None,
+        }
+    };
+    ($($shape:ident ($($surface:tt)*) $material:ident ($($color:tt)*))*) => {
+        $crate::scene::Scene {
+            objects: vec![$(
+                $crate::geometry::Object::new(
+                    $crate::scene!(@surface $shape $($surface)*),
+                    $crate::scene!(@material $material $($color)*),
+                )
+            ),*],
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extend() {
+        let sphere_scene = || Scene {
+            objects: vec![Object::new(
+                Surface::Sphere {
+                    center: Vector3f::xyz(1.0, 0.0, 0.0),
+                    radius: 0.5,
+                },
+                Material::Lambertian {
+                    albedo: Texture::Solid(Vector3f::xyz(0.5, 0.5, 0.5)),
+                },
+            )],
+        };
+        let mut scene = sphere_scene();
+        scene.extend(
+            sphere_scene(),
+            Some(&Transform::translation(&Vector3f::xyz(0.0, 3.0, 0.0))),
+        );
+        assert_eq!(scene.objects.len(), 2);
+        let centers: Vec<Vector3f> = scene
+            .objects
+            .iter()
+            .map(|object| {
+                let (min, max) = get_bounds(&object.surface);
+                (min + max) * 0.5
+            })
+            .collect();
+        assert_matrix_approx!(centers[0], Vector3f::xyz(1.0, 0.0, 0.0), 1e-5);
+        assert_matrix_approx!(centers[1], Vector3f::xyz(1.0, 3.0, 0.0), 1e-5);
+
+        // Transforms of instances compose.
+        let mut twice = Scene { objects: vec![] };
+        twice.extend(
+            scene,
+            Some(&Transform::translation(&Vector3f::xyz(0.0, 0.0, -2.0))),
+        );
+        let (min, max) = get_bounds(&twice.objects[1].surface);
+        assert_matrix_approx!((min + max) * 0.5, Vector3f::xyz(1.0, 3.0, -2.0), 1e-5);
+    }
+
+    #[test]
+    fn test_bounding_sphere() {
+        let sphere = |x: f32| {
+            Object::new(
+                Surface::Sphere {
+                    center: Vector3f::xyz(x, 0.0, 0.0),
+                    radius: 1.0,
+                },
+                Material::Lambertian {
+                    albedo: Texture::Solid(Vector3f::xyz(0.5, 0.5, 0.5)),
+                },
+            )
+        };
+        let scene = Scene {
+            objects: vec![sphere(-2.0), sphere(2.0)],
+        };
+        let (center, radius) = scene.bounding_sphere().unwrap();
+        assert_eq!(center, Vector3f::zeros());
+        assert_approx!(radius, 11_f32.sqrt(), 1e-6);
+        assert!(Scene { objects: vec![] }.bounding_sphere().is_none());
+    }
+
+    #[test]
+    fn test_bounds() {
+        let sphere = |center: Vector3f, radius: f32| {
+            Object::new(
+                Surface::Sphere { center, radius },
+                Material::Lambertian {
+                    albedo: Texture::Solid(Vector3f::xyz(0.5, 0.5, 0.5)),
+                },
+            )
+        };
+        let mut scene = Scene {
+            objects: vec![
+                sphere(Vector3f::xyz(-2.0, 0.0, 0.0), 1.0),
+                sphere(Vector3f::xyz(1.0, 3.0, -1.0), 0.5),
+                sphere(Vector3f::xyz(0.0, -1.0, 2.0), 2.0),
+            ],
+        };
+        let expected = (
+            Vector3f::xyz(-3.0, -3.0, -1.5),
+            Vector3f::xyz(2.0, 3.5, 4.0),
+        );
+        assert_eq!(scene.bounds(), expected);
+        scene.objects.push(Object::new(
+            Surface::Plane {
+                point: Vector3f::zeros(),
+                normal: Vector3f::xyz(0.0, 1.0, 0.0),
+            },
+            Material::Lambertian {
+                albedo: Texture::Solid(Vector3f::xyz(0.5, 0.5, 0.5)),
+            },
+        ));
+        assert_eq!(scene.bounds(), expected);
+    }
+
+    #[test]
+    fn test_scene_macro() {
+        let scene = scene! {
+            sphere(0, 0, -1; 0.5) lambertian(0.1, 0.2, 0.5)
+            cuboid(-1, -2, -3; 1, -1.5, 0) mirror(1, 1, 1)
+        };
+        let manual = [
+            (
+                Surface::Sphere {
+                    center: Vector3f::xyz(0.0, 0.0, -1.0),
+                    radius: 0.5,
+                },
+                Material::Lambertian {
+                    albedo: Texture::Solid(Vector3f::rgb(0.1, 0.2, 0.5)),
+                },
+            ),
+            (
+                Surface::Box {
+                    min: Vector3f::xyz(-1.0, -2.0, -3.0),
+                    max: Vector3f::xyz(1.0, -1.5, 0.0),
+                },
+                Material::Mirror {
+                    tint: Vector3f::rgb(1.0, 1.0, 1.0),
+                },
+            ),
+        ];
+        assert_eq!(scene.objects.len(), manual.len());
+        for (object, (surface, material)) in scene.objects.iter().zip(manual.iter()) {
+            assert_eq!(&object.surface, surface);
+            assert_eq!(object.material.as_ref(), material);
+        }
+    }
+
+    #[test]
+    fn test_named_materials() {
+        let mtl = "newmtl red\n\
+                   Kd 0.8 0.1 0.1\n\
+                   Ns 10 # ignored\n\
+                   newmtl gray\n";
+        let library = MaterialLibrary::parse_mtl(mtl).unwrap();
+        let text = "material light emissive(4, 4, 4)\n\
+                    sphere(0, 0, -1; 0.5) red\n\
+                    sphere(0, 3, -1; 0.5) light\n\
+                    sphere(0, 0, -3; 1) gray\n";
+        let scene = Scene::parse_with_library(text, library).unwrap();
+        let expected = scene! {
+            sphere(0, 0, -1; 0.5) lambertian(0.8, 0.1, 0.1)
+            sphere(0, 3, -1; 0.5) emissive(4, 4, 4)
+            sphere(0, 0, -3; 1) lambertian(0.8, 0.8, 0.8)
+        };
+        assert_eq!(scene.objects.len(), 3);
+        for (a, b) in scene.objects.iter().zip(&expected.objects) {
+            assert_eq!(a.material, b.material);
+        }
+        assert_eq!(
+            Scene::parse("sphere(0, 0, -1; 0.5) blue")
+                .err()
+                .unwrap()
+                .to_string(),
+            "line 1: unknown material `blue`"
+        );
+        assert!(MaterialLibrary::parse_mtl("Kd 1 1 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_scene_file() {
+        let text = "# A test scene\n\
+                    sphere(0, 0, -1; 0.5) lambertian(0.1, 0.2, 0.5)\n\
+                    \n\
+                    cuboid(-1, -2, -3; 1, -1.5, 0) mirror(1, 1, 1) # floor\n";
+        let parsed = Scene::parse(text).unwrap();
+        let expected = scene! {
+            sphere(0, 0, -1; 0.5) lambertian(0.1, 0.2, 0.5)
+            cuboid(-1, -2, -3; 1, -1.5, 0) mirror(1, 1, 1)
+        };
+        assert_eq!(parsed.objects.len(), 2);
+        for (a, b) in parsed.objects.iter().zip(&expected.objects) {
+            assert_eq!(a.surface, b.surface);
+            assert_eq!(a.material, b.material);
+        }
+        let error =
+            Scene::parse("sphere(0, 0, -1; 0.5) lambertian(1, 1, 1)\nsphere(0, 0) mirror(1, 1, 1)");
+        assert_eq!(
+            error.err().unwrap().to_string(),
+            "line 2: cannot parse `sphere(0, 0) mirror(1, 1, 1)`"
+        );
+    }
+
+    #[test]
+    fn test_parse_obj_and_stats() {
+        // A unit cube of quads, the top one with texture coordinates.
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                   v 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n\
+                   vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+                   f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\n\
+                   usemtl red\n\
+                   f 4/1 8/2 7/3 3/4\nf 1//1 5//1 8//1 4//1\nf -7 -6 -2 -3 # relative\n";
+        let library = MaterialLibrary::parse_mtl("newmtl red\nKd 1 0 0").unwrap();
+        let scene = Scene::parse_obj(obj, &library).unwrap();
+        let stats = scene.stats();
+        assert_eq!(stats.objects, 12);
+        assert_eq!(stats.triangles, 12);
+        assert_eq!(stats.bounds_size, Vector3f::xyz(1.0, 1.0, 1.0));
+        assert!(stats.memory_bytes >= 12 * mem::size_of::<Object>());
+        assert!(Arc::ptr_eq(
+            &scene.objects[0].material,
+            &scene.objects[5].material
+        ));
+        assert!(Arc::ptr_eq(
+            &scene.objects[6].material,
+            &library.materials["red"]
+        ));
+        match &scene.objects[6].surface {
+            Surface::Triangle { vertices, uvs } => {
+                assert_eq!(vertices[1], Vector3f::xyz(0.0, 1.0, 1.0));
+                assert_eq!(uvs[2], Vector2f::xy(1.0, 1.0));
+            }
+            _ => panic!(),
+        }
+
+        // Compounds and instances count their triangles.
+        let mut nested = Scene { objects: vec![] };
+        nested.extend(
+            scene,
+            Some(&Transform::translation(&Vector3f::xyz(1.0, 0.0, 0.0))),
+        );
+        assert_eq!(nested.stats().triangles, 12);
+
+        let error = Scene::parse_obj("v 0 0 0\nf 1 2 3", &library)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "line 2: cannot parse `f 1 2 3`");
+        assert!(Scene::parse_obj("usemtl blue", &library).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use background::Background;
+use geometry::Ray;
+use image::Color;
+use matrix::Vector3f;
+use std::f32::consts::PI;
+
+/// Grid of directions used to project the background, in polar bins of
+/// equal area times azimuthal bins.
+const PROJECTION_BINS: (usize, usize) = (32, 64);
+
+/// Distant lighting projected onto the real spherical harmonics up to
+/// order 2, from which the irradiance on a surface of any orientation is
+/// reconstructed (Ramamoorthi and Hanrahan, "An Efficient Representation for
+/// Irradiance Environment Maps", 2001). Ignores occlusion and is exact only
+/// for lighting without higher frequencies.
+pub struct ShIrradiance {
+    coefficients: [Color; 9],
+}
+
+impl ShIrradiance {
+    /// Projects the radiance that indirect rays see in `background`.
+    pub fn from_background(background: &Background) -> Self {
+        let (polar_bins, azimuth_bins) = PROJECTION_BINS;
+        let mut coefficients = [Color::rgb(0.0, 0.0, 0.0); 9];
+        let solid_angle = 4.0 * PI / (polar_bins * azimuth_bins) as f32;
+        for i in 0..polar_bins {
+            // Uniform in z, so all bins have the same solid angle.
+            let z = 1.0 - 2.0 * (i as f32 + 0.5) / polar_bins as f32;
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            for j in 0..azimuth_bins {
+                let phi = 2.0 * PI * (j as f32 + 0.5) / azimuth_bins as f32;
+                let direction = Vector3f::xyz(r * phi.cos(), r * phi.sin(), z);
+                let ray = Ray {
+                    origin: Vector3f::zeros(),
+                    direction,
+                };
+                let radiance = background.indirect_color(&ray) * solid_angle;
+                for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(&direction)) {
+                    *coefficient += radiance * basis;
+                }
+            }
+        }
+        ShIrradiance { coefficients }
+    }
+
+    /// Irradiance on a surface facing `normal`.
+    pub fn irradiance(&self, normal: &Vector3f) -> Color {
+        // Convolution with the clamped cosine scales each band.
+        const BAND_SCALE: [f32; 9] = [
+            PI,
+            2.0 * PI / 3.0,
+            2.0 * PI / 3.0,
+            2.0 * PI / 3.0,
+            PI / 4.0,
+            PI / 4.0,
+            PI / 4.0,
+            PI / 4.0,
+            PI / 4.0,
+        ];
+        let mut irradiance = Color::rgb(0.0, 0.0, 0.0);
+        for ((coefficient, basis), scale) in self
+            .coefficients
+            .iter()
+            .zip(sh_basis(normal))
+            .zip(BAND_SCALE)
+        {
+            irradiance += *coefficient * (basis * scale);
+        }
+        irradiance.clamp(0.0, f32::INFINITY)
+    }
+}
+
+/// Real spherical harmonics of bands 0 to 2 at a unit direction.
+fn sh_basis(d: &Vector3f) -> [f32; 9] {
+    let (x, y, z) = (d.x(), d.y(), d.z());
+    [
+        0.282095,
+        0.488603 * y,
+        0.488603 * z,
+        0.488603 * x,
+        1.092548 * x * y,
+        1.092548 * y * z,
+        0.315392 * (3.0 * z * z - 1.0),
+        1.092548 * x * z,
+        0.546274 * (x * x - y * y),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_environment_gives_constant_irradiance() {
+        let sh = ShIrradiance::from_background(&Background::Uniform(Color::rgb(0.5, 1.0, 2.0)));
+        let normals = [
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(1.0, -1.0, 1.0).normalized(),
+        ];
+        for normal in &normals {
+            // A hemisphere of constant radiance L gives irradiance pi L.
+            let irradiance = sh.irradiance(normal);
+            assert_approx!(irradiance.r(), 0.5 * PI, 1e-2);
+            assert_approx!(irradiance.g(), PI, 1e-2);
+            assert_approx!(irradiance.b(), 2.0 * PI, 2e-2);
+        }
+    }
+
+    #[test]
+    fn test_sky_lights_upward_surfaces_more() {
+        let sky = Background::Procedural(Box::new(|ray: &Ray| {
+            let brightness = ray.direction.y().max(0.0);
+            Color::rgb(brightness, brightness, brightness)
+        }));
+        let sh = ShIrradiance::from_background(&sky);
+        let up = sh.irradiance(&Vector3f::xyz(0.0, 1.0, 0.0)).r();
+        let side = sh.irradiance(&Vector3f::xyz(1.0, 0.0, 0.0)).r();
+        let down = sh.irradiance(&Vector3f::xyz(0.0, -1.0, 0.0)).r();
+        // The exact irradiance facing up is 2 pi / 3.
+        assert_approx!(up, 2.0 * PI / 3.0, 0.1);
+        assert!(up > side && side > down);
+    }
+}
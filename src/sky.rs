@@ -0,0 +1,148 @@
+use image::Color;
+use matrix::Vector3f;
+use spectrum::xyz_to_rgb;
+use std::f32::consts::PI;
+
+/// Converts the luminance of the model, in kcd/m^2, to scene radiance. Puts
+/// a clear sky around 0.1 to 1.
+const LUMINANCE_SCALE: f32 = 0.05;
+/// Angular radius of the sun disk in radians.
+const SUN_ANGULAR_RADIUS: f32 = 0.00465;
+/// Luminance of the sun disk relative to the sky right next to it. The real
+/// ratio is closer to 10^5, which would only produce fireflies as long as the
+/// renderer does not sample the sun explicitly.
+const SUN_RELATIVE_LUMINANCE: f32 = 2000.0;
+
+/// Perez distribution coefficients A to E as linear functions of turbidity,
+/// for the luminance Y and the chromaticities x and y.
+const PEREZ_Y: [(f32, f32); 5] = [
+    (0.1787, -1.4630),
+    (-0.3554, 0.4275),
+    (-0.0227, 5.3251),
+    (0.1206, -2.5771),
+    (-0.0670, 0.3703),
+];
+const PEREZ_X: [(f32, f32); 5] = [
+    (-0.0193, -0.2592),
+    (-0.0665, 0.0008),
+    (-0.0004, 0.2125),
+    (-0.0641, -0.8989),
+    (-0.0033, 0.0452),
+];
+const PEREZ_CHROMA_Y: [(f32, f32); 5] = [
+    (-0.0167, -0.2608),
+    (-0.0950, 0.0092),
+    (-0.0079, 0.2102),
+    (-0.0441, -1.6537),
+    (-0.0109, 0.0529),
+];
+
+/// Radiance of a clear sky toward `direction` for the sun toward
+/// `sun_direction`, with the y axis pointing up (Preetham, Shirley and
+/// Smits, "A Practical Analytic Model for Daylight", 1999). `turbidity`
+/// ranges from about 2 for a very clear to 10 for a hazy sky. Directions
+/// below the horizon see the sky at the horizon.
+pub fn radiance(sun_direction: &Vector3f, turbidity: f32, direction: &Vector3f) -> Color {
+    let sun_direction = sun_direction.normalized();
+    // The model is not defined below the horizon.
+    let horizontal = Vector3f::xyz(direction.x(), 0.0, direction.z());
+    let direction = if direction.y() >= 0.0 {
+        direction.normalized()
+    } else if horizontal.squared_length() > 0.0 {
+        horizontal.normalized()
+    } else {
+        Vector3f::xyz(1.0, 0.0, 0.0)
+    };
+    // Both angles from the zenith.
+    let cos_theta = direction.y().max(1e-3);
+    let theta_sun = sun_direction.y().clamp(1e-3, 1.0).acos();
+    let cos_gamma = direction.dot(&sun_direction).clamp(-1.0, 1.0);
+    let gamma = cos_gamma.acos();
+
+    let luminance_zenith = zenith_luminance(turbidity, theta_sun);
+    let (x_zenith, y_zenith) = zenith_chromaticity(turbidity, theta_sun);
+    let relative = |coefficients: &[(f32, f32); 5]| {
+        let perez = perez_coefficients(coefficients, turbidity);
+        perez_distribution(&perez, cos_theta, gamma, cos_gamma)
+            / perez_distribution(&perez, 1.0, theta_sun, theta_sun.cos())
+    };
+    let mut luminance = luminance_zenith * relative(&PEREZ_Y) * LUMINANCE_SCALE;
+    if gamma < SUN_ANGULAR_RADIUS && sun_direction.y() > 0.0 {
+        luminance *= SUN_RELATIVE_LUMINANCE;
+    }
+    xyy_to_rgb(
+        x_zenith * relative(&PEREZ_X),
+        y_zenith * relative(&PEREZ_CHROMA_Y),
+        luminance,
+    )
+}
+
+fn perez_coefficients(coefficients: &[(f32, f32); 5], turbidity: f32) -> [f32; 5] {
+    coefficients.map(|(slope, offset)| slope * turbidity + offset)
+}
+
+fn perez_distribution(perez: &[f32; 5], cos_theta: f32, gamma: f32, cos_gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *perez;
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+/// Luminance at the zenith in kcd/m^2.
+fn zenith_luminance(turbidity: f32, theta_sun: f32) -> f32 {
+    let chi = (4.0 / 9.0 - turbidity / 120.0) * (PI - 2.0 * theta_sun);
+    (4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192
+}
+
+fn zenith_chromaticity(turbidity: f32, theta_sun: f32) -> (f32, f32) {
+    let t = [turbidity * turbidity, turbidity, 1.0];
+    let theta = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+    let evaluate = |matrix: [[f32; 4]; 3]| -> f32 {
+        (0..3)
+            .map(|i| t[i] * (0..4).map(|j| matrix[i][j] * theta[j]).sum::<f32>())
+            .sum()
+    };
+    let x = evaluate([
+        [0.00166, -0.00375, 0.00209, 0.0],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+    ]);
+    let y = evaluate([
+        [0.00275, -0.00610, 0.00317, 0.0],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+    ]);
+    (x, y)
+}
+
+/// CIE xyY to linear sRGB.
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Color {
+    let xyz = Vector3f::xyz(x * luminance / y, luminance, (1.0 - x - y) * luminance / y);
+    xyz_to_rgb(&xyz).clamp(0.0, f32::INFINITY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zenith_brighter_than_horizon() {
+        // Only for a high sun, a low one brightens the horizon around it
+        // beyond the zenith.
+        let sun = Vector3f::xyz(0.0, 70_f32.to_radians().sin(), 70_f32.to_radians().cos());
+        let zenith = radiance(&sun, 2.5, &Vector3f::xyz(0.0, 1.0, 0.0));
+        let horizon = radiance(&sun, 2.5, &Vector3f::xyz(0.0, 0.0, -1.0));
+        assert!(zenith.luminance() > horizon.luminance());
+        // Clear skies are blue, and most saturated at the zenith.
+        assert!(zenith.b() > zenith.r());
+        assert!(zenith.b() / zenith.r() > horizon.b() / horizon.r());
+    }
+
+    #[test]
+    fn test_sun_disk() {
+        let sun = Vector3f::xyz(1.0, 1.0, 0.0);
+        let at_sun = radiance(&sun, 3.0, &sun).luminance();
+        let next_to_sun = radiance(&sun, 3.0, &Vector3f::xyz(1.0, 1.02, 0.0)).luminance();
+        assert!(at_sun > 100.0 * next_to_sun);
+        let below = radiance(&sun, 3.0, &Vector3f::xyz(0.0, -1.0, 1.0));
+        assert_eq!(below, radiance(&sun, 3.0, &Vector3f::xyz(0.0, 0.0, 1.0)));
+    }
+}
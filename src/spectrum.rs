@@ -0,0 +1,86 @@
+use image::Color;
+use matrix::Vector3f;
+use std::sync::OnceLock;
+
+/// Range of visible wavelengths in nm sampled by spectral rendering.
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 780.0;
+
+/// Maps a uniform `u` in [0, 1] to a wavelength in nm, uniformly over the
+/// visible range.
+pub fn sample_wavelength(u: f32) -> f32 {
+    MIN_WAVELENGTH + u * (MAX_WAVELENGTH - MIN_WAVELENGTH)
+}
+
+/// CIE 1931 color matching functions at `wavelength` in nm, using the
+/// multi-lobe fit of Wyman, Sloan and Shirley, "Simple Analytic
+/// Approximations to the CIE XYZ Color Matching Functions", 2013.
+pub fn color_matching(wavelength: f32) -> Vector3f {
+    let lobe = |mean: f32, sigma_below: f32, sigma_above: f32| {
+        let sigma = if wavelength < mean {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (wavelength - mean) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vector3f::xyz(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// CIE XYZ to linear sRGB.
+pub fn xyz_to_rgb(xyz: &Vector3f) -> Color {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    Color::rgb(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+/// Factor converting RGB radiance carried at a single, uniformly sampled
+/// `wavelength` to its contribution to the pixel. Averages to white over
+/// the visible range, so paths that do not depend on the wavelength keep
+/// their color in expectation. Components may be negative outside the sRGB
+/// gamut.
+pub fn rgb_weight(wavelength: f32) -> Color {
+    static MEAN: OnceLock<Color> = OnceLock::new();
+    let mean = MEAN.get_or_init(|| {
+        const STEPS: usize = 1000;
+        let sum = (0..STEPS)
+            .map(|i| {
+                xyz_to_rgb(&color_matching(sample_wavelength(
+                    (i as f32 + 0.5) / STEPS as f32,
+                )))
+            })
+            .fold(Color::rgb(0.0, 0.0, 0.0), |sum, rgb| sum + rgb);
+        sum / STEPS as f32
+    });
+    let rgb = xyz_to_rgb(&color_matching(wavelength));
+    Color::rgb(rgb.r() / mean.r(), rgb.g() / mean.g(), rgb.b() / mean.b())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgb_weight_averages_to_white() {
+        const STEPS: usize = 4000;
+        let mut sum = Color::rgb(0.0, 0.0, 0.0);
+        for i in 0..STEPS {
+            sum += rgb_weight(sample_wavelength((i as f32 + 0.5) / STEPS as f32));
+        }
+        assert_matrix_approx!(sum / STEPS as f32, Color::rgb(1.0, 1.0, 1.0), 1e-3);
+        // Short wavelengths are blue, long ones red.
+        let blue = rgb_weight(450.0);
+        assert!(blue.b() > blue.r() && blue.b() > blue.g());
+        let red = rgb_weight(650.0);
+        assert!(red.r() > red.g() && red.r() > red.b());
+    }
+}
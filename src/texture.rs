@@ -0,0 +1,38 @@
+use image::{Color, Image};
+use matrix::Vector2f;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Texture {
+    Solid(Color),
+    /// Sampled bilinearly, with v = 0 at the top row.
+    Image(Image),
+}
+
+impl Texture {
+    pub fn sample(&self, uv: &Vector2f) -> Color {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Image(image) => image.sample_bilinear(uv.x(), uv.y()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_texture_lookup() {
+        let mut image = Image::new(2, 2);
+        image.data[1] = Color::rgb(1.0, 0.0, 0.0);
+        let texture = Texture::Image(image);
+        assert_eq!(
+            texture.sample(&Vector2f::xy(0.9, 0.1)),
+            Color::rgb(1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            texture.sample(&Vector2f::xy(0.1, 0.9)),
+            Color::rgb(0.0, 0.0, 0.0)
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! White furnace tests: a lossless sphere under a uniform white
+//! environment must render as white everywhere if the light transport
+//! neither loses nor gains energy. Every material without absorption is
+//! covered, so a new material should get a case here too.
+
+extern crate cpu_path_tracer;
+
+use cpu_path_tracer::background::{Background, Environment};
+use cpu_path_tracer::bvh::Accel;
+use cpu_path_tracer::geometry::{Camera, FovAxis, Material, Object, Surface};
+use cpu_path_tracer::image::{Color, Image, ToneMap};
+use cpu_path_tracer::light::LightList;
+use cpu_path_tracer::matrix::{Vector2i, Vector3f};
+use cpu_path_tracer::renderer::{RenderMode, Renderer, TerminationColor, TileOrder};
+use cpu_path_tracer::sampling::{PixelFilter, SamplePattern};
+use cpu_path_tracer::scene::Scene;
+use cpu_path_tracer::texture::Texture;
+use std::sync::atomic::AtomicU64;
+
+fn white_lambertian() -> Material {
+    Material::Lambertian {
+        albedo: Texture::Solid(Color::rgb(1.0, 1.0, 1.0)),
+    }
+}
+
+fn furnace_renderer(background: Background) -> Renderer {
+    material_furnace_renderer(background, white_lambertian())
+}
+
+fn material_furnace_renderer(background: Background, material: Material) -> Renderer {
+    Renderer {
+        camera: Camera::new(
+            Vector3f::xyz(0.0, 0.0, -1.0),
+            Vector3f::xyz(0.0, 1.0, 0.0),
+            0.0,
+            60_f32.to_radians(),
+            FovAxis::Horizontal,
+            Vector2i::xy(16, 16),
+        ),
+        scene: Scene {
+            objects: vec![Object::new(
+                Surface::Sphere {
+                    center: Vector3f::xyz(0.0, 0.0, -2.0),
+                    radius: 1.0,
+                },
+                material,
+            )],
+        },
+        accel: Accel::Linear,
+        lights: LightList::new(&[]),
+        point_lights: vec![],
+        shadow_samples: 1,
+        background,
+        ambient_intensity: 1.0,
+        tone_map: ToneMap::Linear,
+        max_depth: 64,
+        max_diffuse_bounces: 64,
+        max_specular_bounces: 64,
+        rr_min_bounces: 3,
+        termination_color: TerminationColor::Black,
+        samples_per_pixel: 16,
+        sample_pattern: SamplePattern::Stratified,
+        pixel_filter: PixelFilter::Tent,
+        render_mode: RenderMode::Uniform,
+        tile_order: TileOrder::Scanline,
+        spectral: false,
+        photon_pass: 0,
+        photon_radius: 0.1,
+        reflection_probe: None,
+        max_rays: None,
+        rays_traced: AtomicU64::new(0),
+    }
+}
+
+fn mean_pixel_value(image: &Image) -> f32 {
+    let sum: f32 = image
+        .data
+        .iter()
+        .map(|c| (c.r() + c.g() + c.b()) / 3.0)
+        .sum();
+    sum / image.data.len() as f32
+}
+
+#[test]
+fn test_furnace_uniform_background() {
+    let renderer = furnace_renderer(Background::Uniform(Color::rgb(1.0, 1.0, 1.0)));
+    let image = renderer.render_output(0).image;
+    assert!((mean_pixel_value(&image) - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_furnace_environment_background() {
+    let mut environment = Image::new(32, 16);
+    environment
+        .data
+        .iter_mut()
+        .for_each(|c| *c = Color::rgb(1.0, 1.0, 1.0));
+    let renderer = furnace_renderer(Background::Environment(
+        Environment::new(environment).unwrap(),
+    ));
+    let image = renderer.render_output(0).image;
+    assert!((mean_pixel_value(&image) - 1.0).abs() < 0.01);
+}
+
+fn assert_material_furnace(material: Material, tolerance: f32) {
+    let renderer =
+        material_furnace_renderer(Background::Uniform(Color::rgb(1.0, 1.0, 1.0)), material);
+    let mean = mean_pixel_value(&renderer.render_output(0).image);
+    assert!((mean - 1.0).abs() < tolerance, "mean {}", mean);
+}
+
+#[test]
+fn test_furnace_mirror() {
+    assert_material_furnace(
+        Material::Mirror {
+            tint: Color::rgb(1.0, 1.0, 1.0),
+        },
+        1e-4,
+    );
+}
+
+#[test]
+fn test_furnace_dielectric() {
+    for &ior in &[1.0, 1.5, 2.4] {
+        assert_material_furnace(
+            Material::Dielectric {
+                ior,
+                absorption: Color::rgb(0.0, 0.0, 0.0),
+                dispersion: 0.0,
+            },
+            1e-4,
+        );
+    }
+}
+
+#[test]
+fn test_furnace_blend() {
+    assert_material_furnace(
+        Material::Blend {
+            base: Box::new(white_lambertian()),
+            layer: Box::new(Material::Dielectric {
+                ior: 1.5,
+                absorption: Color::rgb(0.0, 0.0, 0.0),
+                dispersion: 0.0,
+            }),
+            weight: 0.5,
+        },
+        1e-4,
+    );
+}
+
+#[test]
+fn test_furnace_coated() {
+    assert_material_furnace(
+        Material::Coated {
+            base: Box::new(white_lambertian()),
+            coat_ior: 1.5,
+            coat_roughness: 0.0,
+        },
+        1e-4,
+    );
+}